@@ -15,6 +15,7 @@ impl CPU {
             mode: CPUMode::Normal,
             t_cycles: 0,
             ime: false,
+            fault: None,
         }
     }
 
@@ -128,75 +129,125 @@ impl CPU {
 
         self.mmu.borrow_mut().tick();
 
-        for _ in 0..4 {
+        // In double speed mode the PPU runs at half the CPU clock
+        let dots = if self.mmu.borrow().is_double_speed() {
+            2
+        } else {
+            4
+        };
+        for _ in 0..dots {
             self.tick();
         }
     }
 
-    /// returns the new flag value that should be set
+    /// Interrupts both requested and enabled, one bit per source.
     #[inline(always)]
-    pub fn handle_interupt(&mut self, addr: u8) -> bool {
+    pub fn pending_interrupts(&self) -> u8 {
+        let mmu = self.mmu.borrow();
+        let ic = mmu.ic.borrow();
+
+        ic.interrupt_enable.0 & ic.interrupt_flag.0 & 0x1F
+    }
+
+    /// Jumps to the highest priority interrupt vector, takes 5 M-cycles: two wait
+    /// states, the two pushes of PC and the jump.
+    ///
+    /// The vector is only picked after the high byte of PC is pushed. If that push
+    /// overwrote IE and disabled every pending interrupt, the dispatch is cancelled
+    /// and execution continues at 0x0000.
+    pub fn dispatch_interrupt(&mut self) {
+        self.ime = false;
         self.mode = CPUMode::Normal;
 
-        if self.ime == false {
-            self.mode = CPUMode::Normal;
-            return true;
+        self.tick4();
+        self.tick4();
+
+        self.sp = self.sp.wrapping_sub(1);
+        self.write_byte(self.sp, (self.pc >> 8) as u8);
+
+        let pending = self.pending_interrupts();
+
+        self.sp = self.sp.wrapping_sub(1);
+        self.write_byte(self.sp, self.pc as u8);
+
+        self.pc = if pending == 0 {
+            debug!("Interrupt dispatch cancelled");
+            0x0000
         } else {
-            self.ime = false;
-            self.tick4();
-            self.tick4();
-            // Push current PC onto the stack
-            self.sp = self.sp.wrapping_sub(1);
-            self.write_byte(self.sp, (self.pc >> 8) as u8);
-            self.sp = self.sp.wrapping_sub(1);
-            self.write_byte(self.sp, self.pc as u8);
-
-            // Set PC to the new address
-            self.pc = addr as u16;
-            self.tick4();
-
-            return false;
-        }
+            // Lowest bit has the highest priority: VBlank, LCD, Timer, Serial, Joypad
+            let source = pending.trailing_zeros() as u16;
+            let ic = self.mmu.borrow().ic.clone();
+            ic.borrow_mut().interrupt_flag.0 &= !(1 << source);
+
+            debug!("Interrupt {:#04X}", 0x40 + source * 8);
+            0x40 + source * 8
+        };
+
+        self.tick4();
+    }
+
+    /// Locks up the CPU after fetching an illegal opcode at `pc`.
+    pub(crate) fn lock_up(&mut self, pc: u16, opcode: u8) {
+        let fault = CpuFault { pc, opcode };
+        error!("{}", fault);
+
+        self.mode = CPUMode::Locked;
+        self.fault = Some(fault);
     }
 
     pub fn do_step(&mut self) {
-        if self.mode == CPUMode::Normal {
-            self.run_instr();
-        } else {
-            self.tick4();
+        match self.mode {
+            CPUMode::Normal => self.run_instr(),
+            CPUMode::EnableIME => {
+                // EI takes effect once the instruction following it is done
+                self.ime = true;
+                self.mode = CPUMode::Normal;
+                self.run_instr();
+            }
+            CPUMode::HaltBug => self.run_instr(),
+            CPUMode::Locked => {
+                // The rest of the system keeps running
+                self.tick4();
+                return;
+            }
+            CPUMode::Stop => {
+                let mut mmu = self.mmu.borrow_mut();
+
+                if !mmu.joypad.is_input_low() {
+                    // The timer is stopped as well, only the blanked LCD and the
+                    // cartridge keep running
+                    mmu.cartridge.tick(4);
+                    mmu.serial.tick_stopped();
+                    drop(mmu);
+                    self.t_cycles += 4;
+                    for _ in 0..4 {
+                        self.tick();
+                    }
+                    return;
+                }
+
+                mmu.ppu.set_stopped(false);
+                self.mode = CPUMode::Normal;
+            }
+            _ => {
+                if self.pending_interrupts() == 0 {
+                    self.tick4();
+                    return;
+                }
+
+                self.mode = CPUMode::Normal;
+
+                if self.ime {
+                    // Waking up takes one more M-cycle before the dispatch
+                    self.tick4();
+                } else {
+                    self.run_instr();
+                }
+            }
         }
 
-        if self.ime || (self.mode != CPUMode::Normal) {
-            let mmu = self.mmu.borrow();
-            let ic_ref = mmu.ic.clone();
-            let ic = ic_ref.borrow();
-
-            let flags = ic.interrupt_enable & ic.interrupt_flag;
-            drop(ic);
-            drop(mmu);
-
-            if flags.vblank() {
-                debug!("VBlank interrupt");
-                debug!("PPU Tick: {}", self.mmu.borrow().ppu.t_cycles);
-                let f = self.handle_interupt(0x40);
-                ic_ref.borrow_mut().interrupt_flag.set_vblank(f);
-            } else if flags.lcd() {
-                debug!("LCD interrupt");
-                let f = self.handle_interupt(0x48);
-                ic_ref.borrow_mut().interrupt_flag.set_lcd(f);
-            } else if flags.timer() {
-                debug!("Timer interrupt");
-                let f = self.handle_interupt(0x50);
-                ic_ref.borrow_mut().interrupt_flag.set_timer(f);
-            } else if flags.serial() {
-                debug!("Serial interrupt");
-                let f = self.handle_interupt(0x58);
-                ic_ref.borrow_mut().interrupt_flag.set_serial(f);
-            } else if flags.joypad() {
-                debug!("Joypad interrupt");
-                let f = self.handle_interupt(0x60);
-                ic_ref.borrow_mut().interrupt_flag.set_joypad(f);
-            }
+        if self.ime && self.mode != CPUMode::Locked && self.pending_interrupts() != 0 {
+            self.dispatch_interrupt();
         }
     }
 
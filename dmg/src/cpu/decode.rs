@@ -1,5 +1,3 @@
-use std::panic;
-
 use super::*;
 
 use Register8;
@@ -301,7 +299,7 @@ impl CPU {
             0xFF => self.rst(0x38),
 
             0xD3 | 0xDB | 0xDD | 0xE3 | 0xE4 | 0xEB | 0xED | 0xF4 | 0xFC | 0xFD => {
-                panic!("Invalid instruction: 0x{:02X}", byte);
+                self.lock_up(pc, byte)
             }
         }
     }
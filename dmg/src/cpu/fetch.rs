@@ -17,7 +17,14 @@ impl CPU {
     #[inline(always)]
     pub(super) fn read_instruction(&mut self) -> u8 {
         let byte = self.read_byte(self.pc);
-        self.pc = self.pc.wrapping_add(1);
+
+        // HALT bug: PC fails to increment, the byte is read again as an operand
+        // or as the next opcode
+        if self.mode == CPUMode::HaltBug {
+            self.mode = CPUMode::Normal;
+        } else {
+            self.pc = self.pc.wrapping_add(1);
+        }
         byte
     }
 
@@ -1,5 +1,7 @@
 use super::*;
 
+use crate::memory::io_registers::DIV;
+use crate::memory::RegisterTrait;
 use crate::read_register;
 use crate::read_register16;
 use crate::write_register;
@@ -17,33 +19,64 @@ impl CPU {
     }
 
     pub(crate) fn stop(&mut self) {
+        let held = {
+            let mmu = self.mmu.borrow();
+            mmu.joypad.is_input_low() && !mmu.speed_switch_armed()
+        };
+
+        // With a button already held the CPU doesn't stop. STOP is a 1-byte
+        // opcode if an interrupt is pending, otherwise it skips the padding byte
+        // and halts.
+        if held {
+            if self.pending_interrupts() == 0 {
+                self.pc = self.pc.wrapping_add(1);
+                self.mode = CPUMode::Halt;
+                info!("CPU halted (STOP with a button held)");
+            }
+            return;
+        }
+
+        // STOP is followed by a padding byte, skipped without being read
+        self.pc = self.pc.wrapping_add(1);
+
+        let mut mmu = self.mmu.borrow_mut();
+        mmu.timer.write(DIV, 0);
+
+        if mmu.switch_speed() {
+            return;
+        }
+
+        mmu.ppu.set_stopped(true);
         self.mode = CPUMode::Stop;
+        info!("CPU stopped");
     }
 
     pub(crate) fn halt(&mut self) {
-        if self.ime {
-            self.mode = CPUMode::Halt;
+        if !self.ime && self.pending_interrupts() != 0 {
+            // With an interrupt already pending, HALT exits at once and the
+            // next opcode fetch doesn't increment PC
+            self.mode = CPUMode::HaltBug;
+            info!("CPU halted (bug)");
         } else {
-            let mmu = self.mmu.borrow();
-
-            if mmu.ic.borrow().interrupt_enable.0 != 0
-                && (mmu.ic.borrow().interrupt_flag.0 & mmu.ic.borrow().interrupt_enable.0 & 0x1F)
-                    != 0
-            {
-                self.mode = CPUMode::HaltBug;
-                info!("CPU halted (bug)");
-            } else {
-                self.mode = CPUMode::Halt;
-            }
+            // With IME set and an interrupt pending, `do_step` dispatches right away
+            self.mode = CPUMode::Halt;
         }
     }
 
     pub(crate) fn ei(&mut self) {
-        self.ime = true;
+        // IME is set after the next instruction, see `do_step`
+        if !self.ime {
+            self.mode = CPUMode::EnableIME;
+        }
     }
 
     pub(crate) fn di(&mut self) {
         self.ime = false;
+
+        // Cancels a pending EI
+        if self.mode == CPUMode::EnableIME {
+            self.mode = CPUMode::Normal;
+        }
     }
 
     /*******************************************/
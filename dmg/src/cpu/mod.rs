@@ -24,6 +24,25 @@ pub enum CPUMode {
     HaltDI,
     Stop,
     EnableIME,
+    /// Hit an illegal opcode, nothing but a reset gets the CPU out of it
+    Locked,
+}
+
+/// Illegal opcode that locked up the CPU.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CpuFault {
+    pub pc: u16,
+    pub opcode: u8,
+}
+
+impl std::fmt::Display for CpuFault {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(
+            f,
+            "CPU locked up on illegal opcode {:#04X} at {:#06X}",
+            self.opcode, self.pc
+        )
+    }
 }
 
 #[derive(Debug, PartialEq, Eq)]
@@ -76,6 +95,9 @@ pub struct CPU {
     pub t_cycles: usize,
 
     pub ime: bool,
+
+    /// Set when the CPU locks up, the host takes it to report the error
+    pub fault: Option<CpuFault>,
 }
 
 #[cfg(test)]
@@ -19,6 +19,9 @@ mod test_bx;
 
 mod test_cb;
 
+mod test_illegal;
+mod test_interrupts;
+
 struct TestHardware {
     pub cpu: CPU,
     pub instruction_counter: usize,
@@ -38,6 +41,23 @@ struct TestHardware {
 // }
 
 fn run_test<I: Fn(&mut TestHardware) -> ()>(instructions: &[u8], init: I) -> TestHardware {
+    let mut test_hardware = setup(instructions);
+
+    init(&mut test_hardware);
+
+    while test_hardware.cpu.peek_opcode() != 0xFD {
+        println!(
+            "Executing instruction: {:02X}",
+            test_hardware.cpu.peek_opcode()
+        );
+
+        test_hardware.cpu.run_instr();
+        test_hardware.instruction_counter += 1;
+    }
+    test_hardware
+}
+
+fn setup(instructions: &[u8]) -> TestHardware {
     let mut rom: [u8; 0x8000] = [0xFD; 0x8000];
 
     let mut i = 0;
@@ -53,21 +73,8 @@ fn run_test<I: Fn(&mut TestHardware) -> ()>(instructions: &[u8], init: I) -> Tes
     boot_rom.enabled = false;
 
     let mmu: Rc<RefCell<MMU>> = MMU::new(Some(mbc), boot_rom);
-    let mut test_hardware = TestHardware {
+    TestHardware {
         cpu: CPU::new(mmu),
         instruction_counter: 0,
-    };
-
-    init(&mut test_hardware);
-
-    while test_hardware.cpu.peek_opcode() != 0xFD {
-        println!(
-            "Executing instruction: {:02X}",
-            test_hardware.cpu.peek_opcode()
-        );
-
-        test_hardware.cpu.run_instr();
-        test_hardware.instruction_counter += 1;
     }
-    test_hardware
 }
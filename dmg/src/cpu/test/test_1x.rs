@@ -1,16 +1,89 @@
 #[cfg(test)]
 mod test_1x {
-    use crate::cpu::test::run_test;
+    use crate::cpu::test::{run_test, setup};
     use crate::cpu::*;
+    use crate::memory::Model;
 
     #[test]
     fn test_10() {
-        let machine = run_test(&[0x10], |_| {});
-        assert_eq!(machine.cpu.pc, 0x0001);
+        let machine = run_test(&[0x10, 0x00], |_| {});
+        assert_eq!(machine.cpu.pc, 0x0002);
         assert_eq!(machine.cpu.t_cycles, 4);
         assert_eq!(machine.cpu.mode, CPUMode::Stop);
     }
 
+    #[test]
+    fn test_10_joypad_wakeup() {
+        // STOP; INC A
+        let mut machine = setup(&[0x10, 0x00, 0x3C]);
+        for _ in 0..64 {
+            machine.cpu.tick4();
+        }
+        // Select the buttons line
+        machine.cpu.mmu.borrow_mut().write(0xFF00, 0x10);
+
+        machine.cpu.do_step();
+        assert_eq!(machine.cpu.mode, CPUMode::Stop);
+        assert_eq!(machine.cpu.mmu.borrow().read(0xFF04), 0x00);
+
+        // DIV is frozen while stopped
+        for _ in 0..128 {
+            machine.cpu.do_step();
+        }
+        assert_eq!(machine.cpu.mode, CPUMode::Stop);
+        assert_eq!(machine.cpu.mmu.borrow().read(0xFF04), 0x00);
+
+        // Pressing a direction doesn't wake up, the d-pad line isn't selected
+        machine.cpu.mmu.borrow_mut().joypad.set_buttons(0x00, 0x01);
+        machine.cpu.do_step();
+        assert_eq!(machine.cpu.mode, CPUMode::Stop);
+
+        machine.cpu.mmu.borrow_mut().joypad.set_buttons(0x08, 0x00);
+        machine.cpu.do_step();
+        assert_eq!(machine.cpu.mode, CPUMode::Normal);
+        machine.cpu.do_step();
+        assert_eq!(machine.cpu.pc, 0x0003);
+        assert_eq!(machine.cpu.a(), 1);
+    }
+
+    #[test]
+    fn test_10_button_held() {
+        // STOP; INC A
+        let mut machine = setup(&[0x10, 0x00, 0x3C]);
+        machine.cpu.mmu.borrow_mut().write(0xFF00, 0x10);
+        machine.cpu.mmu.borrow_mut().joypad.set_buttons(0x01, 0x00);
+
+        // No interrupt pending: the padding byte is skipped and the CPU halts
+        machine.cpu.do_step();
+        assert_eq!(machine.cpu.mode, CPUMode::Halt);
+        assert_eq!(machine.cpu.pc, 0x0002);
+
+        // With an interrupt pending STOP is a 1-byte opcode and doesn't halt
+        let mut machine = setup(&[0x10, 0x00, 0x3C]);
+        machine.cpu.mmu.borrow_mut().write(0xFF00, 0x10);
+        machine.cpu.mmu.borrow_mut().joypad.set_buttons(0x01, 0x00);
+        machine.cpu.mmu.borrow_mut().write(0xFFFF, 0x10);
+        machine.cpu.mmu.borrow_mut().write(0xFF0F, 0x10);
+
+        machine.cpu.do_step();
+        assert_eq!(machine.cpu.mode, CPUMode::Normal);
+        assert_eq!(machine.cpu.pc, 0x0001);
+    }
+
+    #[test]
+    fn test_10_speed_switch() {
+        let mut machine = setup(&[0x10, 0x00]);
+        machine.cpu.mmu.borrow_mut().set_model(Model::CGB);
+        machine.cpu.mmu.borrow_mut().write(0xFF4D, 0x01);
+        assert_eq!(machine.cpu.mmu.borrow().read(0xFF4D), 0x7F);
+
+        machine.cpu.do_step();
+        assert_eq!(machine.cpu.mode, CPUMode::Normal);
+        assert_eq!(machine.cpu.pc, 0x0002);
+        assert_eq!(machine.cpu.mmu.borrow().read(0xFF4D), 0xFE);
+        assert!(machine.cpu.mmu.borrow().is_double_speed());
+    }
+
     #[test]
     fn test_01() {
         let machine = run_test(&[0x11, 0x34, 0x12], |_| {});
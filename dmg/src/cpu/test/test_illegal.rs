@@ -0,0 +1,34 @@
+#[cfg(test)]
+mod test_illegal {
+    use crate::cpu::test::setup;
+    use crate::cpu::*;
+
+    #[test]
+    fn test_illegal_opcodes() {
+        for opcode in [0xD3, 0xDB, 0xDD, 0xE3, 0xE4, 0xEB, 0xED, 0xF4, 0xFC, 0xFD] {
+            let mut machine = setup(&[0x00, opcode]);
+            machine.cpu.do_step();
+            machine.cpu.do_step();
+
+            assert_eq!(machine.cpu.mode, CPUMode::Locked);
+            assert_eq!(machine.cpu.fault, Some(CpuFault { pc: 0x0001, opcode }));
+        }
+    }
+
+    #[test]
+    fn test_locked() {
+        let mut machine = setup(&[0xD3]);
+        machine.cpu.ime = true;
+        machine.cpu.mmu.borrow_mut().write(0xFFFF, 0x04);
+        machine.cpu.mmu.borrow_mut().write(0xFF0F, 0x04);
+
+        for _ in 0..256 {
+            machine.cpu.do_step();
+        }
+
+        // Interrupts aren't serviced, but the timer keeps running
+        assert_eq!(machine.cpu.pc, 0x0001);
+        assert_eq!(machine.cpu.t_cycles, 256 * 4);
+        assert_eq!(machine.cpu.mmu.borrow().read(0xFF04), 0x04);
+    }
+}
@@ -0,0 +1,133 @@
+#[cfg(test)]
+mod test_interrupts {
+    use crate::cpu::test::setup;
+    use crate::cpu::*;
+
+    const IF: u16 = 0xFF0F;
+    const IE: u16 = 0xFFFF;
+
+    #[test]
+    fn test_ei_delay() {
+        // EI; NOP; NOP
+        let mut machine = setup(&[0xFB, 0x00, 0x00]);
+        machine.cpu.sp = 0xD000;
+        machine.cpu.mmu.borrow_mut().write(IE, 0x01);
+        machine.cpu.mmu.borrow_mut().write(IF, 0x01);
+
+        machine.cpu.do_step();
+        assert_eq!(machine.cpu.pc, 0x0001);
+        assert!(!machine.cpu.ime);
+
+        // The interrupt is taken after the instruction following EI
+        machine.cpu.do_step();
+        assert_eq!(machine.cpu.pc, 0x0040);
+        assert_eq!(machine.cpu.t_cycles, 4 + 4 + 20);
+        assert!(!machine.cpu.ime);
+        assert_eq!(machine.cpu.mmu.borrow().read(0xCFFE), 0x02);
+        assert_eq!(machine.cpu.mmu.borrow().read(IF) & 0x1F, 0x00);
+    }
+
+    #[test]
+    fn test_ei_di() {
+        // EI; DI; NOP
+        let mut machine = setup(&[0xFB, 0xF3, 0x00]);
+        machine.cpu.mmu.borrow_mut().write(IE, 0x01);
+        machine.cpu.mmu.borrow_mut().write(IF, 0x01);
+
+        for _ in 0..3 {
+            machine.cpu.do_step();
+        }
+        assert_eq!(machine.cpu.pc, 0x0003);
+        assert!(!machine.cpu.ime);
+    }
+
+    #[test]
+    fn test_priority() {
+        let mut machine = setup(&[0x00]);
+        machine.cpu.sp = 0xD000;
+        machine.cpu.ime = true;
+        machine.cpu.mmu.borrow_mut().write(IE, 0x1F);
+        machine.cpu.mmu.borrow_mut().write(IF, 0x14);
+
+        machine.cpu.do_step();
+        assert_eq!(machine.cpu.pc, 0x0050);
+        assert_eq!(machine.cpu.mmu.borrow().read(IF) & 0x1F, 0x10);
+    }
+
+    #[test]
+    fn test_ie_push() {
+        // The high byte of PC lands in IE and disables the pending interrupt
+        let mut machine = setup(&[0x00]);
+        machine.cpu.sp = 0x0000;
+        machine.cpu.ime = true;
+        machine.cpu.mmu.borrow_mut().write(IE, 0x04);
+        machine.cpu.mmu.borrow_mut().write(IF, 0x04);
+
+        machine.cpu.do_step();
+        assert_eq!(machine.cpu.pc, 0x0000);
+        assert_eq!(machine.cpu.sp, 0xFFFE);
+        assert_eq!(machine.cpu.mmu.borrow().read(IE) & 0x1F, 0x00);
+        // The request stays pending
+        assert_eq!(machine.cpu.mmu.borrow().read(IF) & 0x1F, 0x04);
+    }
+
+    #[test]
+    fn test_halt_bug() {
+        // HALT; INC A
+        let mut machine = setup(&[0x76, 0x3C]);
+        machine.cpu.mmu.borrow_mut().write(IE, 0x01);
+        machine.cpu.mmu.borrow_mut().write(IF, 0x01);
+
+        machine.cpu.do_step();
+        assert_eq!(machine.cpu.mode, CPUMode::HaltBug);
+
+        // INC A is read twice
+        machine.cpu.do_step();
+        assert_eq!(machine.cpu.pc, 0x0001);
+        machine.cpu.do_step();
+        assert_eq!(machine.cpu.pc, 0x0002);
+        assert_eq!(machine.cpu.a(), 2);
+    }
+
+    #[test]
+    fn test_halt_wakeup() {
+        // HALT; INC A
+        let mut machine = setup(&[0x76, 0x3C]);
+        machine.cpu.mmu.borrow_mut().write(IE, 0x04);
+
+        for _ in 0..4 {
+            machine.cpu.do_step();
+        }
+        assert_eq!(machine.cpu.mode, CPUMode::Halt);
+        assert_eq!(machine.cpu.pc, 0x0001);
+
+        // Without IME the next instruction runs straight away
+        let t_cycles = machine.cpu.t_cycles;
+        machine.cpu.mmu.borrow_mut().write(IF, 0x04);
+        machine.cpu.do_step();
+        assert_eq!(machine.cpu.mode, CPUMode::Normal);
+        assert_eq!(machine.cpu.a(), 1);
+        assert_eq!(machine.cpu.t_cycles, t_cycles + 4);
+    }
+
+    #[test]
+    fn test_halt_wakeup_ime() {
+        // HALT; INC A
+        let mut machine = setup(&[0x76, 0x3C]);
+        machine.cpu.sp = 0xD000;
+        machine.cpu.ime = true;
+        machine.cpu.mmu.borrow_mut().write(IE, 0x04);
+
+        machine.cpu.do_step();
+        machine.cpu.do_step();
+        assert_eq!(machine.cpu.mode, CPUMode::Halt);
+
+        // One extra M-cycle on top of the dispatch
+        let t_cycles = machine.cpu.t_cycles;
+        machine.cpu.mmu.borrow_mut().write(IF, 0x04);
+        machine.cpu.do_step();
+        assert_eq!(machine.cpu.pc, 0x0050);
+        assert_eq!(machine.cpu.t_cycles, t_cycles + 4 + 20);
+        assert_eq!(machine.cpu.mmu.borrow().read(0xCFFE), 0x01);
+    }
+}
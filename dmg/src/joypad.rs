@@ -43,11 +43,18 @@ bitfield! {
 
 }
 
+pub const MAX_PLAYERS: usize = 4;
+
 #[derive(Debug)]
 pub struct Joypad {
     buttons: Buttons,
     dpad: Dpad,
     select: Select,
+    // Extra controllers (players 2-4), only visible when the SGB enables multiplayer
+    extra_buttons: [u8; MAX_PLAYERS - 1],
+    extra_dpad: [u8; MAX_PLAYERS - 1],
+    player_count: u8,
+    current_player: u8,
     ic: Rc<RefCell<InterruptController>>,
 }
 
@@ -57,10 +64,33 @@ impl Joypad {
             buttons: Buttons(0),
             dpad: Dpad(0),
             select: Select(0b0011_0000),
+            extra_buttons: [0; MAX_PLAYERS - 1],
+            extra_dpad: [0; MAX_PLAYERS - 1],
+            player_count: 1,
+            current_player: 0,
             ic,
         }
     }
 
+    /// Sets the number of controllers polled through P1, as requested by SGB MLT_REQ.
+    /// Valid values are 1, 2 and 4.
+    pub fn set_player_count(&mut self, count: u8) {
+        self.player_count = match count {
+            0 | 1 => 1,
+            2 => 2,
+            _ => 4,
+        };
+        self.current_player = 0;
+    }
+
+    pub fn player_count(&self) -> u8 {
+        self.player_count
+    }
+
+    pub fn current_player(&self) -> u8 {
+        self.current_player
+    }
+
     pub fn set_button(&mut self, button: JoypadButton, pressed: bool) {
         match button {
             JoypadButton::A | JoypadButton::B | JoypadButton::Select | JoypadButton::Start => {
@@ -117,32 +147,85 @@ impl Joypad {
     }
 
     pub fn set_buttons(&mut self, buttons: u8, dpad: u8) {
-        self.buttons.0 = buttons & 0b0000_1111;
-        self.dpad.0 = dpad & 0b0000_1111;
+        self.set_player_buttons(0, buttons, dpad);
+    }
+
+    /// Sets the state of controller `player` (0 = player 1).
+    pub fn set_player_buttons(&mut self, player: usize, buttons: u8, dpad: u8) {
+        let buttons = buttons & 0b0000_1111;
+        let dpad = dpad & 0b0000_1111;
 
-        if !self.select.select_buttons() && self.buttons.0 != 0 {
+        match player {
+            0 => {
+                self.buttons.0 = buttons;
+                self.dpad.0 = dpad;
+            }
+            1..MAX_PLAYERS => {
+                self.extra_buttons[player - 1] = buttons;
+                self.extra_dpad[player - 1] = dpad;
+            }
+            _ => return,
+        }
+
+        if player != self.current_player as usize {
+            return;
+        }
+
+        if !self.select.select_buttons() && buttons != 0 {
             self.ic.borrow_mut().interrupt_flag.set_joypad(true);
         }
 
-        if !self.select.select_dpad() && self.dpad.0 != 0 {
+        if !self.select.select_dpad() && dpad != 0 {
             self.ic.borrow_mut().interrupt_flag.set_joypad(true);
         }
     }
 
+    fn player_state(&self, player: u8) -> (u8, u8) {
+        match player {
+            0 => (self.buttons.0, self.dpad.0),
+            p => (
+                self.extra_buttons[p as usize - 1],
+                self.extra_dpad[p as usize - 1],
+            ),
+        }
+    }
+
+    /// True when a button on a selected line is held, which pulls P10-P13 low and
+    /// wakes the CPU from STOP.
+    pub fn is_input_low(&self) -> bool {
+        let (buttons, dpad) = self.player_state(self.current_player);
+
+        (!self.select.select_buttons() && buttons != 0) || (!self.select.select_dpad() && dpad != 0)
+    }
+
     pub fn read(&self) -> u8 {
+        let (buttons, dpad) = self.player_state(self.current_player);
+
+        if self.player_count > 1 && self.select.select_buttons() && self.select.select_dpad() {
+            // With both lines deselected the SGB returns the controller ID (0xF = player 1)
+            return self.select.0 & !self.current_player;
+        }
+
         let mut btn_state = 0;
 
         if !self.select.select_buttons() {
-            btn_state |= self.buttons.0;
+            btn_state |= buttons;
         }
         if !self.select.select_dpad() {
-            btn_state |= self.dpad.0;
+            btn_state |= dpad;
         }
 
         self.select.0 & !btn_state
     }
 
     pub fn write(&mut self, value: u8) {
+        let p15_was_low = !self.select.select_buttons();
+
         self.select.0 = value | 0b1100_1111;
+
+        // The SGB moves to the next controller when P15 goes back high
+        if self.player_count > 1 && p15_was_low && self.select.select_buttons() {
+            self.current_player = (self.current_player + 1) & (self.player_count - 1);
+        }
     }
 }
@@ -1,6 +1,8 @@
 pub mod cpu;
 pub mod joypad;
+pub mod link;
 pub mod memory;
 pub mod ppu;
 pub mod serial;
+pub mod sgb;
 pub mod timer;
@@ -0,0 +1,425 @@
+use crate::cpu::CPU;
+use crate::serial::LinkPartner;
+
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::rc::Rc;
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicU8, Ordering};
+use std::sync::Arc;
+
+/// Longest a single `CPU::do_step` can run: a 6 M-cycle instruction right after EI
+/// followed by the 5 M-cycle interrupt dispatch
+const MAX_STEP_CYCLES: usize = 11 * 4;
+
+#[derive(Debug, Default)]
+struct End {
+    /// M-cycles this end has run
+    cycle: u64,
+    /// SB and the cycle it was first seen in, kept until the other end is past it
+    sb: VecDeque<(u64, u8)>,
+    /// Bits clocked into this end by the other one, with the cycle of the edge
+    edges: VecDeque<(u64, u8)>,
+}
+
+#[derive(Debug, Default)]
+struct Line {
+    ends: [End; 2],
+}
+
+/// One end of a link cable between two Game Boys running in the same process.
+///
+/// Every M-cycle each end logs its SB and the clock edges it drives, stamped with
+/// the cycle. Both Game Boys run on one thread with [`step_lockstep`], which keeps
+/// the one waiting on the external clock behind the other, so both shift a bit at
+/// the same cycle.
+#[derive(Debug)]
+pub struct CablePort {
+    line: Rc<RefCell<Line>>,
+    side: usize,
+}
+
+/// Creates a link cable, plug each end into one Game Boy's serial port before
+/// either of them runs and run both of them with [`step_lockstep`].
+pub fn link_cable() -> (CablePort, CablePort) {
+    let line = Rc::new(RefCell::new(Line::default()));
+
+    (
+        CablePort {
+            line: line.clone(),
+            side: 0,
+        },
+        CablePort { line, side: 1 },
+    )
+}
+
+impl CablePort {
+    /// Runs this end for one more M-cycle. When it drives a clock edge, returns the
+    /// SB of the other end at that cycle, otherwise the bit clocked in by the other
+    /// end, if any.
+    fn sync(&mut self, sb: u8, edge: bool) -> Option<u8> {
+        // An unplugged line is pulled high
+        if Rc::strong_count(&self.line) == 1 {
+            return edge.then_some(0xFF);
+        }
+
+        let mut line = self.line.borrow_mut();
+        let [a, b] = &mut line.ends;
+        let (this, other) = if self.side == 0 { (a, b) } else { (b, a) };
+
+        this.cycle += 1;
+        let cycle = this.cycle;
+        if this.sb.back().map(|&(_, last)| last) != Some(sb) {
+            this.sb.push_back((cycle, sb));
+        }
+
+        // Only the SB the other end had at this cycle is still needed
+        while other.sb.get(1).is_some_and(|&(at, _)| at <= cycle) {
+            other.sb.pop_front();
+        }
+
+        if edge {
+            other.edges.push_back((cycle, sb));
+            return Some(other.sb.front().map_or(0xFF, |&(_, sb)| sb));
+        }
+
+        let mut received = None;
+        while let Some(&(at, sb)) = this.edges.front() {
+            if at > cycle {
+                break;
+            }
+            this.edges.pop_front();
+            received = Some(sb);
+        }
+        received
+    }
+}
+
+impl LinkPartner for CablePort {
+    fn exchange_bit(&mut self, sb: u8) -> u8 {
+        self.sync(sb, true).unwrap_or(0xFF) >> 7
+    }
+
+    fn external_clock(&mut self, sb: u8) -> Option<u8> {
+        self.sync(sb, false).map(|other| other >> 7)
+    }
+
+    fn between_edges(&mut self, sb: u8) {
+        self.sync(sb, false);
+    }
+
+    fn idle(&mut self, sb: u8) {
+        // Clock edges received while no transfer is armed are lost
+        self.sync(sb, false);
+    }
+}
+
+/// Runs one instruction on the Game Boy that is furthest behind and returns its index.
+///
+/// Called in a loop, this keeps linked instances within one instruction of each
+/// other. A Game Boy waiting on the external clock only runs once it is a full
+/// step behind the others, so every clock edge it could see is already driven.
+pub fn step_lockstep(cpus: &mut [&mut CPU]) -> usize {
+    let (idx, cpu) = cpus
+        .iter_mut()
+        .enumerate()
+        .min_by_key(|(_, cpu)| {
+            if cpu.mmu.borrow().serial.waits_for_clock() {
+                cpu.t_cycles + MAX_STEP_CYCLES
+            } else {
+                cpu.t_cycles
+            }
+        })
+        .expect("no Game Boy to step");
+
+    cpu.do_step();
+    idx
+}
+
+/// Spins before yielding while waiting for the other end
+const SPINS: u32 = 64;
+
+/// What one end drove on the line during an M-cycle
+#[derive(Debug, Default)]
+struct Slot {
+    sb: AtomicU8,
+    edge: AtomicBool,
+}
+
+#[derive(Debug, Default)]
+struct ThreadedEnd {
+    /// M-cycles this end has published
+    cycles: AtomicU64,
+    /// Indexed by the parity of the cycle, the other end may already publish the
+    /// next cycle while this one is still being read
+    slots: [Slot; 2],
+}
+
+#[derive(Debug, Default)]
+struct ThreadedLine {
+    ends: [ThreadedEnd; 2],
+    /// Set when either end is unplugged, the other one stops waiting for it
+    closed: AtomicBool,
+}
+
+/// One end of a link cable between two Game Boys each running on its own thread.
+///
+/// Every M-cycle each end publishes its SB and whether it drives a clock edge,
+/// then waits for the other end to reach the same cycle. Driving both ends from
+/// the same thread deadlocks, use [`CablePort`] there.
+#[derive(Debug)]
+pub struct ThreadedCablePort {
+    line: Arc<ThreadedLine>,
+    side: usize,
+    cycle: u64,
+}
+
+/// Creates a link cable for Game Boys on separate threads, plug each end into one
+/// Game Boy's serial port before either of them runs.
+pub fn threaded_link_cable() -> (ThreadedCablePort, ThreadedCablePort) {
+    let line = Arc::new(ThreadedLine::default());
+
+    (
+        ThreadedCablePort {
+            line: line.clone(),
+            side: 0,
+            cycle: 0,
+        },
+        ThreadedCablePort {
+            line,
+            side: 1,
+            cycle: 0,
+        },
+    )
+}
+
+impl ThreadedCablePort {
+    /// M-cycles run since the cable was plugged in
+    pub fn cycles(&self) -> u64 {
+        self.cycle
+    }
+
+    /// Publishes this end for the next M-cycle and returns the SB of the other end
+    /// and whether it drove a clock edge in the same cycle. Returns None once the
+    /// other end is unplugged.
+    fn sync(&mut self, sb: u8, edge: bool) -> Option<(u8, bool)> {
+        self.cycle += 1;
+        let parity = (self.cycle % 2) as usize;
+
+        let end = &self.line.ends[self.side];
+        end.slots[parity].sb.store(sb, Ordering::Relaxed);
+        end.slots[parity].edge.store(edge, Ordering::Relaxed);
+        end.cycles.store(self.cycle, Ordering::Release);
+
+        let other = &self.line.ends[1 - self.side];
+        let mut spins = 0;
+        while other.cycles.load(Ordering::Acquire) < self.cycle {
+            if self.line.closed.load(Ordering::Acquire) {
+                return None;
+            }
+
+            if spins < SPINS {
+                spins += 1;
+                std::hint::spin_loop();
+            } else {
+                std::thread::yield_now();
+            }
+        }
+
+        let slot = &other.slots[parity];
+        Some((
+            slot.sb.load(Ordering::Relaxed),
+            slot.edge.load(Ordering::Relaxed),
+        ))
+    }
+}
+
+impl LinkPartner for ThreadedCablePort {
+    fn exchange_bit(&mut self, sb: u8) -> u8 {
+        // An unplugged line is pulled high
+        self.sync(sb, true).map_or(1, |(other, _)| other >> 7)
+    }
+
+    fn external_clock(&mut self, sb: u8) -> Option<u8> {
+        match self.sync(sb, false) {
+            Some((other, true)) => Some(other >> 7),
+            _ => None,
+        }
+    }
+
+    fn between_edges(&mut self, sb: u8) {
+        self.sync(sb, false);
+    }
+
+    fn idle(&mut self, sb: u8) {
+        // Clock edges received while no transfer is armed are lost
+        self.sync(sb, false);
+    }
+}
+
+impl Drop for ThreadedCablePort {
+    fn drop(&mut self) {
+        self.line.closed.store(true, Ordering::Release);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::memory::{BootRom, MMU};
+
+    use std::thread;
+
+    fn game_boy(program: &[u8]) -> CPU {
+        let mut rom = vec![0; 0x8000];
+        rom[..program.len()].copy_from_slice(program);
+
+        let mut boot_rom = BootRom::new();
+        boot_rom.enabled = false;
+
+        let mbc = mbc::MBC::from(mbc::NoMBC::new(rom));
+        CPU::new(MMU::new(Some(mbc), boot_rom))
+    }
+
+    // ld a, data; ldh (SB), a; ld a, control; ldh (SC), a; jr -2
+    fn transfer_program(data: u8, control: u8) -> [u8; 10] {
+        [
+            0x3E, data, 0xE0, 0x01, 0x3E, control, 0xE0, 0x02, 0x18, 0xFE,
+        ]
+    }
+
+    fn serial_done(cpu: &CPU) -> bool {
+        cpu.mmu.borrow().ic.borrow().interrupt_flag.serial()
+    }
+
+    /// Counts M-cycles and records the cycle of every bit shifted in
+    struct Probe<P> {
+        port: P,
+        cycle: u64,
+        edges: Vec<u64>,
+    }
+
+    impl<P> Probe<P> {
+        fn new(port: P) -> Rc<RefCell<Probe<P>>> {
+            Rc::new(RefCell::new(Probe {
+                port,
+                cycle: 0,
+                edges: Vec::new(),
+            }))
+        }
+    }
+
+    impl<P: LinkPartner> LinkPartner for Probe<P> {
+        fn exchange_bit(&mut self, sb: u8) -> u8 {
+            self.cycle += 1;
+            self.edges.push(self.cycle);
+            self.port.exchange_bit(sb)
+        }
+
+        fn external_clock(&mut self, sb: u8) -> Option<u8> {
+            self.cycle += 1;
+            let bit = self.port.external_clock(sb);
+            if bit.is_some() {
+                self.edges.push(self.cycle);
+            }
+            bit
+        }
+
+        fn between_edges(&mut self, sb: u8) {
+            self.cycle += 1;
+            self.port.between_edges(sb);
+        }
+
+        fn idle(&mut self, sb: u8) {
+            self.cycle += 1;
+            self.port.idle(sb);
+        }
+    }
+
+    #[test]
+    fn test_lockstep_transfer() {
+        let mut master = game_boy(&transfer_program(0x42, 0x81));
+        let mut slave = game_boy(&transfer_program(0x99, 0x80));
+
+        let (a, b) = link_cable();
+        let (a, b) = (Probe::new(a), Probe::new(b));
+        master.mmu.borrow_mut().serial.connect(Box::new(a.clone()));
+        slave.mmu.borrow_mut().serial.connect(Box::new(b.clone()));
+
+        while !serial_done(&master) || !serial_done(&slave) {
+            step_lockstep(&mut [&mut slave, &mut master]);
+            assert!(master.t_cycles.abs_diff(slave.t_cycles) <= 2 * MAX_STEP_CYCLES);
+        }
+
+        assert_eq!(master.mmu.borrow().serial.read_data(), 0x99);
+        assert_eq!(slave.mmu.borrow().serial.read_data(), 0x42);
+
+        // Both ends shift on the same M-cycles, one edge every 128
+        let edges = a.borrow().edges.clone();
+        assert_eq!(edges.len(), 8);
+        assert_eq!(edges, b.borrow().edges);
+        assert!(edges.windows(2).all(|w| w[1] - w[0] == 128));
+    }
+
+    #[test]
+    fn test_unplugged() {
+        let mut cpu = game_boy(&transfer_program(0x42, 0x81));
+
+        let (a, b) = link_cable();
+        drop(b);
+        cpu.mmu.borrow_mut().serial.connect(Box::new(a));
+
+        while !serial_done(&cpu) {
+            step_lockstep(&mut [&mut cpu]);
+        }
+        assert_eq!(cpu.mmu.borrow().serial.read_data(), 0xFF);
+    }
+
+    /// Runs the program on its own thread until the serial interrupt is requested,
+    /// returns the received byte and the cycles the bits arrived in
+    fn run_threaded(
+        program: [u8; 10],
+        port: ThreadedCablePort,
+    ) -> thread::JoinHandle<(u8, Vec<u64>)> {
+        thread::spawn(move || {
+            let probe = Probe::new(port);
+
+            let mut cpu = game_boy(&program);
+            cpu.mmu.borrow_mut().serial.connect(Box::new(probe.clone()));
+
+            while !serial_done(&cpu) {
+                cpu.do_step();
+            }
+
+            let data = cpu.mmu.borrow().serial.read_data();
+            let edges = probe.borrow().edges.clone();
+            (data, edges)
+        })
+    }
+
+    #[test]
+    fn test_threaded_transfer() {
+        let (a, b) = threaded_link_cable();
+        let master = run_threaded(transfer_program(0x42, 0x81), a);
+        let slave = run_threaded(transfer_program(0x99, 0x80), b);
+
+        let (master_data, master_edges) = master.join().unwrap();
+        let (slave_data, slave_edges) = slave.join().unwrap();
+        assert_eq!(master_data, 0x99);
+        assert_eq!(slave_data, 0x42);
+
+        assert_eq!(master_edges.len(), 8);
+        assert_eq!(master_edges, slave_edges);
+        assert!(master_edges.windows(2).all(|w| w[1] - w[0] == 128));
+    }
+
+    #[test]
+    fn test_threaded_unplugged() {
+        let (a, b) = threaded_link_cable();
+        drop(b);
+
+        let (data, _) = run_threaded(transfer_program(0x42, 0x81), a)
+            .join()
+            .unwrap();
+        assert_eq!(data, 0xFF);
+    }
+}
@@ -0,0 +1,484 @@
+use crate::serial::LinkPartner;
+
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use log::{debug, info};
+
+pub const ADAPTER_PLAYERS: usize = 4;
+
+const PING_HEADER: u8 = 0xFE;
+const PING_ACK: u8 = 0x88;
+const START_REQUEST: u8 = 0xAA;
+const START_CONFIRM: u8 = 0xCC;
+
+// The adapter drives the clock, all timings are in M-cycles
+const BIT_PERIOD: u32 = 128;
+const PING_BYTE_GAP: u32 = 4096;
+const BASE_BYTE_GAP: u32 = 1024;
+const RATE_STEP: u32 = 256;
+
+const DEFAULT_PACKET_SIZE: u8 = 4;
+const MAX_PACKET_SIZE: u8 = 16;
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum AdapterPhase {
+    /// Polls every port with `FE STAT STAT STAT` until player 1 asks to start
+    Ping,
+    /// Answers the start request with four `CC`
+    Start,
+    /// Collects one packet from each player and broadcasts the previous ones
+    Transmission,
+}
+
+#[derive(Debug)]
+struct Adapter {
+    connected: [bool; ADAPTER_PLAYERS],
+    acked: [bool; ADAPTER_PLAYERS],
+
+    phase: AdapterPhase,
+    index: usize,
+
+    // Clock generation
+    timer: u32,
+    bits_sent: u8,
+    byte_in_flight: bool,
+    out_bytes: [u8; ADAPTER_PLAYERS],
+    in_bytes: [u8; ADAPTER_PLAYERS],
+    pending: [Option<u8>; ADAPTER_PLAYERS],
+
+    // Ping phase
+    ping_acks: [bool; ADAPTER_PLAYERS],
+    start_requests: u8,
+    rate: u8,
+    packet_size: u8,
+
+    // Transmission phase
+    packet: Vec<u8>,
+    previous_packet: Vec<u8>,
+    all_restart: bool,
+}
+
+impl Adapter {
+    fn new() -> Self {
+        Adapter {
+            connected: [false; ADAPTER_PLAYERS],
+            acked: [false; ADAPTER_PLAYERS],
+            phase: AdapterPhase::Ping,
+            index: 0,
+            timer: PING_BYTE_GAP,
+            bits_sent: 0,
+            byte_in_flight: false,
+            out_bytes: [0; ADAPTER_PLAYERS],
+            in_bytes: [0; ADAPTER_PLAYERS],
+            pending: [None; ADAPTER_PLAYERS],
+            ping_acks: [false; ADAPTER_PLAYERS],
+            start_requests: 0,
+            rate: 0,
+            packet_size: DEFAULT_PACKET_SIZE,
+            packet: Vec::new(),
+            previous_packet: Vec::new(),
+            all_restart: true,
+        }
+    }
+
+    /// The lowest connected port keeps time for the adapter.
+    fn clock_player(&self) -> Option<usize> {
+        self.connected.iter().position(|&connected| connected)
+    }
+
+    fn step(&mut self) {
+        if self.timer > 0 {
+            self.timer -= 1;
+            return;
+        }
+
+        if self.bits_sent == 0 {
+            if self.byte_in_flight {
+                self.byte_received();
+            }
+            self.start_byte();
+        }
+
+        for player in 0..ADAPTER_PLAYERS {
+            self.pending[player] = Some(self.out_bytes[player] >> 7);
+            self.out_bytes[player] <<= 1;
+        }
+
+        self.bits_sent += 1;
+        if self.bits_sent == 8 {
+            self.bits_sent = 0;
+            self.timer = self.byte_gap();
+        } else {
+            self.timer = BIT_PERIOD - 1;
+        }
+    }
+
+    fn byte_gap(&self) -> u32 {
+        match self.phase {
+            AdapterPhase::Ping => PING_BYTE_GAP,
+            AdapterPhase::Start | AdapterPhase::Transmission => {
+                BASE_BYTE_GAP + (self.rate & 0x0F) as u32 * RATE_STEP
+            }
+        }
+    }
+
+    /// Status byte of a ping packet: connected players in the upper nibble,
+    /// the receiving player's ID in the lower bits.
+    fn status(&self, player: usize) -> u8 {
+        let mask = self
+            .acked
+            .iter()
+            .enumerate()
+            .fold(0, |acc, (i, &acked)| acc | (acked as u8) << (4 + i));
+
+        mask | (player as u8 + 1)
+    }
+
+    fn start_byte(&mut self) {
+        for player in 0..ADAPTER_PLAYERS {
+            self.out_bytes[player] = match self.phase {
+                AdapterPhase::Ping if self.index == 0 => PING_HEADER,
+                AdapterPhase::Ping => self.status(player),
+                AdapterPhase::Start => START_CONFIRM,
+                AdapterPhase::Transmission => self.previous_packet[self.index],
+            };
+        }
+
+        self.in_bytes = [0; ADAPTER_PLAYERS];
+        self.byte_in_flight = true;
+    }
+
+    fn byte_received(&mut self) {
+        self.byte_in_flight = false;
+
+        match self.phase {
+            AdapterPhase::Ping => self.ping_received(),
+            AdapterPhase::Start => {
+                self.index += 1;
+                if self.index == 4 {
+                    let size = self.packet_size as usize * ADAPTER_PLAYERS;
+                    self.packet = vec![0; size];
+                    self.previous_packet = vec![0; size];
+                    self.all_restart = true;
+                    self.enter(AdapterPhase::Transmission);
+                }
+            }
+            AdapterPhase::Transmission => self.transmission_received(),
+        }
+    }
+
+    fn ping_received(&mut self) {
+        let player_1 = self.in_bytes[0];
+
+        if player_1 == START_REQUEST {
+            self.start_requests += 1;
+        }
+
+        match self.index {
+            1 => {
+                for player in 0..ADAPTER_PLAYERS {
+                    // Player 1 keeps its place while asking to start
+                    self.ping_acks[player] =
+                        matches!(self.in_bytes[player], PING_ACK | START_REQUEST);
+                }
+            }
+            2 if player_1 != START_REQUEST => self.rate = player_1,
+            3 if player_1 != START_REQUEST => {
+                self.packet_size = player_1.clamp(1, MAX_PACKET_SIZE);
+            }
+            _ => {}
+        }
+
+        self.index += 1;
+        if self.index < 4 {
+            return;
+        }
+
+        for player in 0..ADAPTER_PLAYERS {
+            self.acked[player] = self.connected[player] && self.ping_acks[player];
+        }
+
+        if self.start_requests == 4 && self.connected[0] {
+            info!(
+                "DMG-07 starting transmission: {} bytes per player, rate {:#04X}",
+                self.packet_size, self.rate
+            );
+            self.enter(AdapterPhase::Start);
+        } else {
+            self.enter(AdapterPhase::Ping);
+        }
+    }
+
+    fn transmission_received(&mut self) {
+        let size = self.packet_size as usize;
+
+        for player in 0..ADAPTER_PLAYERS {
+            if !self.acked[player] {
+                continue;
+            }
+
+            let byte = self.in_bytes[player];
+            self.all_restart &= byte == 0xFF;
+
+            if self.index < size {
+                self.packet[player * size + self.index] = byte;
+            }
+        }
+
+        self.index += 1;
+        if self.index < self.packet.len() {
+            return;
+        }
+
+        if self.all_restart {
+            debug!("DMG-07 restarting ping phase");
+            self.enter(AdapterPhase::Ping);
+            return;
+        }
+
+        std::mem::swap(&mut self.packet, &mut self.previous_packet);
+        self.packet.fill(0);
+        self.all_restart = true;
+        self.index = 0;
+    }
+
+    fn enter(&mut self, phase: AdapterPhase) {
+        self.phase = phase;
+        self.index = 0;
+        self.start_requests = 0;
+        self.ping_acks = [false; ADAPTER_PLAYERS];
+    }
+
+    /// Takes the bit clocked into `player` and records the one it sent back.
+    fn take_bit(&mut self, player: usize, sb: u8) -> Option<u8> {
+        let bit = self.pending[player].take()?;
+        self.in_bytes[player] = (self.in_bytes[player] << 1) | (sb >> 7);
+        Some(bit)
+    }
+}
+
+/// DMG-07 four player adapter.
+///
+/// Every Game Boy plugs into one of the ports and stays on the external clock, the
+/// adapter drives all of them: first polling each port with ping packets to negotiate
+/// the packet size and rate with player 1, then collecting one packet from every
+/// player and broadcasting all of them together on the next round.
+#[derive(Debug, Clone)]
+pub struct FourPlayerAdapter {
+    adapter: Rc<RefCell<Adapter>>,
+}
+
+/// One of the adapter's four ports, plug it into a Game Boy's serial port.
+#[derive(Debug)]
+pub struct AdapterPort {
+    adapter: Rc<RefCell<Adapter>>,
+    player: usize,
+}
+
+impl Default for FourPlayerAdapter {
+    fn default() -> FourPlayerAdapter {
+        FourPlayerAdapter::new()
+    }
+}
+
+impl FourPlayerAdapter {
+    pub fn new() -> Self {
+        FourPlayerAdapter {
+            adapter: Rc::new(RefCell::new(Adapter::new())),
+        }
+    }
+
+    /// Returns the port of `player` (0 to 3), or None if it is already in use.
+    pub fn port(&self, player: usize) -> Option<AdapterPort> {
+        let mut adapter = self.adapter.borrow_mut();
+        if player >= ADAPTER_PLAYERS || adapter.connected[player] {
+            return None;
+        }
+
+        adapter.connected[player] = true;
+        Some(AdapterPort {
+            adapter: self.adapter.clone(),
+            player,
+        })
+    }
+
+    pub fn phase(&self) -> AdapterPhase {
+        self.adapter.borrow().phase
+    }
+
+    /// Bytes each player sends per round, as requested by player 1.
+    pub fn packet_size(&self) -> u8 {
+        self.adapter.borrow().packet_size
+    }
+}
+
+impl AdapterPort {
+    fn clock(&mut self, sb: u8) -> Option<u8> {
+        let mut adapter = self.adapter.borrow_mut();
+
+        if adapter.clock_player() == Some(self.player) {
+            adapter.step();
+        }
+
+        adapter.take_bit(self.player, sb)
+    }
+}
+
+impl LinkPartner for AdapterPort {
+    fn exchange_bit(&mut self, _sb: u8) -> u8 {
+        // The adapter only listens to its own clock
+        debug!("Player {} is on the internal clock", self.player + 1);
+        1
+    }
+
+    fn external_clock(&mut self, sb: u8) -> Option<u8> {
+        self.clock(sb)
+    }
+
+    fn idle(&mut self, sb: u8) {
+        // Bits sent while no transfer is armed are lost
+        self.clock(sb);
+    }
+}
+
+impl Drop for AdapterPort {
+    fn drop(&mut self) {
+        let mut adapter = self.adapter.borrow_mut();
+        adapter.connected[self.player] = false;
+        adapter.acked[self.player] = false;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::memory::InterruptController;
+    use crate::serial::Serial;
+
+    struct Player {
+        serial: Serial,
+        ic: InterruptController,
+    }
+
+    impl Player {
+        fn new(port: AdapterPort) -> Self {
+            let mut serial = Serial::new();
+            serial.connect(Box::new(port));
+            Player {
+                serial,
+                ic: InterruptController::new(),
+            }
+        }
+    }
+
+    /// Arms every player with its byte and runs them until all of them received one.
+    fn exchange(players: &mut [Player], data: &[u8]) -> Vec<u8> {
+        for (player, &byte) in players.iter_mut().zip(data) {
+            player.serial.write_data(byte);
+            player.serial.write_control(0x80);
+            player.ic.interrupt_flag.set_serial(false);
+        }
+
+        while !players.iter().all(|p| p.ic.interrupt_flag.serial()) {
+            for player in players.iter_mut() {
+                player.serial.tick(&mut player.ic);
+            }
+        }
+
+        players.iter().map(|p| p.serial.read_data()).collect()
+    }
+
+    #[test]
+    fn test_four_player_session() {
+        let adapter = FourPlayerAdapter::new();
+        let mut players: Vec<Player> = [0, 2]
+            .into_iter()
+            .map(|i| Player::new(adapter.port(i).unwrap()))
+            .collect();
+
+        assert!(adapter.port(0).is_none());
+
+        // First ping: nobody acknowledged yet
+        assert_eq!(exchange(&mut players, &[0x88, 0x88]), [0xFE, 0xFE]);
+        assert_eq!(exchange(&mut players, &[0x88, 0x88]), [0x01, 0x03]);
+        assert_eq!(exchange(&mut players, &[0x00, 0x00]), [0x01, 0x03]);
+        assert_eq!(exchange(&mut players, &[0x02, 0x00]), [0x01, 0x03]);
+
+        // Second ping: players 1 and 3 are connected
+        assert_eq!(exchange(&mut players, &[0xAA, 0x88]), [0xFE, 0xFE]);
+        assert_eq!(exchange(&mut players, &[0xAA, 0x88]), [0x51, 0x53]);
+        assert_eq!(exchange(&mut players, &[0xAA, 0x00]), [0x51, 0x53]);
+        assert_eq!(exchange(&mut players, &[0xAA, 0x00]), [0x51, 0x53]);
+
+        for _ in 0..4 {
+            assert_eq!(exchange(&mut players, &[0x00, 0x00]), [0xCC, 0xCC]);
+        }
+        assert_eq!(adapter.packet_size(), 2);
+
+        // First round only echoes zeroes
+        let sent = [[0x11, 0x12], [0x31, 0x32]];
+        for i in 0..8 {
+            let data =
+                [sent[0].get(i).copied(), sent[1].get(i).copied()].map(|byte| byte.unwrap_or(0x00));
+            assert_eq!(exchange(&mut players, &data), [0x00, 0x00]);
+            assert_eq!(adapter.phase(), AdapterPhase::Transmission);
+        }
+
+        // Second round broadcasts everything received in the first one
+        let expected = [0x11, 0x12, 0x00, 0x00, 0x31, 0x32, 0x00, 0x00];
+        for byte in expected {
+            assert_eq!(exchange(&mut players, &[0x00, 0x00]), [byte, byte]);
+        }
+
+        // A full round of 0xFF from everyone goes back to pinging
+        for _ in 0..8 {
+            exchange(&mut players, &[0xFF, 0xFF]);
+        }
+        assert_eq!(exchange(&mut players, &[0x88, 0x88]), [0xFE, 0xFE]);
+        assert_eq!(adapter.phase(), AdapterPhase::Ping);
+    }
+
+    #[test]
+    fn test_four_players_connected() {
+        let adapter = FourPlayerAdapter::new();
+        let mut players: Vec<Player> = (0..ADAPTER_PLAYERS)
+            .map(|i| Player::new(adapter.port(i).unwrap()))
+            .collect();
+
+        // First ping: every player acknowledges, player 1 asks for 1 byte packets
+        assert_eq!(exchange(&mut players, &[0x88; 4]), [0xFE; 4]);
+        assert_eq!(exchange(&mut players, &[0x88; 4]), [0x01, 0x02, 0x03, 0x04]);
+        assert_eq!(exchange(&mut players, &[0x00; 4]), [0x01, 0x02, 0x03, 0x04]);
+        assert_eq!(
+            exchange(&mut players, &[0x01, 0, 0, 0]),
+            [0x01, 0x02, 0x03, 0x04]
+        );
+
+        // Second ping: all four are connected, player 1 starts
+        let start = [0xAA, 0x88, 0x88, 0x88];
+        assert_eq!(exchange(&mut players, &start), [0xFE; 4]);
+        assert_eq!(exchange(&mut players, &start), [0xF1, 0xF2, 0xF3, 0xF4]);
+        assert_eq!(exchange(&mut players, &start), [0xF1, 0xF2, 0xF3, 0xF4]);
+        assert_eq!(exchange(&mut players, &start), [0xF1, 0xF2, 0xF3, 0xF4]);
+
+        for _ in 0..4 {
+            assert_eq!(exchange(&mut players, &[0x00; 4]), [0xCC; 4]);
+        }
+        assert_eq!(adapter.packet_size(), 1);
+
+        // Each player sends its packet in the first round
+        let sent = [0x11, 0x22, 0x33, 0x44];
+        assert_eq!(exchange(&mut players, &sent), [0x00; 4]);
+        assert_eq!(adapter.phase(), AdapterPhase::Transmission);
+        for _ in 1..4 {
+            assert_eq!(exchange(&mut players, &[0x00; 4]), [0x00; 4]);
+        }
+
+        // and every player receives all four packets in the next one
+        for byte in sent {
+            assert_eq!(exchange(&mut players, &[0x00; 4]), [byte; 4]);
+        }
+        assert_eq!(adapter.phase(), AdapterPhase::Transmission);
+    }
+}
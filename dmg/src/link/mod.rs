@@ -0,0 +1,9 @@
+pub mod cable;
+pub mod four_player;
+pub mod printer;
+pub mod tcp;
+
+pub use cable::*;
+pub use four_player::*;
+pub use printer::*;
+pub use tcp::*;
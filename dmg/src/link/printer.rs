@@ -0,0 +1,484 @@
+use crate::serial::LinkPartner;
+
+use log::{debug, info, warn};
+
+pub const PRINTER_WIDTH: usize = 160;
+
+const MAGIC_1: u8 = 0x88;
+const MAGIC_2: u8 = 0x33;
+const DEVICE_ID: u8 = 0x81;
+
+// One DATA packet holds two rows of 20 tiles
+const MAX_DATA_SIZE: usize = 0x280;
+const MAX_BUFFER_SIZE: usize = 9 * MAX_DATA_SIZE;
+
+// Each margin unit feeds this many blank lines
+const MARGIN_LINES: usize = 8;
+
+// Status queries answered as busy after a PRINT command
+const PRINT_BUSY_POLLS: u8 = 4;
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum PrinterCommand {
+    Init = 0x01,
+    Print = 0x02,
+    Data = 0x04,
+    Status = 0x0F,
+}
+
+impl PrinterCommand {
+    fn from_u8(value: u8) -> Option<Self> {
+        match value {
+            0x01 => Some(PrinterCommand::Init),
+            0x02 => Some(PrinterCommand::Print),
+            0x04 => Some(PrinterCommand::Data),
+            0x0F => Some(PrinterCommand::Status),
+            _ => None,
+        }
+    }
+}
+
+pub mod status {
+    pub const CHECKSUM_ERROR: u8 = 1 << 0;
+    pub const BUSY: u8 = 1 << 1;
+    pub const IMAGE_FULL: u8 = 1 << 2;
+    pub const UNPROCESSED: u8 = 1 << 3;
+    pub const PACKET_ERROR: u8 = 1 << 4;
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+enum State {
+    Magic1,
+    Magic2,
+    Command,
+    Compression,
+    LengthLow,
+    LengthHigh,
+    Data,
+    ChecksumLow,
+    ChecksumHigh,
+    DeviceId,
+    Status,
+}
+
+/// A printed strip, one shade per pixel (0 = white, 3 = black).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Printout {
+    pub width: usize,
+    pub height: usize,
+    pub pixels: Vec<u8>,
+}
+
+impl Printout {
+    /// Gray level of each pixel, 0xFF for white paper.
+    pub fn luma(&self) -> Vec<u8> {
+        self.pixels
+            .iter()
+            .map(|&shade| 0xFF - shade * 0x55)
+            .collect()
+    }
+
+    /// Encodes the strip as a grayscale PNG, fails on an empty strip.
+    pub fn to_png(&self) -> Result<Vec<u8>, png::EncodingError> {
+        let mut out = Vec::new();
+
+        let mut encoder = png::Encoder::new(&mut out, self.width as u32, self.height as u32);
+        encoder.set_color(png::ColorType::Grayscale);
+        encoder.set_depth(png::BitDepth::Eight);
+
+        let mut writer = encoder.write_header()?;
+        writer.write_image_data(&self.luma())?;
+        writer.finish()?;
+
+        Ok(out)
+    }
+}
+
+/// Game Boy Printer, plugged into the serial port as a link partner.
+#[derive(Debug)]
+pub struct Printer {
+    // Byte level shifting
+    in_byte: u8,
+    out_byte: u8,
+    bit_count: u8,
+
+    // Packet parsing
+    state: State,
+    command: u8,
+    compressed: bool,
+    length: usize,
+    data: Vec<u8>,
+    checksum: u16,
+    received_checksum: u16,
+
+    buffer: Vec<u8>,
+    status: u8,
+    busy_polls: u8,
+    printouts: Vec<Printout>,
+}
+
+impl Default for Printer {
+    fn default() -> Printer {
+        Printer::new()
+    }
+}
+
+impl Printer {
+    pub fn new() -> Self {
+        Printer {
+            in_byte: 0,
+            out_byte: 0,
+            bit_count: 0,
+            state: State::Magic1,
+            command: 0,
+            compressed: false,
+            length: 0,
+            data: Vec::with_capacity(MAX_DATA_SIZE),
+            checksum: 0,
+            received_checksum: 0,
+            buffer: Vec::with_capacity(MAX_BUFFER_SIZE),
+            status: 0,
+            busy_polls: 0,
+            printouts: Vec::new(),
+        }
+    }
+
+    /// Strips printed since the last call.
+    pub fn take_printouts(&mut self) -> Vec<Printout> {
+        std::mem::take(&mut self.printouts)
+    }
+
+    /// Handles one received byte, returns the byte to send back with the next one.
+    fn receive(&mut self, byte: u8) -> u8 {
+        match self.state {
+            State::Magic1 => {
+                if byte == MAGIC_1 {
+                    self.state = State::Magic2;
+                }
+            }
+            State::Magic2 => {
+                self.state = if byte == MAGIC_2 {
+                    State::Command
+                } else {
+                    State::Magic1
+                };
+            }
+            State::Command => {
+                self.command = byte;
+                self.checksum = byte as u16;
+                self.state = State::Compression;
+            }
+            State::Compression => {
+                self.compressed = byte & 1 != 0;
+                self.checksum = self.checksum.wrapping_add(byte as u16);
+                self.state = State::LengthLow;
+            }
+            State::LengthLow => {
+                self.length = byte as usize;
+                self.checksum = self.checksum.wrapping_add(byte as u16);
+                self.state = State::LengthHigh;
+            }
+            State::LengthHigh => {
+                self.length |= (byte as usize) << 8;
+                self.checksum = self.checksum.wrapping_add(byte as u16);
+                self.data.clear();
+                self.state = if self.length == 0 {
+                    State::ChecksumLow
+                } else {
+                    State::Data
+                };
+            }
+            State::Data => {
+                self.data.push(byte);
+                self.checksum = self.checksum.wrapping_add(byte as u16);
+                if self.data.len() == self.length {
+                    self.state = State::ChecksumLow;
+                }
+            }
+            State::ChecksumLow => {
+                self.received_checksum = byte as u16;
+                self.state = State::ChecksumHigh;
+            }
+            State::ChecksumHigh => {
+                self.received_checksum |= (byte as u16) << 8;
+                self.state = State::DeviceId;
+
+                if self.received_checksum == self.checksum {
+                    self.status &= !status::CHECKSUM_ERROR;
+                    self.execute();
+                } else {
+                    warn!(
+                        "Printer checksum mismatch: {:#06X} != {:#06X}",
+                        self.received_checksum, self.checksum
+                    );
+                    self.status |= status::CHECKSUM_ERROR;
+                }
+
+                return DEVICE_ID;
+            }
+            State::DeviceId => {
+                self.state = State::Status;
+                return self.report_status();
+            }
+            State::Status => {
+                self.state = State::Magic1;
+            }
+        }
+
+        0x00
+    }
+
+    fn report_status(&mut self) -> u8 {
+        let status = self.status;
+
+        if self.busy_polls > 0 {
+            self.busy_polls -= 1;
+            if self.busy_polls == 0 {
+                self.status &= !(status::BUSY | status::IMAGE_FULL);
+            }
+        }
+
+        status
+    }
+
+    fn execute(&mut self) {
+        match PrinterCommand::from_u8(self.command) {
+            Some(PrinterCommand::Init) => {
+                debug!("Printer INIT");
+                self.buffer.clear();
+                self.status = 0;
+                self.busy_polls = 0;
+            }
+            Some(PrinterCommand::Data) => {
+                let data = std::mem::take(&mut self.data);
+                let data = if self.compressed {
+                    decompress(&data)
+                } else {
+                    data
+                };
+
+                if data.is_empty() {
+                    // An empty DATA packet ends the image
+                    self.status |= status::IMAGE_FULL;
+                } else if self.buffer.len() + data.len() > MAX_BUFFER_SIZE {
+                    warn!("Printer buffer overflow, dropping {} bytes", data.len());
+                    self.status |= status::PACKET_ERROR;
+                } else {
+                    self.buffer.extend_from_slice(&data);
+                    self.status |= status::UNPROCESSED;
+                }
+
+                self.data = Vec::with_capacity(MAX_DATA_SIZE);
+            }
+            Some(PrinterCommand::Print) => {
+                if self.data.len() < 4 {
+                    warn!("Printer PRINT packet too short");
+                    self.status |= status::PACKET_ERROR;
+                    return;
+                }
+
+                let sheets = self.data[0];
+                let margins = self.data[1];
+                let palette = self.data[2];
+
+                if sheets == 0 {
+                    // Only feeds paper, the image stays in the buffer
+                    info!("Printer fed paper");
+                    self.status |= status::BUSY;
+                    self.busy_polls = PRINT_BUSY_POLLS;
+                    return;
+                }
+
+                let printout = self.print(margins >> 4, margins & 0x0F, palette);
+                info!(
+                    "Printer printed {}x{} strip",
+                    printout.width, printout.height
+                );
+                self.printouts.push(printout);
+
+                self.buffer.clear();
+                self.status &= !status::UNPROCESSED;
+                self.status |= status::BUSY | status::IMAGE_FULL;
+                self.busy_polls = PRINT_BUSY_POLLS;
+            }
+            Some(PrinterCommand::Status) => {}
+            None => {
+                warn!("Unknown printer command {:#04X}", self.command);
+                self.status |= status::PACKET_ERROR;
+            }
+        }
+    }
+
+    /// Renders the buffered tiles, 20 per row, through `palette` (BGP layout).
+    fn print(&self, margin_before: u8, margin_after: u8, palette: u8) -> Printout {
+        // Palette 0 is treated as the default identity palette
+        let palette = if palette == 0 { 0xE4 } else { palette };
+
+        let tile_rows = self.buffer.len() / (20 * 16);
+        let top = margin_before as usize * MARGIN_LINES;
+        let height = top + tile_rows * 8 + margin_after as usize * MARGIN_LINES;
+
+        let mut pixels = vec![0; PRINTER_WIDTH * height];
+
+        for (tile, data) in self.buffer.chunks_exact(16).enumerate() {
+            let tile_x = (tile % 20) * 8;
+            let tile_y = top + (tile / 20) * 8;
+
+            for row in 0..8 {
+                let lo = data[row * 2];
+                let hi = data[row * 2 + 1];
+
+                for col in 0..8 {
+                    let color_index = ((lo >> (7 - col)) & 1) | (((hi >> (7 - col)) & 1) << 1);
+                    let shade = (palette >> (color_index * 2)) & 0b11;
+
+                    pixels[(tile_y + row) * PRINTER_WIDTH + tile_x + col] = shade;
+                }
+            }
+        }
+
+        Printout {
+            width: PRINTER_WIDTH,
+            height,
+            pixels,
+        }
+    }
+}
+
+/// Expands the printer's run length encoding. A control byte with bit 7 set repeats
+/// the next byte `(n & 0x7F) + 2` times, otherwise the next `n + 1` bytes are literal.
+fn decompress(data: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(MAX_DATA_SIZE);
+    let mut i = 0;
+
+    while i < data.len() {
+        let control = data[i];
+        i += 1;
+
+        if control & 0x80 != 0 {
+            let count = (control & 0x7F) as usize + 2;
+            if let Some(&value) = data.get(i) {
+                out.extend(std::iter::repeat_n(value, count));
+            }
+            i += 1;
+        } else {
+            let count = control as usize + 1;
+            let end = (i + count).min(data.len());
+            out.extend_from_slice(&data[i..end]);
+            i = end;
+        }
+    }
+
+    out
+}
+
+impl LinkPartner for Printer {
+    fn exchange_bit(&mut self, sb: u8) -> u8 {
+        let out = self.out_byte >> 7;
+        self.out_byte <<= 1;
+
+        self.in_byte = (self.in_byte << 1) | (sb >> 7);
+        self.bit_count += 1;
+
+        if self.bit_count == 8 {
+            self.bit_count = 0;
+            self.out_byte = self.receive(self.in_byte);
+        }
+
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn send_packet(printer: &mut Printer, command: u8, compressed: bool, data: &[u8]) -> [u8; 2] {
+        let mut packet = vec![MAGIC_1, MAGIC_2, command, compressed as u8];
+        packet.extend_from_slice(&(data.len() as u16).to_le_bytes());
+        packet.extend_from_slice(data);
+
+        let checksum = packet[2..]
+            .iter()
+            .fold(0u16, |acc, &byte| acc.wrapping_add(byte as u16));
+        packet.extend_from_slice(&checksum.to_le_bytes());
+        packet.extend_from_slice(&[0, 0]);
+
+        let mut replies = Vec::new();
+        for byte in packet {
+            let mut reply = 0;
+            for bit in 0..8 {
+                reply = (reply << 1) | printer.exchange_bit(byte << bit);
+            }
+            replies.push(reply);
+        }
+
+        [replies[replies.len() - 2], replies[replies.len() - 1]]
+    }
+
+    #[test]
+    fn test_print() {
+        let mut printer = Printer::new();
+
+        assert_eq!(send_packet(&mut printer, 0x01, false, &[]), [0x81, 0x00]);
+
+        // One tile row, the first tile all color 3, the rest color 0
+        let mut tiles = vec![0x00; 20 * 16];
+        tiles[..16].fill(0xFF);
+        let [_, status] = send_packet(&mut printer, 0x04, false, &tiles);
+        assert_eq!(status, status::UNPROCESSED);
+
+        // Compressed: 2 literal bytes then 318 zeroes
+        let mut compressed = vec![0x01, 0xFF, 0xFF];
+        for _ in 0..2 {
+            compressed.extend_from_slice(&[0x80 | 127, 0x00]);
+        }
+        compressed.extend_from_slice(&[0x80 | 58, 0x00]);
+        send_packet(&mut printer, 0x04, true, &compressed);
+        send_packet(&mut printer, 0x04, false, &[]);
+
+        let [_, status] = send_packet(&mut printer, 0x02, false, &[1, 0x10, 0xE4, 0x40]);
+        assert_ne!(status & status::BUSY, 0);
+
+        let printouts = printer.take_printouts();
+        assert_eq!(printouts.len(), 1);
+
+        let printout = &printouts[0];
+        assert_eq!(printout.height, MARGIN_LINES + 16);
+        assert_eq!(printout.pixels[0], 0);
+        assert_eq!(printout.pixels[MARGIN_LINES * PRINTER_WIDTH], 3);
+        assert_eq!(printout.pixels[MARGIN_LINES * PRINTER_WIDTH + 8], 0);
+        // First row of the compressed tile row
+        assert_eq!(printout.pixels[(MARGIN_LINES + 8) * PRINTER_WIDTH], 3);
+        assert_eq!(printout.pixels[(MARGIN_LINES + 9) * PRINTER_WIDTH], 0);
+
+        assert_eq!(&printout.to_png().unwrap()[1..4], b"PNG");
+
+        for _ in 0..PRINT_BUSY_POLLS {
+            send_packet(&mut printer, 0x0F, false, &[]);
+        }
+        assert_eq!(send_packet(&mut printer, 0x0F, false, &[]), [0x81, 0x00]);
+    }
+
+    #[test]
+    fn test_paper_feed() {
+        let mut printer = Printer::new();
+        send_packet(&mut printer, 0x01, false, &[]);
+        send_packet(&mut printer, 0x04, false, &[0x00; 20 * 16]);
+
+        // Zero sheets feeds the margins without printing the buffer
+        let [_, status] = send_packet(&mut printer, 0x02, false, &[0, 0x11, 0xE4, 0x40]);
+        assert_ne!(status & status::BUSY, 0);
+        assert!(printer.take_printouts().is_empty());
+
+        send_packet(&mut printer, 0x02, false, &[1, 0x00, 0xE4, 0x40]);
+        assert_eq!(printer.take_printouts()[0].height, 8);
+
+        let empty = Printout {
+            width: PRINTER_WIDTH,
+            height: 0,
+            pixels: Vec::new(),
+        };
+        assert!(empty.to_png().is_err());
+    }
+}
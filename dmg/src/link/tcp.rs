@@ -0,0 +1,329 @@
+use crate::serial::LinkPartner;
+
+use std::collections::VecDeque;
+use std::io::{self, Read, Write};
+use std::net::{TcpListener, TcpStream, ToSocketAddrs};
+use std::sync::mpsc::{channel, Receiver, TryRecvError};
+use std::time::Duration;
+
+use log::{debug, info, warn};
+
+pub const DEFAULT_PORT: u16 = 5738;
+
+/// The serial port is polled once per M-cycle
+const M_CYCLES_PER_SECOND: u64 = 1_048_576;
+
+// Every message is [kind, sequence, data]
+const MSG_TRANSFER: u8 = 0x01;
+const MSG_REPLY: u8 = 0x02;
+const MSG_CANCEL: u8 = 0x03;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Message {
+    /// Sent by the side driving the clock, carries its SB
+    Transfer(u8, u8),
+    /// The other side's SB, answering the transfer with the same sequence number
+    Reply(u8, u8),
+    /// The transfer with this sequence number timed out and must not be answered
+    Cancel(u8),
+}
+
+/// Link cable to another emulator over TCP.
+///
+/// The side on the internal clock sends its byte when a transfer starts and holds
+/// the serial clock until the partner answers with its own byte, so both sides stay
+/// in sync on every transfer no matter the latency. Emulation keeps running while
+/// it waits. The side on the external clock answers as soon as its transfer is armed.
+pub struct TcpLink {
+    stream: TcpStream,
+    messages: Receiver<Message>,
+    connected: bool,
+    sequence: u8,
+    incoming: u8,
+    bits_left: u8,
+    /// Transfer on the internal clock waiting for its reply
+    pending: Option<u8>,
+    reply: Option<u8>,
+    waited: u64,
+    timeout: u64,
+    /// Transfers from the partner waiting for this side to arm its own
+    transfers: VecDeque<(u8, u8)>,
+}
+
+impl TcpLink {
+    /// Waits for a partner to connect on `addr`.
+    pub fn host<A: ToSocketAddrs>(addr: A) -> io::Result<TcpLink> {
+        let listener = TcpListener::bind(addr)?;
+        let (stream, peer) = listener.accept()?;
+        info!("Link partner connected from {}", peer);
+
+        TcpLink::from_stream(stream)
+    }
+
+    /// Connects to a partner hosting on `addr`.
+    pub fn join<A: ToSocketAddrs>(addr: A) -> io::Result<TcpLink> {
+        let stream = TcpStream::connect(addr)?;
+        info!("Connected to link partner {}", stream.peer_addr()?);
+
+        TcpLink::from_stream(stream)
+    }
+
+    pub fn from_stream(stream: TcpStream) -> io::Result<TcpLink> {
+        stream.set_nodelay(true)?;
+
+        let mut reader = stream.try_clone()?;
+        let (tx, rx) = channel();
+
+        std::thread::spawn(move || {
+            let mut msg = [0u8; 3];
+
+            while reader.read_exact(&mut msg).is_ok() {
+                let message = match msg[0] {
+                    MSG_TRANSFER => Message::Transfer(msg[1], msg[2]),
+                    MSG_REPLY => Message::Reply(msg[1], msg[2]),
+                    MSG_CANCEL => Message::Cancel(msg[1]),
+                    kind => {
+                        warn!("Unknown link message {:#04X}", kind);
+                        continue;
+                    }
+                };
+
+                if tx.send(message).is_err() {
+                    break;
+                }
+            }
+        });
+
+        Ok(TcpLink {
+            stream,
+            messages: rx,
+            connected: true,
+            sequence: 0,
+            incoming: 0xFF,
+            bits_left: 0,
+            pending: None,
+            reply: None,
+            waited: 0,
+            timeout: M_CYCLES_PER_SECOND,
+            transfers: VecDeque::new(),
+        })
+    }
+
+    /// How much emulated time a transfer on the internal clock waits for the
+    /// partner before giving up and reading 0xFF, as with no cable plugged in.
+    pub fn set_timeout(&mut self, timeout: Duration) {
+        self.timeout = (timeout.as_secs_f64() * M_CYCLES_PER_SECOND as f64) as u64;
+    }
+
+    pub fn is_connected(&self) -> bool {
+        self.connected
+    }
+
+    fn send(&mut self, kind: u8, sequence: u8, data: u8) {
+        if self.connected && self.stream.write_all(&[kind, sequence, data]).is_err() {
+            warn!("Link partner disconnected");
+            self.connected = false;
+        }
+    }
+
+    fn disconnected(&mut self) {
+        if self.connected {
+            warn!("Link partner disconnected");
+            self.connected = false;
+        }
+    }
+
+    /// Handles every message received so far without blocking.
+    fn poll(&mut self) {
+        loop {
+            match self.messages.try_recv() {
+                Ok(Message::Transfer(sequence, value)) => {
+                    self.transfers.push_back((sequence, value))
+                }
+                Ok(Message::Cancel(sequence)) => {
+                    self.transfers.retain(|&(queued, _)| queued != sequence)
+                }
+                Ok(Message::Reply(sequence, value)) if self.pending == Some(sequence) => {
+                    self.reply = Some(value)
+                }
+                Ok(Message::Reply(sequence, _)) => {
+                    debug!("Dropping reply to cancelled transfer {}", sequence)
+                }
+                Err(TryRecvError::Empty) => break,
+                Err(TryRecvError::Disconnected) => {
+                    self.disconnected();
+                    break;
+                }
+            }
+        }
+    }
+
+    fn start_byte(&mut self, value: u8) {
+        self.incoming = value;
+        self.bits_left = 8;
+    }
+
+    fn next_bit(&mut self) -> u8 {
+        let bit = self.incoming >> 7;
+        self.incoming <<= 1;
+        self.bits_left -= 1;
+        bit
+    }
+}
+
+impl LinkPartner for TcpLink {
+    /// Sends SB when a transfer starts and holds the clock until the reply comes in.
+    fn ready(&mut self, sb: u8) -> bool {
+        if self.bits_left > 0 {
+            return true;
+        }
+
+        let sequence = match self.pending {
+            Some(sequence) => sequence,
+            None => {
+                self.sequence = self.sequence.wrapping_add(1);
+                self.send(MSG_TRANSFER, self.sequence, sb);
+                self.pending = Some(self.sequence);
+                self.waited = 0;
+                self.sequence
+            }
+        };
+
+        self.poll();
+
+        // Both sides on the internal clock, each one gets the other's byte
+        while let Some((other, _)) = self.transfers.pop_front() {
+            self.send(MSG_REPLY, other, sb);
+        }
+
+        let value = match self.reply.take() {
+            Some(value) => value,
+            None if !self.connected => 0xFF,
+            None if self.waited >= self.timeout => {
+                debug!("Link transfer {} timed out", sequence);
+                self.send(MSG_CANCEL, sequence, 0);
+                0xFF
+            }
+            None => {
+                self.waited += 1;
+                return false;
+            }
+        };
+
+        self.pending = None;
+        self.start_byte(value);
+        true
+    }
+
+    fn exchange_bit(&mut self, sb: u8) -> u8 {
+        if self.bits_left == 0 {
+            // Only reached by callers that don't poll `ready`
+            self.start_byte(0xFF);
+            debug!("Link clocked without a reply, SB {:#04X}", sb);
+        }
+
+        self.next_bit()
+    }
+
+    fn external_clock(&mut self, sb: u8) -> Option<u8> {
+        if self.bits_left == 0 {
+            self.poll();
+
+            let (sequence, value) = self.transfers.pop_front()?;
+            self.send(MSG_REPLY, sequence, sb);
+            self.start_byte(value);
+        }
+
+        Some(self.next_bit())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::memory::InterruptController;
+    use crate::serial::Serial;
+
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    fn run_transfer(link: TcpLink, data: u8, control: u8) -> u8 {
+        let mut serial = Serial::new();
+        let mut ic = InterruptController::new();
+        serial.connect(Box::new(link));
+        serial.write_data(data);
+        serial.write_control(control);
+
+        while !ic.interrupt_flag.serial() {
+            serial.tick(&mut ic);
+        }
+
+        serial.read_data()
+    }
+
+    #[test]
+    fn test_localhost_transfer() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        let host = std::thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let link = TcpLink::from_stream(stream).unwrap();
+            // External clock, waits for the other side
+            run_transfer(link, 0x99, 0x80)
+        });
+
+        let mut link = TcpLink::join(addr).unwrap();
+        link.set_timeout(Duration::from_secs(10));
+        // Internal clock, drives the transfer
+        assert_eq!(run_transfer(link, 0x42, 0x81), 0x99);
+        assert_eq!(host.join().unwrap(), 0x42);
+    }
+
+    #[test]
+    fn test_timeout_and_cancel() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let (armed_tx, armed_rx) = channel();
+
+        let host = std::thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let link = TcpLink::from_stream(stream).unwrap();
+
+            // Arms late, the cancelled transfer must not be answered
+            armed_rx.recv().unwrap();
+            std::thread::sleep(Duration::from_millis(200));
+            run_transfer(link, 0x99, 0x80)
+        });
+
+        let link = TcpLink::join(addr).unwrap();
+        let link = Rc::new(RefCell::new(link));
+
+        // Nobody answers, the transfer completes after 1 ms, about 1048 M-cycles, of
+        // emulated time
+        link.borrow_mut().set_timeout(Duration::from_micros(1000));
+        let mut serial = Serial::new();
+        let mut ic = InterruptController::new();
+        serial.connect(Box::new(link.clone()));
+        serial.write_data(0x42);
+        serial.write_control(0x81);
+        let mut cycles = 0;
+        while !ic.interrupt_flag.serial() {
+            serial.tick(&mut ic);
+            cycles += 1;
+        }
+        assert_eq!(serial.read_data(), 0xFF);
+        assert!(cycles < 8 * 128 + 2000);
+
+        armed_tx.send(()).unwrap();
+        link.borrow_mut().set_timeout(Duration::from_secs(10));
+        serial.write_data(0x43);
+        serial.write_control(0x81);
+        ic.interrupt_flag.set_serial(false);
+        while !ic.interrupt_flag.serial() {
+            serial.tick(&mut ic);
+        }
+        assert_eq!(serial.read_data(), 0x99);
+        assert_eq!(host.join().unwrap(), 0x43);
+    }
+}
@@ -53,7 +53,10 @@ impl RegisterTrait for BootRom {
                 "Writing to Boot ROM at address: {:#04X}, value: {:#04X}",
                 address, value
             );
-            self.enabled = value == 0;
+            // Once unmapped the boot ROM stays unmapped until reset
+            if value & 0x01 != 0 {
+                self.enabled = false;
+            }
 
             debug!("Boot ROM enabled: {}", self.enabled);
         }
@@ -1,5 +1,9 @@
 use super::RegisterTrait;
 
+/// OAM DMA, copies 160 bytes from `source << 8` to OAM, one byte per M-cycle.
+///
+/// While running it owns the bus its source sits on (the external bus or the VRAM
+/// bus) and OAM, the CPU only gets the byte being transferred when reading there.
 #[derive(Debug)]
 pub struct DMA {
     source: u8,
@@ -7,6 +11,8 @@ pub struct DMA {
     enabled: bool,
     starting: bool,
     starting_sync: bool,
+    // Last byte read by the DMA, seen by the CPU on a bus conflict
+    bus_data: u8,
 }
 
 impl DMA {
@@ -17,6 +23,7 @@ impl DMA {
             enabled: false,
             starting: false,
             starting_sync: false,
+            bus_data: 0xFF,
         }
     }
 
@@ -24,6 +31,8 @@ impl DMA {
     pub fn tick(&mut self) -> Option<u16> {
         let mut ret = None;
 
+        // A restart only takes over once the new transfer starts, until then
+        // the previous one keeps going
         if self.starting_sync {
             self.enabled = true;
             self.starting = false;
@@ -52,6 +61,35 @@ impl DMA {
     pub fn is_enabled(&self) -> bool {
         self.enabled
     }
+
+    /// Records the byte the DMA just read, it stays on the bus until the next one.
+    #[inline(always)]
+    pub fn set_bus_data(&mut self, value: u8) {
+        self.bus_data = value;
+    }
+
+    #[inline(always)]
+    fn on_vram_bus(&self) -> bool {
+        matches!(self.next_addr >> 8, 0x80..=0x9F)
+    }
+
+    /// Value seen by the CPU reading `addr` while the DMA runs, None when there is
+    /// no conflict and the read goes through.
+    #[inline(always)]
+    pub fn conflict(&self, addr: u16) -> Option<u8> {
+        if !self.enabled {
+            return None;
+        }
+
+        match addr {
+            // OAM is owned by the DMA
+            0xFE00..=0xFEFF => Some(0xFF),
+            0x8000..=0x9FFF if self.on_vram_bus() => Some(self.bus_data),
+            0x0000..=0x7FFF | 0xA000..=0xFDFF if !self.on_vram_bus() => Some(self.bus_data),
+            // IO registers, HRAM and IE are never blocked
+            _ => None,
+        }
+    }
 }
 
 impl RegisterTrait for DMA {
@@ -61,7 +99,108 @@ impl RegisterTrait for DMA {
 
     fn write(&mut self, _: u16, value: u8) {
         self.source = value;
-        self.next_addr = 0;
         self.starting = true;
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::memory::{BootRom, MMU};
+
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    fn mmu() -> Rc<RefCell<MMU>> {
+        let mut boot_rom = BootRom::new();
+        boot_rom.enabled = false;
+
+        let mmu = MMU::new(None, boot_rom);
+        for (i, byte) in mmu.borrow_mut().wram.iter_mut().enumerate() {
+            *byte = i as u8 ^ (i >> 8) as u8;
+        }
+        mmu
+    }
+
+    fn start(mmu: &Rc<RefCell<MMU>>, source: u8) {
+        mmu.borrow_mut().write(0xFF46, source);
+        // The transfer starts after one M-cycle of setup
+        mmu.borrow_mut().tick();
+    }
+
+    #[test]
+    fn test_bus_conflicts() {
+        let mmu = mmu();
+        mmu.borrow_mut().write(0x8000, 0x42);
+        mmu.borrow_mut().write(0xFF80, 0x24);
+
+        start(&mmu, 0xC1);
+        for _ in 0..4 {
+            mmu.borrow_mut().tick();
+        }
+
+        let mmu = mmu.borrow();
+        // Fourth byte of the transfer
+        let on_bus = mmu.wram[0x103];
+        assert_eq!(mmu.read(0xC000), on_bus);
+        assert_eq!(mmu.read(0x0150), on_bus);
+        assert_eq!(mmu.read(0xFE00), 0xFF);
+        // The VRAM bus, HRAM and IO are free
+        assert_eq!(mmu.read(0x8000), 0x42);
+        assert_eq!(mmu.read(0xFF80), 0x24);
+        assert_eq!(mmu.read(0xFF46), 0xC1);
+    }
+
+    #[test]
+    fn test_transfer() {
+        let mmu = mmu();
+
+        start(&mmu, 0xC1);
+        for _ in 0..160 {
+            mmu.borrow_mut().tick();
+        }
+        assert_eq!(mmu.borrow().read(0xC000), mmu.borrow().wram[0x19F]);
+
+        mmu.borrow_mut().tick();
+        let mmu = mmu.borrow();
+        assert_eq!(mmu.read(0xC000), mmu.wram[0]);
+        assert_eq!(&mmu.ppu.oam[..], &mmu.wram[0x100..0x1A0]);
+    }
+
+    #[test]
+    fn test_echo_source() {
+        let mmu = mmu();
+
+        // 0xFE00 mirrors 0xDE00
+        start(&mmu, 0xFE);
+        for _ in 0..161 {
+            mmu.borrow_mut().tick();
+        }
+
+        let mmu = mmu.borrow();
+        assert_eq!(&mmu.ppu.oam[..], &mmu.wram[0x1E00..0x1EA0]);
+    }
+
+    #[test]
+    fn test_restart() {
+        let mmu = mmu();
+
+        start(&mmu, 0xC0);
+        for _ in 0..10 {
+            mmu.borrow_mut().tick();
+        }
+
+        // The first transfer keeps going until the new one starts
+        mmu.borrow_mut().write(0xFF46, 0xD0);
+        mmu.borrow_mut().tick();
+        assert_eq!(mmu.borrow().ppu.oam[10], mmu.borrow().wram[10]);
+        assert_eq!(mmu.borrow().read(0xFE00), 0xFF);
+
+        for _ in 0..161 {
+            mmu.borrow_mut().tick();
+        }
+
+        let mmu = mmu.borrow();
+        assert_eq!(&mmu.ppu.oam[..], &mmu.wram[0x1000..0x10A0]);
+        assert_eq!(mmu.read(0xC000), mmu.wram[0]);
+    }
+}
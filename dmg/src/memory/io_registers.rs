@@ -81,3 +81,223 @@ pub struct IORegisters {
     pub timer: u8,
     pub dma: u8,
 }
+
+/// Hardware model, decides which IO registers exist.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Model {
+    DMG,
+    CGB,
+}
+
+/// Bits implemented by an IO register. Bits outside `read_mask` read back as 1,
+/// bits outside `write_mask` are left untouched by writes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct IORegister {
+    pub address: u16,
+    pub name: &'static str,
+    pub read_mask: u8,
+    pub write_mask: u8,
+}
+
+const fn reg(address: u16, name: &'static str, read_mask: u8, write_mask: u8) -> IORegister {
+    IORegister {
+        address,
+        name,
+        read_mask,
+        write_mask,
+    }
+}
+
+/// Registers present on every model
+pub const DMG_IO_REGISTERS: &[IORegister] = &[
+    reg(P1_JOYP, "P1", 0x3F, 0x30),
+    reg(SB, "SB", 0xFF, 0xFF),
+    reg(SC, "SC", 0x81, 0x81),
+    reg(DIV, "DIV", 0xFF, 0xFF),
+    reg(TIMA, "TIMA", 0xFF, 0xFF),
+    reg(TMA, "TMA", 0xFF, 0xFF),
+    reg(TAC, "TAC", 0x07, 0x07),
+    reg(IF, "IF", 0x1F, 0x1F),
+    reg(NR10, "NR10", 0x7F, 0x7F),
+    reg(NR11, "NR11", 0xC0, 0xFF),
+    reg(NR12, "NR12", 0xFF, 0xFF),
+    reg(NR13, "NR13", 0x00, 0xFF),
+    reg(NR14, "NR14", 0x40, 0xC7),
+    reg(NR21, "NR21", 0xC0, 0xFF),
+    reg(NR22, "NR22", 0xFF, 0xFF),
+    reg(NR23, "NR23", 0x00, 0xFF),
+    reg(NR24, "NR24", 0x40, 0xC7),
+    reg(NR30, "NR30", 0x80, 0x80),
+    reg(NR31, "NR31", 0x00, 0xFF),
+    reg(NR32, "NR32", 0x60, 0x60),
+    reg(NR33, "NR33", 0x00, 0xFF),
+    reg(NR34, "NR34", 0x40, 0xC7),
+    reg(NR41, "NR41", 0x00, 0x3F),
+    reg(NR42, "NR42", 0xFF, 0xFF),
+    reg(NR43, "NR43", 0xFF, 0xFF),
+    reg(NR44, "NR44", 0x40, 0xC0),
+    reg(NR50, "NR50", 0xFF, 0xFF),
+    reg(NR51, "NR51", 0xFF, 0xFF),
+    reg(NR52, "NR52", 0x8F, 0x80),
+    reg(WAVE_RAM_0, "WAVE0", 0xFF, 0xFF),
+    reg(WAVE_RAM_1, "WAVE1", 0xFF, 0xFF),
+    reg(WAVE_RAM_2, "WAVE2", 0xFF, 0xFF),
+    reg(WAVE_RAM_3, "WAVE3", 0xFF, 0xFF),
+    reg(WAVE_RAM_4, "WAVE4", 0xFF, 0xFF),
+    reg(WAVE_RAM_5, "WAVE5", 0xFF, 0xFF),
+    reg(WAVE_RAM_6, "WAVE6", 0xFF, 0xFF),
+    reg(WAVE_RAM_7, "WAVE7", 0xFF, 0xFF),
+    reg(WAVE_RAM_8, "WAVE8", 0xFF, 0xFF),
+    reg(WAVE_RAM_9, "WAVE9", 0xFF, 0xFF),
+    reg(WAVE_RAM_A, "WAVEA", 0xFF, 0xFF),
+    reg(WAVE_RAM_B, "WAVEB", 0xFF, 0xFF),
+    reg(WAVE_RAM_C, "WAVEC", 0xFF, 0xFF),
+    reg(WAVE_RAM_D, "WAVED", 0xFF, 0xFF),
+    reg(WAVE_RAM_E, "WAVEE", 0xFF, 0xFF),
+    reg(WAVE_RAM_F, "WAVEF", 0xFF, 0xFF),
+    reg(LCDC, "LCDC", 0xFF, 0xFF),
+    reg(STAT, "STAT", 0x7F, 0x78),
+    reg(SCY, "SCY", 0xFF, 0xFF),
+    reg(SCX, "SCX", 0xFF, 0xFF),
+    reg(LY, "LY", 0xFF, 0x00),
+    reg(LYC, "LYC", 0xFF, 0xFF),
+    reg(DMA, "DMA", 0xFF, 0xFF),
+    reg(BGP, "BGP", 0xFF, 0xFF),
+    reg(OBP0, "OBP0", 0xFF, 0xFF),
+    reg(OBP1, "OBP1", 0xFF, 0xFF),
+    reg(WY, "WY", 0xFF, 0xFF),
+    reg(WX, "WX", 0xFF, 0xFF),
+    reg(R_BANK, "BANK", 0x00, 0x01),
+];
+
+/// Registers only present on the CGB, or with more bits than on the DMG
+pub const CGB_IO_REGISTERS: &[IORegister] = &[
+    reg(SC, "SC", 0x83, 0x83),
+    reg(KEY1_SPD, "KEY1", 0x81, 0x01),
+    reg(VBK, "VBK", 0x01, 0x01),
+    reg(HDMA1, "HDMA1", 0x00, 0xFF),
+    reg(HDMA2, "HDMA2", 0x00, 0xF0),
+    reg(HDMA3, "HDMA3", 0x00, 0x1F),
+    reg(HDMA4, "HDMA4", 0x00, 0xF0),
+    reg(HDMA5, "HDMA5", 0xFF, 0xFF),
+    reg(RP, "RP", 0xC3, 0xC1),
+    reg(BCPS_BGPI, "BCPS", 0xBF, 0xBF),
+    reg(BCPD_BGPD, "BCPD", 0xFF, 0xFF),
+    reg(OCPS_OBPI, "OCPS", 0xBF, 0xBF),
+    reg(OCPD_OBPD, "OCPD", 0xFF, 0xFF),
+    reg(OPRI, "OPRI", 0x01, 0x01),
+    reg(SVBK_WBK, "SVBK", 0x07, 0x07),
+    reg(0xFF72, "FF72", 0xFF, 0xFF),
+    reg(0xFF73, "FF73", 0xFF, 0xFF),
+    reg(0xFF75, "FF75", 0x70, 0x70),
+    reg(PCM12, "PCM12", 0xFF, 0x00),
+    reg(PCM34, "PCM34", 0xFF, 0x00),
+];
+
+/// Lookup table for 0xFF00-0xFF7F, unmapped addresses read 0xFF and ignore writes.
+#[derive(Debug)]
+pub struct IOMap {
+    registers: [Option<IORegister>; 0x80],
+}
+
+impl IOMap {
+    pub fn new(model: Model) -> Self {
+        let mut registers = [None; 0x80];
+
+        let tables: &[&[IORegister]] = match model {
+            Model::DMG => &[DMG_IO_REGISTERS],
+            Model::CGB => &[DMG_IO_REGISTERS, CGB_IO_REGISTERS],
+        };
+
+        for register in tables.iter().flat_map(|table| table.iter()) {
+            registers[(register.address - 0xFF00) as usize] = Some(*register);
+        }
+
+        IOMap { registers }
+    }
+
+    #[inline(always)]
+    pub fn get(&self, address: u16) -> Option<&IORegister> {
+        self.registers[(address - 0xFF00) as usize].as_ref()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::memory::{BootRom, MMU};
+
+    #[test]
+    fn test_unused_bits() {
+        let mmu = MMU::new(None, BootRom::new());
+        let mut mmu = mmu.borrow_mut();
+
+        for addr in 0xFF00..=0xFF7F {
+            if addr != P1_JOYP && addr != DMA {
+                mmu.write(addr, 0x00);
+            }
+        }
+
+        assert_eq!(mmu.read(P1_JOYP) & 0xC0, 0xC0);
+        assert_eq!(mmu.read(SC), 0x7E);
+        assert_eq!(mmu.read(TAC), 0xF8);
+        assert_eq!(mmu.read(IF), 0xE0);
+        assert_eq!(mmu.read(STAT) & 0x80, 0x80);
+        assert_eq!(mmu.read(NR10), 0x80);
+        assert_eq!(mmu.read(NR13), 0xFF);
+        assert_eq!(mmu.read(NR30), 0x7F);
+        assert_eq!(mmu.read(NR52), 0x70);
+        assert_eq!(mmu.read(WAVE_RAM_0), 0x00);
+        assert_eq!(mmu.read(R_BANK), 0xFF);
+
+        // Unmapped and CGB-only registers
+        for addr in [0xFF03, 0xFF15, 0xFF27, KEY1_SPD, VBK, SVBK_WBK, 0xFF7F] {
+            assert_eq!(mmu.read(addr), 0xFF, "{:#06X}", addr);
+        }
+
+        mmu.write(NR52, 0xFF);
+        assert_eq!(mmu.read(NR52), 0xF0);
+    }
+
+    #[test]
+    fn test_boot_rom_unmap_is_sticky() {
+        let mut rom = vec![0; 0x8000];
+        rom[0x0000] = 0xC3;
+
+        let mut boot_rom = BootRom::new();
+        boot_rom.rom[0x0000] = 0x31;
+
+        let mmu = MMU::new(Some(mbc::MBC::from(mbc::NoMBC::new(rom))), boot_rom);
+        let mut mmu = mmu.borrow_mut();
+
+        mmu.write(R_BANK, 0x00);
+        assert_eq!(mmu.read(0x0000), 0x31);
+
+        mmu.write(R_BANK, 0x01);
+        assert_eq!(mmu.read(0x0000), 0xC3);
+
+        mmu.write(R_BANK, 0x02);
+        assert_eq!(mmu.read(0x0000), 0xC3);
+        mmu.write(R_BANK, 0x00);
+        assert_eq!(mmu.read(0x0000), 0xC3);
+    }
+
+    #[test]
+    fn test_cgb_registers() {
+        let mmu = MMU::new(None, BootRom::new());
+        let mut mmu = mmu.borrow_mut();
+        mmu.set_model(Model::CGB);
+
+        mmu.write(SVBK_WBK, 0xFF);
+        assert_eq!(mmu.read(SVBK_WBK), 0xFF);
+        mmu.write(SVBK_WBK, 0x02);
+        assert_eq!(mmu.read(SVBK_WBK), 0xFA);
+
+        mmu.write(KEY1_SPD, 0x00);
+        assert_eq!(mmu.read(KEY1_SPD), 0x7E);
+        mmu.write(0xFF75, 0xFF);
+        assert_eq!(mmu.read(0xFF75), 0xFF);
+        mmu.write(0xFF75, 0x00);
+        assert_eq!(mmu.read(0xFF75), 0x8F);
+    }
+}
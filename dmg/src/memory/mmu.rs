@@ -3,9 +3,10 @@ use super::*;
 use crate::joypad::Joypad;
 use crate::ppu::PPU;
 use crate::serial::Serial;
+use crate::sgb::SGB;
 use crate::timer::Timer;
 
-use log::warn;
+use log::{debug, warn};
 use std::{cell::RefCell, rc::Rc};
 
 use mbc::MBC;
@@ -23,6 +24,14 @@ pub struct MMU {
     pub timer: Timer,
     pub joypad: Joypad,
     pub serial: Serial,
+    pub sgb: Option<SGB>,
+
+    model: Model,
+    io_map: IOMap,
+    // Backing store for IO registers without a device behind them (sound, CGB)
+    io: [u8; 0x80],
+    // KEY1: bit 7 current speed, bit 0 switch armed
+    key1: u8,
 }
 
 impl MMU {
@@ -43,6 +52,11 @@ impl MMU {
             timer: Timer::new(),
             joypad: joypad,
             serial: Serial::new(),
+            sgb: None,
+            model: Model::DMG,
+            io_map: IOMap::new(Model::DMG),
+            io: [0; 0x80],
+            key1: 0,
         }));
 
         mmu.clone()
@@ -52,22 +66,61 @@ impl MMU {
         self.timer.tick(&mut self.ic.borrow_mut());
         self.serial.tick(&mut self.ic.borrow_mut());
 
+        // The cartridge runs off its own clock, unaffected by double speed
+        let cycles = if self.is_double_speed() { 2 } else { 4 };
+        self.cartridge.tick(cycles);
+
         let res = self.dma.tick();
         if let Some(src) = res {
             let dst_idx = src as u8;
             let src_data = self.read_dma(src);
+            self.dma.set_bus_data(src_data);
             self.ppu.oam[dst_idx as usize] = src_data;
         }
     }
 
+    pub fn model(&self) -> Model {
+        self.model
+    }
+
+    /// Selects the hardware model, which changes the set of IO registers.
+    pub fn set_model(&mut self, model: Model) {
+        self.model = model;
+        self.io_map = IOMap::new(model);
+    }
+
+    pub fn is_double_speed(&self) -> bool {
+        self.key1 & 0x80 != 0
+    }
+
+    /// Whether a CGB speed switch was armed through KEY1, to happen on the next STOP
+    pub fn speed_switch_armed(&self) -> bool {
+        self.model == Model::CGB && self.key1 & 0x01 != 0
+    }
+
+    /// Performs the CGB speed switch if it was armed through KEY1, returns whether it happened.
+    pub fn switch_speed(&mut self) -> bool {
+        if !self.speed_switch_armed() {
+            return false;
+        }
+
+        self.key1 = (self.key1 ^ 0x80) & 0x80;
+        log::info!(
+            "Switched to {} speed",
+            if self.is_double_speed() {
+                "double"
+            } else {
+                "normal"
+            }
+        );
+        true
+    }
+
     #[inline(always)]
     pub fn read(&self, addr: u16) -> u8 {
-        if self.dma.is_enabled() && addr <= 0xFF00 {
-            log::error!(
-                "Attempt to read from OAM during DMA transfer at address {:#04X}",
-                addr
-            );
-            return 0xFF; // Return 0xFF to avoid reading from OAM during DMA transfer
+        if let Some(value) = self.dma.conflict(addr) {
+            log::debug!("Bus conflict with DMA reading {:#06X}", addr);
+            return value;
         }
 
         match addr {
@@ -94,21 +147,7 @@ impl MMU {
             // Unusable memory
             0xFEA0..=0xFEFF => return 0,
             // IO Registers
-            0xFF00..=0xFF7F => match addr {
-                P1_JOYP => self.joypad.read(),
-                SB => self.serial.read_data(),
-                SC => self.serial.read_control(),
-                IF => self.ic.borrow().interrupt_flag.0,
-                DIV..=TAC => self.timer.read(addr),
-                LCDC..=LYC => self.ppu.read(addr),
-                DMA => self.dma.read(DMA),
-                BGP..=WX => self.ppu.read(addr),
-                R_BANK => self.boot_rom.read(R_BANK),
-                _ => {
-                    warn!("Read from IO Register {:#04X} is not implemented", addr);
-                    0xFF
-                }
-            },
+            0xFF00..=0xFF7F => self.read_io(addr),
             // HRAM
             0xFF80..=0xFFFE => self.hram[(addr - 0xFF80) as usize],
             // Interrupt Enable Register
@@ -135,10 +174,8 @@ impl MMU {
             0xA000..=0xBFFF => self.cartridge.read_ram(addr),
             // Work RAM
             0xC000..=0xDFFF => self.wram[(addr & 0x1FFF) as usize],
-            // Echo RAM - Copy of Work RAM
-            0xE000..=0xFDFF => self.wram[(addr & 0x1FFF) as usize],
-            // OAM
-            0xFE00..=0xFFFF => self.wram[(addr & 0x1FFF) as usize],
+            // Sources past 0xE000 all mirror Work RAM, OAM and IO aren't reachable
+            0xE000..=0xFFFF => self.wram[(addr & 0x1FFF) as usize],
         }
     }
 
@@ -165,33 +202,82 @@ impl MMU {
             0xC000..=0xDFFF => self.wram[(addr & 0x1FFF) as usize] = value,
             // Echo RAM - Copy of Work RAM
             0xE000..=0xFDFF => self.wram[(addr & 0x1FFF) as usize] = value,
-            // OAM
+            // OAM, owned by the DMA while it runs
+            0xFE00..=0xFE9F if self.dma.is_enabled() => (),
             0xFE00..=0xFE9F => self.ppu.write(addr, value),
             // Unusable memory
             0xFEA0..=0xFEFF => (),
             // IO Registers
-            0xFF00..=0xFF7F => match addr {
-                P1_JOYP => self.joypad.write(value),
-                SB => self.serial.write_data(value),
-                SC => self.serial.write_control(value),
-                IF => self.ic.borrow_mut().interrupt_flag.0 = 0b1110_0000 | value,
-                DIV..=TAC => self.timer.write(addr, value),
-                DMA => {
-                    self.dma.write(DMA, value);
-                }
-                R_BANK => self.boot_rom.write(R_BANK, value),
-                LCDC..=LYC => self.ppu.write(addr, value),
-                BGP..=WX => self.ppu.write(addr, value),
-
-                _ => warn!(
-                    "Write to IO Register {:#04X} with value {:#04X} is not implemented",
-                    addr, value,
-                ),
-            },
+            0xFF00..=0xFF7F => self.write_io(addr, value),
             // HRAM
             0xFF80..=0xFFFE => self.hram[(addr - 0xFF80) as usize] = value,
             // Interrupt Enable Register
             IE => self.ic.borrow_mut().interrupt_enable.0 = value,
         }
     }
+
+    /// Reads an IO register through the model's register map, unused bits read as 1.
+    fn read_io(&self, addr: u16) -> u8 {
+        let Some(register) = self.io_map.get(addr) else {
+            return 0xFF;
+        };
+
+        let value = match addr {
+            P1_JOYP => self.joypad.read(),
+            SB => self.serial.read_data(),
+            SC => self.serial.read_control(),
+            IF => self.ic.borrow().interrupt_flag.0,
+            DIV..=TAC => self.timer.read(addr),
+            LCDC..=LYC => self.ppu.read(addr),
+            DMA => self.dma.read(DMA),
+            BGP..=WX => self.ppu.read(addr),
+            R_BANK => self.boot_rom.read(R_BANK),
+            KEY1_SPD => self.key1,
+            _ => self.io[(addr - 0xFF00) as usize],
+        };
+
+        value | !register.read_mask
+    }
+
+    /// Writes an IO register through the model's register map, read-only bits are kept.
+    fn write_io(&mut self, addr: u16, value: u8) {
+        let Some(register) = self.io_map.get(addr) else {
+            debug!(
+                "Write to unmapped IO Register {:#04X} with value {:#04X}",
+                addr, value
+            );
+            return;
+        };
+
+        if register.write_mask == 0 {
+            debug!("Write to read-only IO Register {}", register.name);
+            return;
+        }
+
+        let value = value & register.write_mask;
+
+        match addr {
+            P1_JOYP => {
+                self.joypad.write(value);
+                if let Some(sgb) = self.sgb.as_mut() {
+                    sgb.write_p1(value, &mut self.joypad, &self.ppu);
+                }
+            }
+            SB => self.serial.write_data(value),
+            SC => self.serial.write_control(value),
+            IF => self.ic.borrow_mut().interrupt_flag.0 = 0b1110_0000 | value,
+            DIV..=TAC => self.timer.write(addr, value),
+            DMA => {
+                self.dma.write(DMA, value);
+            }
+            R_BANK => self.boot_rom.write(R_BANK, value),
+            KEY1_SPD => self.key1 = (self.key1 & 0x80) | value,
+            LCDC..=LYC => self.ppu.write(addr, value),
+            BGP..=WX => self.ppu.write(addr, value),
+            _ => {
+                let stored = &mut self.io[(addr - 0xFF00) as usize];
+                *stored = (*stored & !register.write_mask) | value;
+            }
+        }
+    }
 }
@@ -89,6 +89,8 @@ pub struct PPU {
     mode: PPUMode,
     frame_counter: u32,
     line_counter: u16,
+    // Dots counted while the CPU is in STOP mode and the LCD is blanked
+    stop_counter: Option<u32>,
 
     pub scan_line: u8,
     pub frame_buffer: [Color32; 160 * 144],
@@ -9,6 +9,9 @@ pub const VRAM_END: u16 = 0x9FFF;
 pub const OAM_START: u16 = 0xFE00;
 pub const OAM_END: u16 = 0xFE9F;
 
+/// What the LCD shows while it is off or the CPU is stopped, lighter than color 0
+const BLANK_COLOR: Color32 = Color32::RGB(202, 220, 159);
+
 impl<T> OAMEntry<T> {
     pub fn get_y(&self) -> i32
     where
@@ -45,6 +48,7 @@ impl PPU {
             scan_line: 0,
             frame_counter: 0,
             line_counter: 0,
+            stop_counter: None,
             frame_buffer: [Color32::RGB(220, 220, 159); 160 * 144],
             obj_scanline: [OAMEntry([0, 0, 0, 0]); 10],
             frame_ready: false,
@@ -110,7 +114,7 @@ impl PPU {
                 warn!("The screen shouldn't turn off while not in VBLANK");
             }
 
-            self.frame_buffer.fill(Color32::RGB(202, 220, 159));
+            self.frame_buffer.fill(BLANK_COLOR);
             self.scan_line = 0;
         } else if !is_lcd_enabled && self.lcd_control.lcd_enable() {
             // Enabling LCD
@@ -120,6 +124,17 @@ impl PPU {
         }
     }
 
+    /// Blanks the LCD while the CPU is in STOP mode. The PPU state is frozen, but
+    /// blank frames keep being signalled so the host keeps polling input.
+    pub fn set_stopped(&mut self, stopped: bool) {
+        if stopped {
+            self.frame_buffer.fill(BLANK_COLOR);
+            self.stop_counter = Some(0);
+        } else {
+            self.stop_counter = None;
+        }
+    }
+
     #[inline]
     pub fn update_ppu_mode(&mut self, mode: PPUMode) {
         if self.mode != mode {
@@ -154,6 +169,15 @@ impl PPU {
     pub fn tick(&mut self) {
         // fake that the ppu does something
 
+        if let Some(counter) = self.stop_counter.as_mut() {
+            *counter += 1;
+            if *counter == 70224 {
+                *counter = 0;
+                self.frame_ready = true;
+            }
+            return;
+        }
+
         if self.lcd_control.lcd_enable() == false {
             return;
         }
@@ -427,6 +451,32 @@ impl PPU {
         }
     }
 
+    /// Returns the tile data of the first 256 tiles shown on screen, read through the
+    /// background map (20 tiles per row). This is what the SGB captures on a VRAM transfer.
+    pub(crate) fn screen_tile_data(&self) -> Vec<u8> {
+        let bg_map_base = self.get_map_base_address() as i32;
+        let bg_tile_base = self.get_tile_base_address() as i32;
+
+        let mut data = Vec::with_capacity(0x1000);
+
+        for i in 0..256 {
+            let (row, col) = (i / 20, i % 20);
+            let tile_index: u8 = self.vram_read((bg_map_base + row * 32 + col) as u16);
+
+            let tile_index = if bg_tile_base == 0x9000 {
+                tile_index as i8 as i32
+            } else {
+                tile_index as i32
+            };
+
+            for byte in 0..16 {
+                data.push(self.vram_read((bg_tile_base + tile_index * 16 + byte) as u16));
+            }
+        }
+
+        data
+    }
+
     #[inline]
     fn vram_read(&self, address: u16) -> u8 {
         self.vram[address as usize - VRAM_START as usize]
@@ -2,6 +2,9 @@ use crate::memory::interrupt_controller::InterruptController;
 
 use bitfield::bitfield;
 
+use std::cell::RefCell;
+use std::rc::Rc;
+
 bitfield! {
     #[derive(Clone, Copy)]
     struct SerialControl(u8);
@@ -12,12 +15,62 @@ bitfield! {
     clock_select, set_clock_select: 0;
 }
 
-#[derive(Debug)]
+/// The device on the other end of the link cable.
+pub trait LinkPartner {
+    /// Called on every clock edge driven by this Game Boy (internal clock).
+    /// `sb` is the shift register before the shift, the bit going out is its MSB.
+    /// Returns the bit shifted in.
+    fn exchange_bit(&mut self, sb: u8) -> u8;
+
+    /// Polled every M-cycle before a clock edge driven by this Game Boy. Returning
+    /// false holds the clock, for partners that need time to answer.
+    fn ready(&mut self, _sb: u8) -> bool {
+        true
+    }
+
+    /// Polled every M-cycle while this Game Boy is on the external clock.
+    /// Returns the incoming bit when the partner drives a clock edge.
+    fn external_clock(&mut self, _sb: u8) -> Option<u8> {
+        None
+    }
+
+    /// Called every M-cycle of a transfer on the internal clock that has no clock edge.
+    fn between_edges(&mut self, _sb: u8) {}
+
+    /// Called every M-cycle while no transfer is in progress.
+    fn idle(&mut self, _sb: u8) {}
+}
+
+/// Lets the host keep a handle on a device plugged into the serial port.
+impl<T: LinkPartner> LinkPartner for Rc<RefCell<T>> {
+    fn exchange_bit(&mut self, sb: u8) -> u8 {
+        self.borrow_mut().exchange_bit(sb)
+    }
+
+    fn ready(&mut self, sb: u8) -> bool {
+        self.borrow_mut().ready(sb)
+    }
+
+    fn external_clock(&mut self, sb: u8) -> Option<u8> {
+        self.borrow_mut().external_clock(sb)
+    }
+
+    fn between_edges(&mut self, sb: u8) {
+        self.borrow_mut().between_edges(sb)
+    }
+
+    fn idle(&mut self, sb: u8) {
+        self.borrow_mut().idle(sb)
+    }
+}
+
 pub struct Serial {
     data: u8,
     enabled: SerialControl,
     internal_divider: u8,
     transfer_count: u8,
+    output: Vec<u8>,
+    partner: Option<Box<dyn LinkPartner>>,
 }
 
 impl Serial {
@@ -27,31 +80,107 @@ impl Serial {
             enabled: SerialControl(0b1000_0001),
             internal_divider: 0,
             transfer_count: 0,
+            output: Vec::new(),
+            partner: None,
         }
     }
 
+    /// Plugs a device into the link port, replacing the previous one.
+    pub fn connect(&mut self, partner: Box<dyn LinkPartner>) {
+        self.partner = Some(partner);
+    }
+
+    pub fn disconnect(&mut self) -> Option<Box<dyn LinkPartner>> {
+        self.partner.take()
+    }
+
+    pub fn is_connected(&self) -> bool {
+        self.partner.is_some()
+    }
+
+    /// Bytes sent by the Game Boy since the last call.
+    pub fn take_output(&mut self) -> Vec<u8> {
+        std::mem::take(&mut self.output)
+    }
+
+    pub fn output(&self) -> &[u8] {
+        &self.output
+    }
+
+    /// A transfer is armed on the external clock, waiting for the partner to drive it.
+    pub fn waits_for_clock(&self) -> bool {
+        self.enabled.transfer_enable() && !self.enabled.clock_select()
+    }
+
     pub fn tick(&mut self, mmu: &mut InterruptController) {
-        if self.enabled.transfer_enable() {
+        if !self.enabled.transfer_enable() {
+            if let Some(partner) = self.partner.as_mut() {
+                partner.idle(self.data);
+            }
+            return;
+        }
+
+        let recv_bit = if self.enabled.clock_select() {
             self.internal_divider += 1;
             self.internal_divider %= 128; // Serial runs at 8192Hz == 1Kb/s
 
-            if self.internal_divider == 0 {
-                // TODO: Implement receiving bit logic
-                let recv_bit = 0;
-
-                if self.transfer_count == 0 {
-                    print!("h{:02x} ({})", self.data, self.data as char)
+            if self.internal_divider != 0 {
+                if let Some(partner) = self.partner.as_mut() {
+                    partner.between_edges(self.data);
                 }
+                return;
+            }
 
-                self.data = (self.data << 1) | (recv_bit & 0b1);
-                self.transfer_count += 1;
+            let data = self.data;
+            if self
+                .partner
+                .as_mut()
+                .is_some_and(|partner| !partner.ready(data))
+            {
+                // Try the edge again on the next M-cycle
+                self.internal_divider = 127;
+                return;
+            }
 
-                if self.transfer_count == 8 {
-                    self.transfer_count = 0;
-                    self.enabled.set_transfer_enable(false);
-                    mmu.interrupt_flag.set_serial(true);
-                }
+            if self.transfer_count == 0 {
+                self.output.push(self.data);
+            }
+
+            // With nothing connected the line is pulled high
+            match self.partner.as_mut() {
+                Some(partner) => partner.exchange_bit(self.data),
+                None => 1,
+            }
+        } else {
+            let Some(bit) = self
+                .partner
+                .as_mut()
+                .and_then(|partner| partner.external_clock(self.data))
+            else {
+                return;
+            };
+
+            if self.transfer_count == 0 {
+                self.output.push(self.data);
             }
+            bit
+        };
+
+        self.data = (self.data << 1) | (recv_bit & 0b1);
+        self.transfer_count += 1;
+
+        if self.transfer_count == 8 {
+            self.transfer_count = 0;
+            self.enabled.set_transfer_enable(false);
+            mmu.interrupt_flag.set_serial(true);
+        }
+    }
+
+    /// Called every M-cycle while the system clock is stopped, no clock edges are
+    /// driven but partners that count M-cycles are kept in step.
+    pub fn tick_stopped(&mut self) {
+        if let Some(partner) = self.partner.as_mut() {
+            partner.idle(self.data);
         }
     }
 
@@ -76,3 +205,66 @@ impl Serial {
         self.enabled.0
     }
 }
+
+impl std::fmt::Debug for Serial {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_struct("Serial")
+            .field("data", &self.data)
+            .field("enabled", &self.enabled)
+            .field("transfer_count", &self.transfer_count)
+            .field("output", &self.output.len())
+            .field("connected", &self.is_connected())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct Echo(u8);
+
+    impl LinkPartner for Echo {
+        fn exchange_bit(&mut self, _sb: u8) -> u8 {
+            let bit = self.0 >> 7;
+            self.0 <<= 1;
+            bit
+        }
+    }
+
+    fn transfer(serial: &mut Serial, ic: &mut InterruptController, data: u8) {
+        serial.write_data(data);
+        serial.write_control(0x81);
+        for _ in 0..8 * 128 {
+            serial.tick(ic);
+        }
+    }
+
+    #[test]
+    fn test_output() {
+        let mut serial = Serial::new();
+        let mut ic = InterruptController::new();
+
+        for &byte in b"Passed" {
+            transfer(&mut serial, &mut ic, byte);
+            assert!(ic.interrupt_flag.serial());
+            ic.interrupt_flag.set_serial(false);
+            // Nothing connected, the line reads high
+            assert_eq!(serial.read_data(), 0xFF);
+        }
+
+        assert_eq!(serial.take_output(), b"Passed");
+        assert!(serial.take_output().is_empty());
+    }
+
+    #[test]
+    fn test_partner() {
+        let mut serial = Serial::new();
+        let mut ic = InterruptController::new();
+        serial.connect(Box::new(Echo(0xA5)));
+
+        transfer(&mut serial, &mut ic, 0x42);
+        assert_eq!(serial.read_data(), 0xA5);
+        assert_eq!(serial.output(), &[0x42]);
+    }
+}
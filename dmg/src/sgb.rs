@@ -0,0 +1,289 @@
+use crate::joypad::Joypad;
+use crate::ppu::{Color32, PPU};
+
+use log::{debug, info};
+
+pub const SGB_WIDTH: usize = 256;
+pub const SGB_HEIGHT: usize = 224;
+
+// Position of the Game Boy screen inside the SGB output
+pub const SCREEN_X: usize = 48;
+pub const SCREEN_Y: usize = 40;
+
+const SCREEN_WIDTH: usize = 160;
+const SCREEN_HEIGHT: usize = 144;
+
+// Shown through transparent border pixels, matches the PPU's lightest shade
+const BACKDROP: Color32 = Color32::RGB(155, 188, 15);
+
+const PACKET_BITS: usize = 16 * 8;
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum SGBCommand {
+    MltReq = 0x11,
+    ChrTrn = 0x13,
+    PctTrn = 0x14,
+}
+
+impl SGBCommand {
+    fn from_u8(value: u8) -> Option<Self> {
+        match value {
+            0x11 => Some(SGBCommand::MltReq),
+            0x13 => Some(SGBCommand::ChrTrn),
+            0x14 => Some(SGBCommand::PctTrn),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct SGB {
+    // Packet reception over P1
+    packet: [u8; 16],
+    bit_index: usize,
+    receiving: bool,
+    last_p1: u8,
+    command_data: Vec<u8>,
+    remaining_packets: u8,
+
+    // Border state
+    border_tiles: Box<[u8; 256 * 32]>,
+    border_map: Box<[u16; 32 * 32]>,
+    border_palettes: [[Color32; 16]; 4],
+    pub border_enabled: bool,
+}
+
+impl Default for SGB {
+    fn default() -> SGB {
+        SGB::new()
+    }
+}
+
+impl SGB {
+    pub fn new() -> Self {
+        SGB {
+            packet: [0; 16],
+            bit_index: 0,
+            receiving: false,
+            last_p1: 0x30,
+            command_data: Vec::new(),
+            remaining_packets: 0,
+            border_tiles: Box::new([0; 256 * 32]),
+            border_map: Box::new([0; 32 * 32]),
+            border_palettes: [[BACKDROP; 16]; 4],
+            border_enabled: false,
+        }
+    }
+
+    /// Feeds a P1 write to the packet receiver.
+    ///
+    /// A packet starts with a reset pulse (P14 and P15 low), followed by 128 data bits,
+    /// LSB first, and a '0' stop bit. P14 low sends a '0', P15 low sends a '1', and every
+    /// pulse must be followed by both lines going high again.
+    pub fn write_p1(&mut self, value: u8, joypad: &mut Joypad, ppu: &PPU) {
+        let lines = value & 0x30;
+        let last = self.last_p1;
+        self.last_p1 = lines;
+
+        if lines == 0x00 {
+            self.receiving = true;
+            self.bit_index = 0;
+            self.packet = [0; 16];
+            return;
+        }
+
+        if !self.receiving || last != 0x30 || lines == 0x30 {
+            return;
+        }
+
+        let bit = lines == 0x10;
+
+        if self.bit_index == PACKET_BITS {
+            // Stop bit
+            self.receiving = false;
+            if !bit {
+                self.packet_received(joypad, ppu);
+            }
+            return;
+        }
+
+        if bit {
+            self.packet[self.bit_index / 8] |= 1 << (self.bit_index % 8);
+        }
+        self.bit_index += 1;
+    }
+
+    fn packet_received(&mut self, joypad: &mut Joypad, ppu: &PPU) {
+        if self.remaining_packets == 0 {
+            self.command_data.clear();
+            self.remaining_packets = (self.packet[0] & 0b111).max(1);
+        }
+
+        self.command_data.extend_from_slice(&self.packet);
+        self.remaining_packets -= 1;
+
+        if self.remaining_packets == 0 {
+            self.execute(joypad, ppu);
+        }
+    }
+
+    fn execute(&mut self, joypad: &mut Joypad, ppu: &PPU) {
+        let command = self.command_data[0] >> 3;
+
+        match SGBCommand::from_u8(command) {
+            Some(SGBCommand::MltReq) => {
+                let players = match self.command_data[1] & 0b11 {
+                    1 => 2,
+                    3 => 4,
+                    _ => 1,
+                };
+                info!("SGB MLT_REQ: {} players", players);
+                joypad.set_player_count(players);
+            }
+            Some(SGBCommand::ChrTrn) => {
+                let data = ppu.screen_tile_data();
+                let offset = (self.command_data[1] & 1) as usize * 128 * 32;
+                self.border_tiles[offset..offset + 128 * 32].copy_from_slice(&data[..128 * 32]);
+                debug!(
+                    "SGB CHR_TRN: tiles {:#04X}-{:#04X}",
+                    offset / 32,
+                    offset / 32 + 127
+                );
+            }
+            Some(SGBCommand::PctTrn) => {
+                let data = ppu.screen_tile_data();
+
+                for (i, entry) in self.border_map.iter_mut().enumerate() {
+                    *entry = u16::from_le_bytes([data[i * 2], data[i * 2 + 1]]);
+                }
+
+                for (i, palette) in self.border_palettes.iter_mut().enumerate() {
+                    for (j, color) in palette.iter_mut().enumerate() {
+                        let idx = 0x800 + (i * 16 + j) * 2;
+                        *color = bgr555_to_color(u16::from_le_bytes([data[idx], data[idx + 1]]));
+                    }
+                }
+
+                self.border_enabled = true;
+                debug!("SGB PCT_TRN: border loaded");
+            }
+            None => debug!("SGB command {:#04X} is not implemented", command),
+        }
+    }
+
+    /// Composes the 256x224 SGB picture, with the Game Boy `screen` in the middle
+    /// and the border drawn on top of it.
+    pub fn render(&self, screen: &[Color32], frame_buffer: &mut [Color32]) {
+        frame_buffer[..SGB_WIDTH * SGB_HEIGHT].fill(BACKDROP);
+
+        for y in 0..SCREEN_HEIGHT {
+            let dst = (SCREEN_Y + y) * SGB_WIDTH + SCREEN_X;
+            frame_buffer[dst..dst + SCREEN_WIDTH]
+                .copy_from_slice(&screen[y * SCREEN_WIDTH..(y + 1) * SCREEN_WIDTH]);
+        }
+
+        if !self.border_enabled {
+            return;
+        }
+
+        for map_y in 0..SGB_HEIGHT / 8 {
+            for map_x in 0..SGB_WIDTH / 8 {
+                let entry = self.border_map[map_y * 32 + map_x];
+
+                let tile = (entry & 0xFF) as usize;
+                let palette = &self.border_palettes[((entry >> 10) & 0b11) as usize];
+                let x_flip = entry & 0x4000 != 0;
+                let y_flip = entry & 0x8000 != 0;
+
+                for row in 0..8 {
+                    let tile_row = if y_flip { 7 - row } else { row };
+                    let base = tile * 32 + tile_row * 2;
+                    let planes = [
+                        self.border_tiles[base],
+                        self.border_tiles[base + 1],
+                        self.border_tiles[base + 16],
+                        self.border_tiles[base + 17],
+                    ];
+
+                    for col in 0..8 {
+                        let bit = if x_flip { col } else { 7 - col };
+                        let color_index = planes
+                            .iter()
+                            .enumerate()
+                            .fold(0, |acc, (p, plane)| acc | (((plane >> bit) & 1) << p));
+
+                        // Color 0 is transparent
+                        if color_index == 0 {
+                            continue;
+                        }
+
+                        let idx = (map_y * 8 + row) * SGB_WIDTH + map_x * 8 + col;
+                        frame_buffer[idx] = palette[color_index as usize];
+                    }
+                }
+            }
+        }
+    }
+}
+
+fn bgr555_to_color(value: u16) -> Color32 {
+    let expand = |c: u16| ((c << 3) | (c >> 2)) as u8;
+
+    Color32::RGB(
+        expand(value & 0x1F),
+        expand((value >> 5) & 0x1F),
+        expand((value >> 10) & 0x1F),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::memory::InterruptController;
+
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    fn send_packet(sgb: &mut SGB, joypad: &mut Joypad, ppu: &PPU, packet: &[u8; 16]) {
+        let mut write = |value: u8| {
+            joypad.write(value);
+            sgb.write_p1(value, joypad, ppu);
+        };
+
+        write(0x00);
+        write(0x30);
+        for i in 0..PACKET_BITS {
+            let bit = (packet[i / 8] >> (i % 8)) & 1;
+            write(if bit == 1 { 0x10 } else { 0x20 });
+            write(0x30);
+        }
+        write(0x20);
+        write(0x30);
+    }
+
+    #[test]
+    fn test_mlt_req() {
+        let ic = Rc::new(RefCell::new(InterruptController::new()));
+        let ppu = PPU::new(ic.clone());
+        let mut joypad = Joypad::new(ic);
+        let mut sgb = SGB::new();
+
+        let mut packet = [0; 16];
+        packet[0] = (SGBCommand::MltReq as u8) << 3 | 1;
+        packet[1] = 0x03;
+        send_packet(&mut sgb, &mut joypad, &ppu, &packet);
+
+        assert_eq!(joypad.player_count(), 4);
+
+        // Each P15 low -> high transition selects the next controller
+        for id in [0x0F, 0x0E, 0x0D, 0x0C, 0x0F] {
+            joypad.write(0x30);
+            assert_eq!(joypad.read() & 0x0F, id);
+            joypad.write(0x10);
+        }
+
+        packet[1] = 0x00;
+        send_packet(&mut sgb, &mut joypad, &ppu, &packet);
+        assert_eq!(joypad.player_count(), 1);
+    }
+}
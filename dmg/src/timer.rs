@@ -10,59 +10,95 @@ bitfield! {
     enable, set_enable: 2;
 }
 
+/// State of TIMA after it overflows.
+///
+/// TIMA reads 0x00 for one M-cycle before TMA gets loaded and the interrupt is
+/// requested, the reload itself then lasts another M-cycle.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+enum Reload {
+    None,
+    /// TIMA overflowed, TMA is loaded on the next M-cycle. Writing TIMA cancels it.
+    Pending,
+    /// TMA was just loaded. Writes to TIMA are ignored, writes to TMA go through to TIMA.
+    Reloading,
+}
+
 #[derive(Debug)]
 pub struct Timer {
-    pub div: u16,
-    pub tima: u16,
-    pub tma: u16,
+    /// 16-bit system counter, incremented every T-cycle. DIV is its upper byte.
+    pub counter: u16,
+    pub tima: u8,
+    pub tma: u8,
     pub tac: TimerControlRegister,
-    step: u16,
-    tima_overflow: bool,
+    reload: Reload,
 }
 
 impl Timer {
     pub fn new() -> Self {
         Timer {
-            div: 0,
+            counter: 0,
             tima: 0,
             tma: 0,
             tac: TimerControlRegister(0b1111_1000),
-            step: 1,
-            tima_overflow: false,
+            reload: Reload::None,
         }
     }
 
     pub fn tick(&mut self, ic: &mut InterruptController) {
-        self.div = self.div.wrapping_add(4);
-
-        if self.tac.enable() {
-            if self.tima_overflow {
+        match self.reload {
+            Reload::Pending => {
                 self.tima = self.tma;
                 ic.interrupt_flag.set_timer(true);
+                self.reload = Reload::Reloading;
             }
+            Reload::Reloading => self.reload = Reload::None,
+            Reload::None => {}
+        }
+
+        let before = self.timer_signal();
+        self.counter = self.counter.wrapping_add(4);
 
-            (self.tima, self.tima_overflow) = self.tima.overflowing_add(self.step);
+        if before && !self.timer_signal() {
+            self.increment_tima();
         }
     }
 
+    /// Bit of the system counter selected by TAC.
     #[inline(always)]
-    pub(crate) fn tima_divider_step(&self) -> u16 {
+    pub(crate) fn counter_bit(&self) -> u16 {
         match self.tac.clock_selection() & 0b11 {
-            0 => 256 / 256,
-            1 => 256 / 4,
-            2 => 256 / 16,
-            3 => 256 / 64,
+            0 => 9,
+            1 => 3,
+            2 => 5,
+            3 => 7,
             _ => unreachable!(),
         }
     }
+
+    /// Input of the falling-edge detector: the selected counter bit ANDed with the
+    /// enable bit. TIMA increments whenever it goes from high to low, which also
+    /// happens when DIV is reset or TAC is changed.
+    #[inline(always)]
+    fn timer_signal(&self) -> bool {
+        self.tac.enable() && (self.counter >> self.counter_bit()) & 1 != 0
+    }
+
+    fn increment_tima(&mut self) {
+        let (tima, overflow) = self.tima.overflowing_add(1);
+        self.tima = tima;
+
+        if overflow {
+            self.reload = Reload::Pending;
+        }
+    }
 }
 
 impl RegisterTrait for Timer {
     fn read(&self, address: u16) -> u8 {
         match address {
-            DIV => (self.div >> 8) as u8,
-            TIMA => (self.tima >> 8) as u8,
-            TMA => self.tma as u8,
+            DIV => (self.counter >> 8) as u8,
+            TIMA => self.tima,
+            TMA => self.tma,
             TAC => self.tac.0,
             _ => unreachable!(),
         }
@@ -71,16 +107,36 @@ impl RegisterTrait for Timer {
     fn write(&mut self, address: u16, value: u8) {
         match address {
             DIV => {
-                // Writing to DIV resets it
-                // There is a global shared, so lower part of TIMA is reset also
-                self.div = 0;
-                self.tima &= 0xFF00;
+                // Writing to DIV resets the whole system counter
+                let before = self.timer_signal();
+                self.counter = 0;
+
+                if before {
+                    self.increment_tima();
+                }
+            }
+            TIMA => match self.reload {
+                Reload::Reloading => {}
+                Reload::Pending => {
+                    self.tima = value;
+                    self.reload = Reload::None;
+                }
+                Reload::None => self.tima = value,
+            },
+            TMA => {
+                self.tma = value;
+
+                if self.reload == Reload::Reloading {
+                    self.tima = value;
+                }
             }
-            TIMA => self.tima = (value as u16) << 8,
-            TMA => self.tma = (value as u16) << 8,
             TAC => {
+                let before = self.timer_signal();
                 self.tac.0 = value | 0b1111_1000;
-                self.step = self.tima_divider_step();
+
+                if before && !self.timer_signal() {
+                    self.increment_tima();
+                }
             }
             _ => unreachable!(),
         }
@@ -110,40 +166,101 @@ mod tests {
         assert_eq!(timer.read(DIV), 0);
     }
 
-    // #[test]
-    // fn test_tma() {
-    //     let mut timer = Timer::new();
-    //     let mut ic = InterruptController::new();
-    //     timer.tac.set_enable(false);
-    //     timer.tac.set_clock_selection(0);
-
-    //     for _ in 0..1024 {
-    //         timer.tick(&mut ic);
-    //         assert_eq!(timer.tima, 0);
-    //     }
-
-    //     timer.write(TAC, 0b0000100);
-    //     for _ in 0..1024 {
-    //         timer.tick(&mut ic);
-    //     }
-    //     assert_eq!(timer.tima >> 8, 4);
-
-    //     timer.write(TAC, 0b0000101);
-
-    //     for _ in 0..16 {
-    //         timer.tick(&mut ic);
-    //     }
-    //     assert_eq!(timer.tima >> 8, 8);
-
-    //     timer.tma = 0xFF << 8;
-    //     for _ in 0..(248 * 4 + 4) {
-    //         timer.tick(&mut ic);
-    //     }
-    //     assert_eq!(timer.tima >> 8, 0xFF);
-    //     for _ in 0..(4) {
-    //         timer.tick(&mut ic);
-    //     }
-    //     assert_eq!(timer.tima >> 8, 0xFF);
-    //     assert_eq!(ic.interrupt_flag.timer(), true);
-    // }
+    #[test]
+    fn test_tma() {
+        let mut timer = Timer::new();
+        let mut ic = InterruptController::new();
+        timer.tac.set_enable(false);
+        timer.tac.set_clock_selection(0);
+
+        for _ in 0..1024 {
+            timer.tick(&mut ic);
+            assert_eq!(timer.tima, 0);
+        }
+
+        timer.write(TAC, 0b0000100);
+        for _ in 0..1024 {
+            timer.tick(&mut ic);
+        }
+        assert_eq!(timer.tima, 4);
+
+        timer.write(TAC, 0b0000101);
+
+        for _ in 0..16 {
+            timer.tick(&mut ic);
+        }
+        assert_eq!(timer.tima, 8);
+
+        timer.tma = 0xFF;
+        for _ in 0..(248 * 4) {
+            timer.tick(&mut ic);
+        }
+        // TIMA reads 0 for one cycle before the reload
+        assert_eq!(timer.tima, 0);
+        assert!(!ic.interrupt_flag.timer());
+
+        timer.tick(&mut ic);
+        assert_eq!(timer.tima, 0xFF);
+        assert!(ic.interrupt_flag.timer());
+    }
+
+    #[test]
+    fn test_falling_edge() {
+        let mut timer = Timer::new();
+        let mut ic = InterruptController::new();
+        timer.write(TAC, 0b0000101);
+
+        // Bit 3 is set after 2 M-cycles, resetting DIV makes it fall
+        timer.tick(&mut ic);
+        timer.tick(&mut ic);
+        timer.write(DIV, 0);
+        assert_eq!(timer.tima, 1);
+
+        // Same when disabling the timer or selecting a bit that is low
+        timer.tick(&mut ic);
+        timer.tick(&mut ic);
+        timer.write(TAC, 0b0000001);
+        assert_eq!(timer.tima, 2);
+
+        timer.write(TAC, 0b0000101);
+        timer.write(TAC, 0b0000100);
+        assert_eq!(timer.tima, 3);
+    }
+
+    #[test]
+    fn test_reload_writes() {
+        let mut timer = Timer::new();
+        let mut ic = InterruptController::new();
+        timer.write(TAC, 0b0000101);
+        timer.write(TMA, 0x42);
+
+        // Writing TIMA during the overflow cycle cancels the reload
+        timer.write(TIMA, 0xFF);
+        for _ in 0..4 {
+            timer.tick(&mut ic);
+        }
+        assert_eq!(timer.tima, 0);
+        timer.write(TIMA, 0x10);
+        timer.tick(&mut ic);
+        assert_eq!(timer.tima, 0x10);
+        assert!(!ic.interrupt_flag.timer());
+
+        // Writing TIMA during the reload cycle is ignored, TMA goes through
+        timer.write(TIMA, 0xFF);
+        while timer.tima != 0 {
+            timer.tick(&mut ic);
+        }
+        timer.tick(&mut ic);
+        assert_eq!(timer.tima, 0x42);
+        timer.write(TIMA, 0x10);
+        assert_eq!(timer.tima, 0x42);
+        timer.write(TMA, 0x24);
+        assert_eq!(timer.tima, 0x24);
+        assert!(ic.interrupt_flag.timer());
+
+        // Back to normal on the next cycle
+        timer.tick(&mut ic);
+        timer.write(TMA, 0x00);
+        assert_eq!(timer.tima, 0x24);
+    }
 }
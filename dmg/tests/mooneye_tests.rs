@@ -7,6 +7,9 @@ use std::rc::Rc;
 
 use rstest::*;
 
+/// Emulated time after which a test that hasn't finished counts as hung
+const MAX_T_CYCLES: usize = 4_194_304 * 120;
+
 #[rstest]
 fn mooneye_test(
     #[files("./tests/mooneye-test-suite/**/**/*.gb")]
@@ -41,6 +44,15 @@ fn test_mooneye_rom(rom: &[u8]) {
     loop {
         cpu.do_step();
 
+        if let Some(fault) = cpu.fault.take() {
+            panic!("CPU fault at PC {:04X}: {:?}", cpu.pc, fault);
+        }
+        assert!(
+            cpu.t_cycles < MAX_T_CYCLES,
+            "Test didn't finish within {} cycles",
+            MAX_T_CYCLES
+        );
+
         let pc = cpu.pc;
         let mmu = mmu.borrow();
 
@@ -2,29 +2,129 @@ use std::mem::MaybeUninit;
 use std::sync::Arc;
 use std::sync::Condvar;
 use std::sync::Mutex;
-use std::sync::mpsc::Sender;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{Receiver, Sender};
+
+use dmg::cpu::CpuFault;
+use dmg::joypad::MAX_PLAYERS;
+use dmg::link::{DEFAULT_PORT, TcpLink};
 
 use egui::ColorImage;
 use egui::Key;
 use egui::TextureOptions;
 use egui::Ui;
 
+/// Device to plug into the serial port of the emulated Game Boy
+pub enum LinkRequest {
+    Cable(TcpLink),
+    Printer,
+}
+
+/// Keyboard layout of one controller: A, B, Select, Start, Right, Left, Up, Down
+type KeyLayout = [Key; 8];
+
+/// Player 1 keeps the original bindings, the others are only read by games that
+/// request several controllers through the SGB
+const KEY_LAYOUTS: [KeyLayout; MAX_PLAYERS] = [
+    [
+        Key::A,
+        Key::S,
+        Key::Space,
+        Key::Enter,
+        Key::ArrowRight,
+        Key::ArrowLeft,
+        Key::ArrowUp,
+        Key::ArrowDown,
+    ],
+    [
+        Key::O,
+        Key::U,
+        Key::Num7,
+        Key::Num8,
+        Key::L,
+        Key::J,
+        Key::I,
+        Key::K,
+    ],
+    [
+        Key::Y,
+        Key::R,
+        Key::Num4,
+        Key::Num5,
+        Key::H,
+        Key::F,
+        Key::T,
+        Key::G,
+    ],
+    [
+        Key::C,
+        Key::X,
+        Key::Num1,
+        Key::Num2,
+        Key::B,
+        Key::V,
+        Key::N,
+        Key::M,
+    ],
+];
+
+fn read_layout(input: &egui::InputState, layout: &KeyLayout) -> (u8, u8) {
+    let state = layout.iter().enumerate().fold(0u8, |acc, (bit, key)| {
+        acc | (input.key_down(*key) as u8) << bit
+    });
+
+    (state & 0x0F, state >> 4)
+}
+
+/// How tilt is fed to cartridges with an accelerometer
+#[derive(Clone, Copy, PartialEq, serde::Deserialize, serde::Serialize)]
+enum TiltInput {
+    /// Arrow keys tilt while Shift is held, instead of pressing the D-pad
+    Keys,
+    /// Pointer position relative to the center of the screen
+    Mouse,
+}
+
 #[derive(serde::Deserialize, serde::Serialize)]
 #[serde(default)]
 pub struct App {
     scale_factor: f32,
     running: bool,
     show_debug: bool,
+    link_address: String,
+    tilt_input: TiltInput,
 
     #[serde(skip)]
     frame_ready: Arc<(Mutex<bool>, Condvar)>,
 
     #[serde(skip)]
-    keypad_channel_sender: MaybeUninit<Sender<(u8, u8)>>,
+    keypad_channel_sender: MaybeUninit<Sender<[(u8, u8); MAX_PLAYERS]>>,
+
+    #[serde(skip)]
+    link_sender: Option<Sender<LinkRequest>>,
+    #[serde(skip)]
+    link_status: Arc<Mutex<String>>,
+
+    #[serde(skip)]
+    cpu_fault: Arc<Mutex<Option<CpuFault>>>,
+
+    /// Motor state of rumble cartridges
+    #[serde(skip)]
+    rumble: Arc<AtomicBool>,
+    /// Tilt of MBC7 cartridges in g, along x and y
+    #[serde(skip)]
+    tilt: Arc<Mutex<(f32, f32)>>,
+
+    #[serde(skip)]
+    printout_receiver: Option<Receiver<ColorImage>>,
+    #[serde(skip)]
+    printouts: Vec<egui::TextureHandle>,
 
     #[serde(skip)]
     screen_window: FrameWindow,
     #[serde(skip)]
+    second_screen_window: Option<FrameWindow>,
+    #[serde(skip)]
     background_window: FrameWindow,
     #[serde(skip)]
     oam_window: FrameWindow,
@@ -43,13 +143,28 @@ impl Default for App {
             scale_factor: 1.0,
             running: true,
             show_debug: true,
+            link_address: format!("127.0.0.1:{}", DEFAULT_PORT),
+            tilt_input: TiltInput::Keys,
 
             frame_ready: Arc::default(),
 
             keypad_channel_sender: MaybeUninit::uninit(),
 
+            link_sender: None,
+            link_status: Arc::new(Mutex::new("Not connected".to_string())),
+
+            cpu_fault: Arc::default(),
+
+            rumble: Arc::default(),
+            tilt: Arc::default(),
+
+            printout_receiver: None,
+            printouts: Vec::new(),
+
             screen_window: FrameWindow::new("GameBoy".to_string(), Arc::default()),
 
+            second_screen_window: None,
+
             background_window: FrameWindow::new("Background".to_string(), Arc::default()),
 
             oam_window: FrameWindow::new("OAM".to_string(), Arc::default()),
@@ -63,13 +178,20 @@ impl Default for App {
 
 impl App {
     /// Called once before the first frame.
+    #[allow(clippy::too_many_arguments)]
     pub fn new(
         cc: &eframe::CreationContext<'_>,
         frame_ready: Arc<(Mutex<bool>, Condvar)>,
         screen_buffer: Arc<Mutex<ColorImage>>,
+        second_screen_buffer: Option<Arc<Mutex<ColorImage>>>,
         background_buffer: Arc<Mutex<ColorImage>>,
         sprites_buffer: Arc<Mutex<ColorImage>>,
-        keypad_channel_sender: Sender<(u8, u8)>,
+        keypad_channel_sender: Sender<[(u8, u8); MAX_PLAYERS]>,
+        link_sender: Sender<LinkRequest>,
+        printout_receiver: Receiver<ColorImage>,
+        cpu_fault: Arc<Mutex<Option<CpuFault>>>,
+        rumble: Arc<AtomicBool>,
+        tilt: Arc<Mutex<(f32, f32)>>,
     ) -> Self {
         // This is also where you can customize the look and feel of egui using
         // `cc.egui_ctx.set_visuals` and `cc.egui_ctx.set_fonts`.
@@ -83,12 +205,22 @@ impl App {
 
         app.frame_ready = frame_ready;
         app.screen_window.image = screen_buffer;
+        app.second_screen_window =
+            second_screen_buffer.map(|image| FrameWindow::new("GameBoy 2".to_string(), image));
         app.background_window.image = background_buffer;
         app.oam_window.image = sprites_buffer;
         app.oam_window.scale_factor = 4.0;
         app.keypad_channel_sender = MaybeUninit::new(keypad_channel_sender);
+        app.link_sender = Some(link_sender);
+        app.printout_receiver = Some(printout_receiver);
+        app.cpu_fault = cpu_fault;
+        app.rumble = rumble;
+        app.tilt = tilt;
 
         app.screen_window.create_texture(&cc.egui_ctx);
+        if let Some(window) = app.second_screen_window.as_mut() {
+            window.create_texture(&cc.egui_ctx);
+        }
         app.background_window.create_texture(&cc.egui_ctx);
         app.oam_window.create_texture(&cc.egui_ctx);
 
@@ -96,6 +228,77 @@ impl App {
     }
 }
 
+impl App {
+    /// Hosts or joins a link cable session in the background, the emulator thread
+    /// plugs the link into the serial port once connected.
+    fn start_link(&mut self, host: bool) {
+        let Some(sender) = self.link_sender.clone() else {
+            return;
+        };
+
+        let address = self.link_address.clone();
+        let status = self.link_status.clone();
+
+        *status.lock().unwrap() = if host {
+            format!("Waiting for partner on {}", address)
+        } else {
+            format!("Connecting to {}", address)
+        };
+
+        std::thread::spawn(move || {
+            let link = if host {
+                TcpLink::host(address.as_str())
+            } else {
+                TcpLink::join(address.as_str())
+            };
+
+            *status.lock().unwrap() = match link {
+                Ok(link) => {
+                    _ = sender.send(LinkRequest::Cable(link));
+                    format!("Linked with {}", address)
+                }
+                Err(err) => format!("Link failed: {}", err),
+            };
+        });
+    }
+
+    fn connect_printer(&mut self) {
+        if let Some(sender) = self.link_sender.as_ref() {
+            _ = sender.send(LinkRequest::Printer);
+            *self.link_status.lock().unwrap() = "Printer connected".to_string();
+        }
+    }
+
+    /// Shows every strip printed so far, newest at the bottom.
+    fn show_printouts(&mut self, ctx: &egui::Context) {
+        if let Some(receiver) = self.printout_receiver.as_ref() {
+            for image in receiver.try_iter() {
+                let name = format!("printout_{}", self.printouts.len());
+                self.printouts
+                    .push(ctx.load_texture(name, image, TextureOptions::NEAREST));
+            }
+        }
+
+        if self.printouts.is_empty() {
+            return;
+        }
+
+        let mut open = true;
+        egui::Window::new("Printer")
+            .open(&mut open)
+            .vscroll(true)
+            .show(ctx, |ui| {
+                for texture in &self.printouts {
+                    ui.image((texture.id(), texture.size_vec2() * 2.0));
+                }
+            });
+
+        if !open {
+            self.printouts.clear();
+        }
+    }
+}
+
 impl eframe::App for App {
     fn save(&mut self, storage: &mut dyn eframe::Storage) {
         eframe::set_value(storage, eframe::APP_KEY, self);
@@ -118,6 +321,27 @@ impl eframe::App for App {
                         ctx.send_viewport_cmd(egui::ViewportCommand::Close);
                     }
                 });
+                ui.menu_button("Link", |ui| {
+                    ui.horizontal(|ui| {
+                        ui.label("Address");
+                        ui.text_edit_singleline(&mut self.link_address);
+                    });
+                    if ui.button("Host link").clicked() {
+                        self.start_link(true);
+                    }
+                    if ui.button("Join link").clicked() {
+                        self.start_link(false);
+                    }
+                    ui.separator();
+                    if ui.button("Connect printer").clicked() {
+                        self.connect_printer();
+                    }
+                    ui.label(self.link_status.lock().unwrap().as_str());
+                });
+                ui.menu_button("Tilt", |ui| {
+                    ui.radio_value(&mut self.tilt_input, TiltInput::Keys, "Shift + arrow keys");
+                    ui.radio_value(&mut self.tilt_input, TiltInput::Mouse, "Mouse over screen");
+                });
                 ui.menu_button("Debug", |ui| {
                     ui.checkbox(&mut self.show_debug, "Show Debug Panel");
                 });
@@ -139,6 +363,14 @@ impl eframe::App for App {
                     ui.with_layout(egui::Layout::right_to_left(egui::Align::Min), |ui| {
                         egui::widgets::global_theme_preference_buttons(ui);
                         egui::warn_if_debug_build(ui);
+
+                        if let Some(fault) = self.cpu_fault.lock().unwrap().as_ref() {
+                            ui.colored_label(ui.visuals().error_fg_color, fault.to_string());
+                        }
+
+                        if self.rumble.load(Ordering::Relaxed) {
+                            ui.colored_label(ui.visuals().warn_fg_color, "📳 Rumble");
+                        }
                     });
                 });
             });
@@ -163,26 +395,54 @@ impl eframe::App for App {
                 egui::Layout::centered_and_justified(egui::Direction::TopDown),
                 |ui| {
                     self.screen_window.scale_factor = self.scale_factor;
-                    self.screen_window.show(ui);
+
+                    match self.second_screen_window.as_mut() {
+                        // Linked Game Boys side by side
+                        Some(second_screen_window) => {
+                            second_screen_window.scale_factor = self.scale_factor;
+                            ui.horizontal_centered(|ui| {
+                                self.screen_window.show(ui);
+                                second_screen_window.show(ui);
+                            });
+                        }
+                        None => self.screen_window.show(ui),
+                    }
                 },
             );
         });
 
+        self.show_printouts(ctx);
+
         ctx.input(|input| {
-            let input = (
-                (input.key_down(Key::A) as u8
-                    | (input.key_down(Key::S) as u8) << 1
-                    | (input.key_down(Key::Space) as u8) << 2
-                    | (input.key_down(Key::Enter) as u8) << 3),
-                (input.key_down(Key::ArrowRight) as u8
-                    | (input.key_down(Key::ArrowLeft) as u8) << 1
-                    | (input.key_down(Key::ArrowUp) as u8) << 2
-                    | (input.key_down(Key::ArrowDown) as u8) << 3),
-            );
+            let mut players = KEY_LAYOUTS.map(|layout| read_layout(input, &layout));
+
+            let tilt = match self.tilt_input {
+                TiltInput::Keys if input.modifiers.shift => {
+                    // The arrows tilt the cartridge instead of pressing the D-pad
+                    players[0].1 = 0;
+                    let axis = |negative, positive| {
+                        input.key_down(positive) as i8 as f32
+                            - input.key_down(negative) as i8 as f32
+                    };
+                    (
+                        axis(Key::ArrowLeft, Key::ArrowRight),
+                        axis(Key::ArrowUp, Key::ArrowDown),
+                    )
+                }
+                TiltInput::Keys => (0.0, 0.0),
+                TiltInput::Mouse => match (self.screen_window.rect, input.pointer.hover_pos()) {
+                    (Some(rect), Some(pos)) => {
+                        let offset = (pos - rect.center()) / (rect.size() / 2.0);
+                        (offset.x.clamp(-1.0, 1.0), offset.y.clamp(-1.0, 1.0))
+                    }
+                    _ => (0.0, 0.0),
+                },
+            };
+            *self.tilt.lock().unwrap() = tilt;
 
             unsafe {
                 let sender = self.keypad_channel_sender.assume_init_mut();
-                _ = sender.send(input);
+                _ = sender.send(players);
             }
         });
 
@@ -194,6 +454,8 @@ struct FrameWindow {
     pub name: String,
     pub scale_factor: f32,
     pub image: Arc<Mutex<ColorImage>>,
+    /// Where the frame was last drawn
+    pub rect: Option<egui::Rect>,
     buffer_as_texture: Option<egui::TextureHandle>,
 }
 
@@ -203,6 +465,7 @@ impl FrameWindow {
             name,
             scale_factor: 1.0,
             image,
+            rect: None,
             buffer_as_texture: None,
         }
     }
@@ -234,6 +497,6 @@ impl FrameWindow {
         let img = egui::Image::from_texture(self.buffer_as_texture.as_ref().unwrap())
             .fit_to_original_size(self.scale_factor as f32);
 
-        ui.add(img);
+        self.rect = Some(ui.add(img).rect);
     }
 }
@@ -1,26 +1,39 @@
 mod app;
-pub use app::App;
+pub use app::{App, LinkRequest};
 
 use dmg::ppu::IntoRawBytes;
 use dmg::{
     cpu::CPU,
+    joypad::MAX_PLAYERS,
+    link::{Printer, Printout, link_cable, step_lockstep},
     memory::{BootRom, MMU},
     ppu::color32::Color32,
+    sgb::{SGB, SGB_HEIGHT, SGB_WIDTH},
 };
 
 use eframe::{UserEvent, egui};
+use std::io::Write;
 use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::mpsc::channel;
 use std::sync::{Arc, Condvar, Mutex};
-use std::{cell::RefCell, rc::Rc, rc::Weak};
+use std::{cell::RefCell, rc::Rc};
 use winit::event_loop::{ControlFlow, EventLoop};
 
 use flexi_logger::{DeferredNow, Logger, WriteMode};
 use log::Record;
 
 static DMG_ROM: &[u8] = include_bytes!("../../roms/dmg_boot.bin");
-static TEST_ROM: &[u8] =
-    include_bytes!("../../roms/Pocket Monsters - Midori (Japan) (Rev 1) (SGB Enhanced).gb");
+/// How often battery RAM is written to disk after the game changed it
+const AUTOSAVE_INTERVAL: std::time::Duration = std::time::Duration::from_secs(5);
+
+macro_rules! test_rom_path {
+    () => {
+        "../../roms/Pocket Monsters - Midori (Japan) (Rev 1) (SGB Enhanced).gb"
+    };
+}
+
+static TEST_ROM: &[u8] = include_bytes!(test_rom_path!());
+static TEST_ROM_PATH: &str = test_rom_path!();
 
 pub fn no_info_format(
     w: &mut dyn std::io::Write,
@@ -45,8 +58,16 @@ fn main() {
     let frame_ready_condvar = Arc::new((Mutex::new(true), Condvar::new()));
     let frame_ready_condvar_clone = Arc::clone(&frame_ready_condvar);
 
+    // SGB enhanced carts get the 256x224 picture with the border around the screen
+    let sgb_enabled = TEST_ROM[0x146] == 0x03 && TEST_ROM[0x14B] == 0x33;
+    let screen_size = if sgb_enabled {
+        [SGB_WIDTH, SGB_HEIGHT]
+    } else {
+        [160, 144]
+    };
+
     let screen_buffer = Arc::new(Mutex::new(egui::ColorImage::filled(
-        [160, 144],
+        screen_size,
         egui::Color32::PURPLE,
     )));
     let background_buffer = Arc::new(Mutex::new(egui::ColorImage::filled(
@@ -58,30 +79,97 @@ fn main() {
         egui::Color32::TRANSPARENT,
     )));
 
+    // `--local-link` runs a second Game Boy in the same window, linked by cable
+    let local_link = std::env::args().any(|arg| arg == "--local-link");
+    let second_screen_buffer = local_link.then(|| {
+        Arc::new(Mutex::new(egui::ColorImage::filled(
+            [160, 144],
+            egui::Color32::PURPLE,
+        )))
+    });
+
     let screen_buffer_clone = screen_buffer.clone();
+    let second_screen_buffer_clone = second_screen_buffer.clone();
     let background_buffer_clone = background_buffer.clone();
     let sprites_buffer_clone = sprites_buffer.clone();
 
-    let (keypad_tx, keypad_rx) = channel::<(u8, u8)>();
+    let (keypad_tx, keypad_rx) = channel::<[(u8, u8); MAX_PLAYERS]>();
+    let (link_tx, link_rx) = channel::<LinkRequest>();
+    let (printout_tx, printout_rx) = channel::<egui::ColorImage>();
+
+    let cpu_fault = Arc::new(Mutex::new(None));
+    let cpu_fault_clone = cpu_fault.clone();
+
+    let rumble = Arc::new(AtomicBool::new(false));
+    let rumble_clone = rumble.clone();
+
+    let tilt = Arc::new(Mutex::new((0.0, 0.0)));
+    let tilt_clone = tilt.clone();
 
     // Run emulator in a seperate thread
     let emu_thread = std::thread::spawn(move || {
         let mut bootrom = BootRom::new();
         _ = bootrom.load(DMG_ROM);
 
-        let dma = Rc::new(RefCell::new(dmg::memory::dma::DMA::new(Weak::new())));
-
         // Create a new MBC
-        let rom = mbc::MBC::new(TEST_ROM.to_vec());
+        let mut rom = mbc::MBC::new(TEST_ROM.to_vec());
 
         log::warn!("Starting emulator with ROM: {:?}", rom);
 
-        let mmu: Rc<RefCell<MMU>> = MMU::new(Some(rom), bootrom.clone(), dma.clone());
+        let save_path = save_path(TEST_ROM_PATH);
+        // A save that failed to load is never overwritten with blank RAM
+        let can_save = rom.has_battery() && load_save(&save_path, &mut rom);
+        let mut last_save = std::time::Instant::now();
+
+        let mmu: Rc<RefCell<MMU>> = MMU::new(Some(rom), bootrom.clone());
+
+        if sgb_enabled {
+            mmu.borrow_mut().sgb = Some(SGB::new());
+        }
+
+        let mut sgb_frame_buffer = [Color32::RGB(0, 0, 0); SGB_WIDTH * SGB_HEIGHT];
 
         let mut cpu = CPU::new(mmu.clone());
 
+        let mut printer: Option<Rc<RefCell<Printer>>> = None;
+
+        let mut second = local_link.then(|| {
+            let rom = mbc::MBC::new(TEST_ROM.to_vec());
+            let second_mmu: Rc<RefCell<MMU>> = MMU::new(Some(rom), bootrom.clone());
+
+            let (port_a, port_b) = link_cable();
+            mmu.borrow_mut().serial.connect(Box::new(port_a));
+            second_mmu.borrow_mut().serial.connect(Box::new(port_b));
+
+            // Point the IR ports of HuC1/HuC3 carts at each other as well
+            let (ir_a, ir_b) = mbc::infrared_link();
+            mmu.borrow_mut().cartridge.connect_infrared(Box::new(ir_a));
+            second_mmu
+                .borrow_mut()
+                .cartridge
+                .connect_infrared(Box::new(ir_b));
+
+            let second_cpu = CPU::new(second_mmu.clone());
+            (second_mmu, second_cpu)
+        });
+
         while r.load(Ordering::Relaxed) {
-            cpu.do_step();
+            match second.as_mut() {
+                Some((second_mmu, second_cpu)) => {
+                    step_lockstep(&mut [&mut cpu, second_cpu]);
+
+                    let mut second_mmu = second_mmu.borrow_mut();
+                    if second_mmu.ppu.frame_ready {
+                        let mut screen =
+                            second_screen_buffer_clone.as_ref().unwrap().lock().unwrap();
+                        screen
+                            .as_raw_mut()
+                            .clone_from_slice(second_mmu.ppu.frame_buffer.as_raw_bytes());
+                        second_mmu.ppu.frame_ready = false;
+                    }
+                }
+                None => cpu.do_step(),
+            }
 
             // if cpu.pc == 0x0100 {
             //     _logger.parse_new_spec("dmg::cpu::decode = trace").unwrap();
@@ -91,13 +179,19 @@ fn main() {
             if mmu.borrow().ppu.frame_ready {
                 // Copy the frame_buffer to the screen buffer
                 {
-                    let ppu = &mut mmu.borrow_mut().ppu;
+                    let mmu = &mut *mmu.borrow_mut();
+                    let ppu = &mut mmu.ppu;
 
                     // Copy the screen
                     let mut screen_buffer = screen_buffer_clone.lock().unwrap();
                     let screen_buffer_ptr = screen_buffer.as_raw_mut();
-                    let src = &ppu.frame_buffer.as_raw_bytes();
-                    screen_buffer_ptr.clone_from_slice(src);
+                    if let Some(sgb) = mmu.sgb.as_ref() {
+                        sgb.render(&ppu.frame_buffer, &mut sgb_frame_buffer);
+                        screen_buffer_ptr.clone_from_slice(sgb_frame_buffer.as_raw_bytes());
+                    } else {
+                        let src = &ppu.frame_buffer.as_raw_bytes();
+                        screen_buffer_ptr.clone_from_slice(src);
+                    }
 
                     // Render the background debug view
                     let background_buffer = background_buffer_clone.lock().unwrap();
@@ -131,8 +225,67 @@ fn main() {
                     .recv_timeout(std::time::Duration::from_millis(100))
                     .ok();
 
-                if let Some((buttons, dpad)) = input {
-                    mmu.borrow_mut().joypad.set_buttons(buttons, dpad);
+                if let Some(players) = input {
+                    match second.as_ref() {
+                        // The second keyboard layout plays on the linked Game Boy
+                        Some((second_mmu, _)) => {
+                            let (buttons, dpad) = players[0];
+                            mmu.borrow_mut().joypad.set_buttons(buttons, dpad);
+                            let (buttons, dpad) = players[1];
+                            second_mmu.borrow_mut().joypad.set_buttons(buttons, dpad);
+                        }
+                        None => {
+                            let joypad = &mut mmu.borrow_mut().joypad;
+                            for (player, (buttons, dpad)) in players.into_iter().enumerate() {
+                                joypad.set_player_buttons(player, buttons, dpad);
+                            }
+                        }
+                    }
+                }
+
+                let (x, y) = *tilt_clone.lock().unwrap();
+                mmu.borrow_mut().cartridge.set_tilt(x, y);
+
+                match link_rx.try_recv() {
+                    Ok(LinkRequest::Cable(link)) => {
+                        printer = None;
+                        mmu.borrow_mut().serial.connect(Box::new(link));
+                    }
+                    Ok(LinkRequest::Printer) => {
+                        let device = Rc::new(RefCell::new(Printer::new()));
+                        mmu.borrow_mut().serial.connect(Box::new(device.clone()));
+                        printer = Some(device);
+                    }
+                    Err(_) => {}
+                }
+
+                if let Some(printer) = printer.as_ref() {
+                    for printout in printer.borrow_mut().take_printouts() {
+                        save_printout(&printout);
+                        _ = printout_tx.send(egui::ColorImage::from_gray(
+                            [printout.width, printout.height],
+                            &printout.luma(),
+                        ));
+                    }
+                }
+
+                if let Some(fault) = cpu.fault.take() {
+                    *cpu_fault_clone.lock().unwrap() = Some(fault);
+                }
+
+                rumble_clone.store(mmu.borrow().cartridge.rumble(), Ordering::Relaxed);
+
+                let serial_output = mmu.borrow_mut().serial.take_output();
+                if !serial_output.is_empty() {
+                    log::info!("Serial: {}", String::from_utf8_lossy(&serial_output));
+                }
+
+                if can_save && last_save.elapsed() >= AUTOSAVE_INTERVAL {
+                    let cartridge = &mut mmu.borrow_mut().cartridge;
+                    if cartridge.take_ram_dirty() {
+                        write_save(&save_path, cartridge);
+                    }
+                    last_save = std::time::Instant::now();
                 }
 
                 mmu.borrow_mut().ppu.frame_ready = false;
@@ -140,6 +293,10 @@ fn main() {
         }
 
         log::info!("Exiting emulator loop");
+
+        if can_save {
+            write_save(&save_path, &mmu.borrow().cartridge);
+        }
     });
 
     let native_options = eframe::NativeOptions {
@@ -159,9 +316,15 @@ fn main() {
                 cc,
                 frame_ready_condvar,
                 screen_buffer,
+                second_screen_buffer,
                 background_buffer,
                 sprites_buffer,
                 keypad_tx,
+                link_tx,
+                printout_rx,
+                cpu_fault,
+                rumble,
+                tilt,
             )))
         }),
         &eventloop,
@@ -173,3 +336,94 @@ fn main() {
     run_emulator.store(false, Ordering::Relaxed);
     emu_thread.join().unwrap();
 }
+
+/// Battery saves go to `saves/<title>.sav`
+/// Saves are named after the ROM file, header titles may not be valid file names.
+fn save_path(rom_path: &str) -> std::path::PathBuf {
+    let stem = std::path::Path::new(rom_path)
+        .file_stem()
+        .map_or("untitled".into(), |stem| stem.to_string_lossy());
+
+    std::path::Path::new("saves").join(format!("{}.sav", stem))
+}
+
+/// Restores battery RAM and the RTC from a .sav file, if there is one. Returns
+/// false if the file exists but couldn't be read.
+fn load_save(path: &std::path::Path, rom: &mut mbc::MBC) -> bool {
+    match std::fs::read(path) {
+        Ok(data) => {
+            rom.load_save_data(&data);
+            log::info!("Loaded save from {}", path.display());
+            true
+        }
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => true,
+        Err(err) => {
+            log::error!(
+                "Failed to read {}, saving is disabled: {}",
+                path.display(),
+                err
+            );
+            false
+        }
+    }
+}
+
+fn write_save(path: &std::path::Path, rom: &mbc::MBC) {
+    if let Some(dir) = path.parent()
+        && let Err(err) = std::fs::create_dir_all(dir)
+    {
+        log::error!("Failed to create {}: {}", dir.display(), err);
+        return;
+    }
+
+    match std::fs::write(path, rom.save_data()) {
+        Ok(()) => log::info!("Saved to {}", path.display()),
+        Err(err) => log::error!("Failed to save {}: {}", path.display(), err),
+    }
+}
+
+/// Writes a printed strip to `printouts/` as a PNG.
+fn save_printout(printout: &Printout) {
+    let dir = std::path::Path::new("printouts");
+    if let Err(err) = std::fs::create_dir_all(dir) {
+        log::error!("Failed to create {}: {}", dir.display(), err);
+        return;
+    }
+
+    let png = match printout.to_png() {
+        Ok(png) => png,
+        Err(err) => {
+            log::error!("Failed to encode printout: {}", err);
+            return;
+        }
+    };
+
+    let timestamp = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis();
+
+    // Several strips can be printed in the same millisecond, never overwrite one
+    for index in 0.. {
+        let path = match index {
+            0 => dir.join(format!("printout_{}.png", timestamp)),
+            _ => dir.join(format!("printout_{}_{}.png", timestamp, index)),
+        };
+
+        let file = std::fs::OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&path);
+        let result = match file {
+            Ok(mut file) => file.write_all(&png),
+            Err(err) if err.kind() == std::io::ErrorKind::AlreadyExists => continue,
+            Err(err) => Err(err),
+        };
+
+        match result {
+            Ok(()) => log::info!("Saved printout to {}", path.display()),
+            Err(err) => log::error!("Failed to save {}: {}", path.display(), err),
+        }
+        return;
+    }
+}
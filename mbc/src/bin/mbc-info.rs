@@ -1,17 +1,353 @@
+use mbc::header::{CartridgeHeader, CgbSupport, Licensee};
 use mbc::MBC;
 
+use std::fmt::Write;
+use std::path::{Path, PathBuf};
 use std::{env, fs};
 
+const BANK_SIZE: usize = 0x4000;
+const ROM_EXTENSIONS: [&str; 3] = ["gb", "gbc", "sgb"];
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum BankKind {
+    Empty,
+    Filled,
+    Data,
+}
+
+struct BankInfo {
+    kind: BankKind,
+    entropy: f64,
+}
+
+struct RomInfo {
+    path: PathBuf,
+    size: usize,
+    header: Option<CartridgeHeader>,
+    mbc: Result<String, String>,
+    banks: Vec<BankInfo>,
+}
+
+/// Shannon entropy in bits per byte
+fn entropy(data: &[u8]) -> f64 {
+    let mut counts = [0usize; 256];
+    for &b in data {
+        counts[b as usize] += 1;
+    }
+
+    counts
+        .iter()
+        .filter(|&&count| count > 0)
+        .map(|&count| count as f64 / data.len() as f64)
+        .fold(0.0, |sum, p| sum - p * p.log2())
+}
+
+fn bank_info(bank: &[u8]) -> BankInfo {
+    let kind = if bank.iter().all(|&b| b == 0x00) {
+        BankKind::Empty
+    } else if bank.iter().all(|&b| b == 0xFF) {
+        BankKind::Filled
+    } else {
+        BankKind::Data
+    };
+
+    BankInfo {
+        kind,
+        entropy: entropy(bank),
+    }
+}
+
+fn inspect(path: &Path) -> std::io::Result<RomInfo> {
+    let rom = fs::read(path)?;
+
+    Ok(RomInfo {
+        path: path.to_path_buf(),
+        size: rom.len(),
+        header: CartridgeHeader::parse(&rom),
+        banks: rom.chunks(BANK_SIZE).map(bank_info).collect(),
+        mbc: MBC::try_new(rom)
+            .map(|mbc| mbc.name())
+            .map_err(|e| e.to_string()),
+    })
+}
+
+fn cgb_name(cgb: CgbSupport) -> &'static str {
+    match cgb {
+        CgbSupport::None => "no",
+        CgbSupport::Compatible => "compatible",
+        CgbSupport::Only => "only",
+    }
+}
+
+fn licensee_code(licensee: &Licensee) -> String {
+    match licensee {
+        Licensee::Old(code) => format!("{:02X}", code.0),
+        Licensee::New(code) => String::from_utf8_lossy(&code.0).into_owned(),
+    }
+}
+
+fn checksum_status(valid: bool) -> &'static str {
+    match valid {
+        true => "OK",
+        false => "BAD",
+    }
+}
+
+fn print_text(info: &RomInfo) {
+    println!("{}", info.path.display());
+    println!("  File size:       {} bytes", info.size);
+
+    let Some(header) = &info.header else {
+        println!("  No cartridge header");
+        return;
+    };
+
+    println!("  Title:           {}", header.title);
+    if let Some(code) = &header.manufacturer_code {
+        println!("  Manufacturer:    {}", code);
+    }
+    println!(
+        "  Licensee:        {} ({})",
+        header.licensee,
+        licensee_code(&header.licensee)
+    );
+    println!(
+        "  Cartridge type:  {} (0x{:02X})",
+        header.cartridge_type,
+        header.cartridge_type.code()
+    );
+    match header.rom_size() {
+        Some(size) => println!("  ROM size:        {} KiB", size / 1024),
+        None => println!(
+            "  ROM size:        invalid (0x{:02X})",
+            header.rom_size_code
+        ),
+    }
+    match header.ram_size() {
+        Some(size) => println!("  RAM size:        {} KiB", size / 1024),
+        None => println!(
+            "  RAM size:        invalid (0x{:02X})",
+            header.ram_size_code
+        ),
+    }
+    println!("  CGB:             {}", cgb_name(header.cgb));
+    println!(
+        "  SGB:             {}",
+        if header.sgb { "yes" } else { "no" }
+    );
+    println!("  Destination:     {:?}", header.destination);
+    println!("  Version:         {}", header.version);
+    println!(
+        "  Logo:            {}",
+        if header.logo_valid { "OK" } else { "BAD" }
+    );
+    println!(
+        "  Header checksum: 0x{:02X} {} (computed 0x{:02X})",
+        header.header_checksum,
+        checksum_status(header.header_checksum_valid()),
+        header.computed_header_checksum()
+    );
+    println!(
+        "  Global checksum: 0x{:04X} {} (computed 0x{:04X})",
+        header.global_checksum,
+        checksum_status(header.global_checksum_valid()),
+        header.computed_global_checksum()
+    );
+    match &info.mbc {
+        Ok(name) => println!("  MBC:             {}", name),
+        Err(e) => println!("  MBC:             {}", e),
+    }
+
+    let unused = info
+        .banks
+        .iter()
+        .filter(|bank| bank.kind != BankKind::Data)
+        .count();
+    println!(
+        "  Banks:           {} ({} unused)",
+        info.banks.len(),
+        unused
+    );
+    for (i, bank) in info.banks.iter().enumerate() {
+        let kind = match bank.kind {
+            BankKind::Empty => " empty",
+            BankKind::Filled => " 0xFF",
+            BankKind::Data => "",
+        };
+        println!("    {:03X}: entropy {:.2}{}", i, bank.entropy, kind);
+    }
+}
+
+fn json_string(s: &str) -> String {
+    let mut out = String::with_capacity(s.len() + 2);
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            c if (c as u32) < 0x20 || c as u32 >= 0x7F => {
+                // Characters outside the BMP are escaped as a surrogate pair
+                for unit in c.encode_utf16(&mut [0; 2]) {
+                    _ = write!(out, "\\u{:04x}", unit);
+                }
+            }
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+fn json_option<T: ToString>(value: Option<T>) -> String {
+    value.map_or("null".to_string(), |v| v.to_string())
+}
+
+fn to_json(info: &RomInfo) -> String {
+    let mut out = String::new();
+    _ = write!(
+        out,
+        "{{\"path\":{},\"size\":{}",
+        json_string(&info.path.to_string_lossy()),
+        info.size
+    );
+
+    match &info.header {
+        Some(header) => {
+            _ = write!(
+                out,
+                ",\"header\":{{\"title\":{},\"manufacturer_code\":{},\"licensee\":{},\"licensee_code\":{},\
+                 \"cartridge_type\":{},\"cartridge_type_code\":{},\"ram\":{},\"battery\":{},\"rtc\":{},\
+                 \"rumble\":{},\"sensor\":{},\"rom_size\":{},\"ram_size\":{},\"cgb\":{},\"sgb\":{},\
+                 \"destination\":{},\"version\":{},\"logo_valid\":{},\"header_checksum\":{},\
+                 \"header_checksum_valid\":{},\"global_checksum\":{},\"global_checksum_valid\":{}}}",
+                json_string(&header.title),
+                json_option(header.manufacturer_code.as_deref().map(json_string)),
+                json_string(&header.licensee.to_string()),
+                json_string(&licensee_code(&header.licensee)),
+                json_string(&header.cartridge_type.to_string()),
+                header.cartridge_type.code(),
+                header.cartridge_type.has_ram(),
+                header.cartridge_type.has_battery(),
+                header.cartridge_type.has_rtc(),
+                header.cartridge_type.has_rumble(),
+                header.cartridge_type.has_sensor(),
+                json_option(header.rom_size()),
+                json_option(header.ram_size()),
+                json_string(cgb_name(header.cgb)),
+                header.sgb,
+                json_string(&format!("{:?}", header.destination)),
+                header.version,
+                header.logo_valid,
+                header.header_checksum,
+                header.header_checksum_valid(),
+                header.global_checksum,
+                header.global_checksum_valid(),
+            );
+        }
+        None => out.push_str(",\"header\":null"),
+    }
+
+    match &info.mbc {
+        Ok(name) => _ = write!(out, ",\"mbc\":{},\"error\":null", json_string(name)),
+        Err(e) => _ = write!(out, ",\"mbc\":null,\"error\":{}", json_string(e)),
+    }
+
+    out.push_str(",\"banks\":[");
+    for (i, bank) in info.banks.iter().enumerate() {
+        if i > 0 {
+            out.push(',');
+        }
+        let kind = match bank.kind {
+            BankKind::Empty => "empty",
+            BankKind::Filled => "filled",
+            BankKind::Data => "data",
+        };
+        _ = write!(
+            out,
+            "{{\"bank\":{},\"kind\":\"{}\",\"entropy\":{:.4}}}",
+            i, kind, bank.entropy
+        );
+    }
+    out.push_str("]}");
+    out
+}
+
+/// Expands directories into the ROM files they contain
+fn collect_files(paths: &[String]) -> std::io::Result<Vec<PathBuf>> {
+    let mut files = vec![];
+
+    for path in paths.iter().map(PathBuf::from) {
+        if path.is_dir() {
+            let mut entries: Vec<PathBuf> = fs::read_dir(&path)?
+                .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+                .filter(|path| {
+                    path.is_file()
+                        && path.extension().is_some_and(|ext| {
+                            ROM_EXTENSIONS.contains(&ext.to_string_lossy().to_lowercase().as_str())
+                        })
+                })
+                .collect();
+            entries.sort();
+            files.extend(entries);
+        } else {
+            files.push(path);
+        }
+    }
+    Ok(files)
+}
+
 fn main() {
     let args: Vec<String> = env::args().collect();
-    dbg!(&args);
 
-    if args.len() != 2 {
-        eprintln!("Usage: {} <rom file>", args[0]);
+    let json = args.iter().skip(1).any(|arg| arg == "--json");
+    let paths: Vec<String> = args
+        .iter()
+        .skip(1)
+        .filter(|arg| *arg != "--json")
+        .cloned()
+        .collect();
+
+    if paths.is_empty() {
+        eprintln!("Usage: {} [--json] <rom file or directory>...", args[0]);
+        std::process::exit(1);
+    }
+
+    let files = collect_files(&paths).unwrap_or_else(|e| {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    });
+
+    let mut failed = false;
+    let mut reports = vec![];
+
+    for file in &files {
+        match inspect(file) {
+            Ok(info) if json => reports.push(to_json(&info)),
+            Ok(info) => print_text(&info),
+            Err(e) => {
+                eprintln!("{}: {}", file.display(), e);
+                failed = true;
+            }
+        }
+    }
+
+    if json {
+        println!("[{}]", reports.join(","));
+    }
+
+    if failed {
         std::process::exit(1);
     }
+}
 
-    let file = fs::read(args[1].clone()).unwrap();
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-    println!("{:#?}", MBC::new(file));
+    #[test]
+    fn test_json_string() {
+        assert_eq!(json_string("A \"B\" \\"), r#""A \"B\" \\""#);
+        assert_eq!(json_string("\n\u{7F}é"), r#""\u000a\u007f\u00e9""#);
+        assert_eq!(json_string("\u{1F600}"), r#""\ud83d\ude00""#);
+    }
 }
@@ -0,0 +1,285 @@
+use mbc::header::{
+    global_checksum, header_checksum, CgbSupport, CARTRIDGE_TYPE, GLOBAL_CHECKSUM, HEADER_CHECKSUM,
+    MANUFACTURER_CODE, ROM_SIZE, TITLE,
+};
+use mbc::{CartridgeHeader, CartridgeType, MBC};
+
+use std::path::PathBuf;
+use std::{env, fs};
+
+const BANK_SIZE: usize = 0x4000;
+
+const USAGE: &str = "Usage: rom-tool <command> <rom file> [args] [-o <output file>]
+
+Commands:
+  fix-checksums         recompute the header and global checksums
+  pad                   pad to a power of two number of banks with 0xFF
+  trim                  remove trailing banks filled with 0xFF, down to a
+                        power of two number of banks, fails if that is
+                        larger than the file
+  set-title <title>     set the title
+  set-type <code>       set the cartridge type (e.g. 0x1B)
+
+The ROM is edited in place unless -o is given. Every command leaves the
+checksums fixed.";
+
+enum Command {
+    FixChecksums,
+    Pad,
+    Trim,
+    SetTitle(String),
+    SetType(u8),
+}
+
+fn parse_u8(s: &str) -> Result<u8, String> {
+    let parsed = match s.strip_prefix("0x").or_else(|| s.strip_prefix("0X")) {
+        Some(hex) => u8::from_str_radix(hex, 16),
+        None => s.parse(),
+    };
+    parsed.map_err(|e| format!("Invalid value {}: {}", s, e))
+}
+
+fn parse_args(args: &[String]) -> Result<(Command, PathBuf, PathBuf), String> {
+    let mut positional = vec![];
+    let mut output = None;
+
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-o" => output = Some(args.next().ok_or("Missing output file")?.into()),
+            _ => positional.push(arg.as_str()),
+        }
+    }
+
+    let (command, input, rest) = match positional.as_slice() {
+        [command, input, rest @ ..] => (*command, PathBuf::from(input), rest),
+        _ => return Err(USAGE.to_string()),
+    };
+
+    let command = match (command, rest) {
+        ("fix-checksums", []) => Command::FixChecksums,
+        ("pad", []) => Command::Pad,
+        ("trim", []) => Command::Trim,
+        ("set-title", [title]) => Command::SetTitle(title.to_string()),
+        ("set-type", [code]) => Command::SetType(parse_u8(code)?),
+        _ => return Err(USAGE.to_string()),
+    };
+
+    let output = output.unwrap_or_else(|| input.clone());
+    Ok((command, input, output))
+}
+
+fn fix_checksums(rom: &mut [u8]) {
+    rom[HEADER_CHECKSUM] = header_checksum(rom);
+
+    let [high, low] = global_checksum(rom).to_be_bytes();
+    rom[GLOBAL_CHECKSUM] = high;
+    rom[GLOBAL_CHECKSUM + 1] = low;
+}
+
+/// Header ROM size code for a ROM of `banks` banks, 32 KiB << code
+fn rom_size_code(banks: usize) -> Option<u8> {
+    let code = banks.max(2).ilog2() - 1;
+    (banks.is_power_of_two() && code <= 8).then_some(code as u8)
+}
+
+fn pad(rom: &mut Vec<u8>) -> Result<(), String> {
+    let banks = rom.len().div_ceil(BANK_SIZE).next_power_of_two().max(2);
+    rom.resize(banks * BANK_SIZE, 0xFF);
+
+    rom[ROM_SIZE] = rom_size_code(banks).ok_or(format!("Too many banks: {}", banks))?;
+    Ok(())
+}
+
+fn trim(rom: &mut Vec<u8>) -> Result<(), String> {
+    let used = rom
+        .chunks(BANK_SIZE)
+        .rposition(|bank| bank.iter().any(|&b| b != 0xFF))
+        .map_or(0, |last| last + 1);
+
+    // Keep the fixed bank and one switchable bank, the header can only describe
+    // a power of two number of banks
+    let banks = used.max(2).next_power_of_two();
+    if banks * BANK_SIZE > rom.len() {
+        return Err(format!(
+            "Can't trim {} banks to {}, use pad instead",
+            rom.len().div_ceil(BANK_SIZE),
+            banks
+        ));
+    }
+    rom.truncate(banks * BANK_SIZE);
+
+    rom[ROM_SIZE] = rom_size_code(banks).ok_or(format!("Too many banks: {}", banks))?;
+    Ok(())
+}
+
+fn set_title(rom: &mut [u8], header: &CartridgeHeader, title: &str) -> Result<(), String> {
+    // CGB cartridges share the end of the title area with the manufacturer code
+    let size = match header.cgb {
+        CgbSupport::None => 16,
+        _ => MANUFACTURER_CODE - TITLE,
+    };
+
+    if !title.is_ascii() || title.len() > size {
+        return Err(format!(
+            "Title must be at most {} ASCII characters: {}",
+            size, title
+        ));
+    }
+
+    let area = &mut rom[TITLE..TITLE + size];
+    area.fill(0);
+    area[..title.len()].copy_from_slice(title.as_bytes());
+    Ok(())
+}
+
+fn run(command: Command, rom: &mut Vec<u8>) -> Result<(), String> {
+    let header =
+        CartridgeHeader::parse(rom).ok_or(format!("ROM is truncated: {} bytes", rom.len()))?;
+
+    match command {
+        Command::FixChecksums => {}
+        Command::Pad => pad(rom)?,
+        Command::Trim => trim(rom)?,
+        Command::SetTitle(title) => set_title(rom, &header, &title)?,
+        Command::SetType(code) => {
+            if let CartridgeType::Unknown(code) = CartridgeType::from(code) {
+                return Err(format!("Unknown cartridge type: 0x{:02X}", code));
+            }
+            rom[CARTRIDGE_TYPE] = code;
+        }
+    }
+
+    fix_checksums(rom);
+
+    // Make sure the result still loads
+    let header = CartridgeHeader::parse(rom).ok_or("Edited ROM has no header")?;
+    if !header.header_checksum_valid() || !header.global_checksum_valid() {
+        return Err("Checksums don't match after editing".to_string());
+    }
+    MBC::try_new(rom.clone()).map_err(|e| e.to_string())?;
+
+    Ok(())
+}
+
+fn main() {
+    let args: Vec<String> = env::args().collect();
+
+    let (command, input, output) = parse_args(&args[1..]).unwrap_or_else(|e| {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    });
+
+    let mut rom = fs::read(&input).unwrap_or_else(|e| {
+        eprintln!("{}: {}", input.display(), e);
+        std::process::exit(1);
+    });
+
+    if let Err(e) = run(command, &mut rom) {
+        eprintln!("{}: {}", input.display(), e);
+        std::process::exit(1);
+    }
+
+    if let Err(e) = fs::write(&output, &rom) {
+        eprintln!("{}: {}", output.display(), e);
+        std::process::exit(1);
+    }
+
+    let header = CartridgeHeader::parse(&rom).unwrap();
+    println!(
+        "{}: {} {} {} KiB, checksums 0x{:02X} 0x{:04X}",
+        output.display(),
+        header.title,
+        header.cartridge_type,
+        rom.len() / 1024,
+        header.header_checksum,
+        header.global_checksum
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use mbc::header::CGB_FLAG;
+
+    /// ROM with a header and `used` banks of data, followed by 0xFF up to `banks`
+    fn rom(banks: usize, used: usize) -> Vec<u8> {
+        let mut rom = vec![0xFF; banks * BANK_SIZE];
+        for bank in 0..used {
+            rom[bank * BANK_SIZE] = bank as u8;
+        }
+        rom[TITLE..GLOBAL_CHECKSUM + 2].fill(0);
+        rom
+    }
+
+    #[test]
+    fn test_rom_size_code() {
+        assert_eq!(rom_size_code(2), Some(0x00));
+        assert_eq!(rom_size_code(4), Some(0x01));
+        assert_eq!(rom_size_code(512), Some(0x08));
+        assert_eq!(rom_size_code(3), None);
+        assert_eq!(rom_size_code(1024), None);
+    }
+
+    #[test]
+    fn test_pad() {
+        let mut padded = rom(3, 3);
+        pad(&mut padded).unwrap();
+        assert_eq!(padded.len(), 4 * BANK_SIZE);
+        assert_eq!(padded[ROM_SIZE], 0x01);
+        assert!(padded[3 * BANK_SIZE..].iter().all(|&b| b == 0xFF));
+
+        let mut odd = rom(1, 1);
+        odd.truncate(BANK_SIZE + 1);
+        pad(&mut odd).unwrap();
+        assert_eq!(odd.len(), 2 * BANK_SIZE);
+        assert_eq!(odd[ROM_SIZE], 0x00);
+    }
+
+    #[test]
+    fn test_trim() {
+        let mut trimmed = rom(8, 3);
+        trim(&mut trimmed).unwrap();
+        assert_eq!(trimmed.len(), 4 * BANK_SIZE);
+        assert_eq!(trimmed[ROM_SIZE], 0x01);
+        assert_eq!(trimmed[2 * BANK_SIZE], 2);
+
+        // Never below two banks
+        let mut trimmed = rom(4, 1);
+        trim(&mut trimmed).unwrap();
+        assert_eq!(trimmed.len(), 2 * BANK_SIZE);
+
+        // Rounding up would grow the file
+        let mut full = rom(3, 3);
+        assert!(trim(&mut full).is_err());
+        assert_eq!(full.len(), 3 * BANK_SIZE);
+    }
+
+    #[test]
+    fn test_set_title() {
+        let mut dmg = rom(2, 2);
+        let header = CartridgeHeader::parse(&dmg).unwrap();
+        set_title(&mut dmg, &header, "SIXTEEN CHARS XX").unwrap();
+        assert_eq!(
+            CartridgeHeader::parse(&dmg).unwrap().title,
+            "SIXTEEN CHARS XX"
+        );
+        assert!(set_title(&mut dmg, &header, "SEVENTEEN CHARS XX").is_err());
+        assert!(set_title(&mut dmg, &header, "TÏTLE").is_err());
+
+        // Shorter titles clear the rest of the old one
+        set_title(&mut dmg, &header, "SHORT").unwrap();
+        assert_eq!(CartridgeHeader::parse(&dmg).unwrap().title, "SHORT");
+
+        let mut cgb = rom(2, 2);
+        cgb[CGB_FLAG] = 0x80;
+        cgb[MANUFACTURER_CODE..CGB_FLAG].copy_from_slice(b"ABCD");
+        let header = CartridgeHeader::parse(&cgb).unwrap();
+        set_title(&mut cgb, &header, "ELEVEN CHRS").unwrap();
+        assert!(set_title(&mut cgb, &header, "TWELVE CHARS").is_err());
+
+        let header = CartridgeHeader::parse(&cgb).unwrap();
+        assert_eq!(header.title, "ELEVEN CHRS");
+        assert_eq!(header.manufacturer_code.as_deref(), Some("ABCD"));
+    }
+}
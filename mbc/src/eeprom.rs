@@ -0,0 +1,213 @@
+/// Size of the EEPROM in bytes
+pub const EEPROM_SIZE: usize = 0x100;
+
+const WORDS: usize = EEPROM_SIZE / 2;
+
+enum State {
+    /// Waiting for the start bit
+    Idle,
+    /// Shifting in the opcode and address
+    Command { bits: u16, count: u8 },
+    /// Shifting out a word, reads continue with the following word
+    Read { address: u8, count: u8 },
+    /// Shifting in a word for WRITE, or for WRAL if there is no address
+    Write {
+        address: Option<u8>,
+        bits: u16,
+        count: u8,
+    },
+    /// Command finished, ignores the clock until chip select drops
+    Done,
+}
+
+/// 93LC56 serial EEPROM wired as 128 16-bit words, as found on MBC7 carts.
+///
+/// The game bit-bangs the chip select, clock and data lines. Commands start with
+/// a 1 bit followed by a 2-bit opcode and 8 address bits, shifted in MSB first on
+/// the rising clock edge while chip select is high.
+pub struct Eeprom {
+    words: [u16; WORDS],
+    write_enabled: bool,
+    /// Contents changed since the last save
+    dirty: bool,
+    state: State,
+    cs: bool,
+    clk: bool,
+    di: bool,
+    data_out: bool,
+}
+
+impl Eeprom {
+    pub fn new() -> Eeprom {
+        Eeprom {
+            words: [0xFFFF; WORDS],
+            write_enabled: false,
+            dirty: false,
+            state: State::Idle,
+            cs: false,
+            clk: false,
+            di: false,
+            data_out: true,
+        }
+    }
+
+    /// Pin states: bit 7 CS, bit 6 CLK, bit 1 DI and bit 0 DO
+    pub fn read(&self) -> u8 {
+        ((self.cs as u8) << 7)
+            | ((self.clk as u8) << 6)
+            | ((self.di as u8) << 1)
+            | self.data_out as u8
+    }
+
+    pub fn write(&mut self, value: u8) {
+        let cs = value & 0x80 != 0;
+        let clk = value & 0x40 != 0;
+        let rising = clk && !self.clk;
+
+        self.cs = cs;
+        self.clk = clk;
+        self.di = value & 0x02 != 0;
+
+        if !cs {
+            self.state = State::Idle;
+            self.data_out = true;
+        } else if rising {
+            self.clock_in(self.di);
+        }
+    }
+
+    fn clock_in(&mut self, bit: bool) {
+        self.state = match std::mem::replace(&mut self.state, State::Idle) {
+            State::Idle if bit => State::Command { bits: 0, count: 0 },
+            State::Idle => State::Idle,
+
+            State::Command { bits, count } => {
+                let bits = (bits << 1) | bit as u16;
+                match count + 1 {
+                    10 => self.command(bits),
+                    count => State::Command { bits, count },
+                }
+            }
+
+            State::Read { address, count } => {
+                let word = self.words[address as usize];
+                self.data_out = word & (0x8000 >> count) != 0;
+                match count + 1 {
+                    16 => State::Read {
+                        address: (address + 1) % WORDS as u8,
+                        count: 0,
+                    },
+                    count => State::Read { address, count },
+                }
+            }
+
+            State::Write {
+                address,
+                bits,
+                count,
+            } => {
+                let bits = (bits << 1) | bit as u16;
+                if count + 1 < 16 {
+                    State::Write {
+                        address,
+                        bits,
+                        count: count + 1,
+                    }
+                } else {
+                    if self.write_enabled {
+                        self.store(address, bits);
+                    }
+                    // Writes complete instantly, DO reports ready
+                    self.data_out = true;
+                    State::Done
+                }
+            }
+
+            State::Done => State::Done,
+        };
+    }
+
+    /// Runs a command once its opcode and address are shifted in
+    fn command(&mut self, bits: u16) -> State {
+        // The 8th address bit is not connected on the 128 word configuration
+        let address = (bits & 0x7F) as u8;
+
+        match bits >> 8 {
+            // READ, a dummy 0 bit comes before the data
+            0b10 => {
+                self.data_out = false;
+                State::Read { address, count: 0 }
+            }
+            // WRITE
+            0b01 => State::Write {
+                address: Some(address),
+                bits: 0,
+                count: 0,
+            },
+            // ERASE
+            0b11 => {
+                if self.write_enabled {
+                    self.store(Some(address), 0xFFFF);
+                }
+                State::Done
+            }
+            _ => match (bits >> 6) & 0b11 {
+                // EWDS
+                0b00 => {
+                    self.write_enabled = false;
+                    State::Done
+                }
+                // WRAL
+                0b01 => State::Write {
+                    address: None,
+                    bits: 0,
+                    count: 0,
+                },
+                // ERAL
+                0b10 => {
+                    if self.write_enabled {
+                        self.store(None, 0xFFFF);
+                    }
+                    State::Done
+                }
+                // EWEN
+                _ => {
+                    self.write_enabled = true;
+                    State::Done
+                }
+            },
+        }
+    }
+
+    /// Writes one word, or every word without an address
+    fn store(&mut self, address: Option<u8>, value: u16) {
+        let words = match address {
+            Some(address) => std::slice::from_mut(&mut self.words[address as usize]),
+            None => &mut self.words[..],
+        };
+
+        for word in words {
+            self.dirty |= *word != value;
+            *word = value;
+        }
+    }
+
+    /// Returns whether the contents changed since the last call
+    pub fn take_dirty(&mut self) -> bool {
+        std::mem::take(&mut self.dirty)
+    }
+
+    /// Contents as little endian words
+    pub fn dump(&self) -> Vec<u8> {
+        self.words
+            .iter()
+            .flat_map(|word| word.to_le_bytes())
+            .collect()
+    }
+
+    pub fn load(&mut self, data: &[u8]) {
+        for (word, bytes) in self.words.iter_mut().zip(data.chunks_exact(2)) {
+            *word = u16::from_le_bytes([bytes[0], bytes[1]]);
+        }
+    }
+}
@@ -0,0 +1,52 @@
+use std::fmt;
+
+use crate::header::CartridgeType;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CartridgeError {
+    /// The cartridge type byte names a mapper that isn't emulated
+    UnsupportedMapper(CartridgeType),
+    /// The ROM is too short to contain a cartridge header
+    TruncatedRom { size: usize },
+    /// The ROM size at 0x148 is invalid, or doesn't fit the file
+    RomSizeMismatch { header: Option<u32>, actual: usize },
+    /// Unknown RAM size code at 0x149
+    BadRamSize(u8),
+    /// RAM size in bytes that doesn't make up a whole number of banks
+    UnsupportedRamSize(u32),
+}
+
+impl fmt::Display for CartridgeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CartridgeError::UnsupportedMapper(ty) => {
+                write!(f, "Unsupported MBC type: {} (0x{:02X})", ty, ty.code())
+            }
+            CartridgeError::TruncatedRom { size } => {
+                write!(f, "ROM is truncated: {} bytes, no cartridge header", size)
+            }
+            CartridgeError::RomSizeMismatch {
+                header: Some(header),
+                actual,
+            } => write!(
+                f,
+                "ROM size mismatch: header declares {} bytes, file has {} bytes",
+                header, actual
+            ),
+            CartridgeError::RomSizeMismatch {
+                header: None,
+                actual,
+            } => write!(
+                f,
+                "ROM size mismatch: invalid size in header, file has {} bytes",
+                actual
+            ),
+            CartridgeError::BadRamSize(code) => write!(f, "Unsupported RAM size: 0x{:02X}", code),
+            CartridgeError::UnsupportedRamSize(size) => {
+                write!(f, "Unsupported RAM size: {} bytes", size)
+            }
+        }
+    }
+}
+
+impl std::error::Error for CartridgeError {}
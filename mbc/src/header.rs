@@ -0,0 +1,557 @@
+use std::fmt;
+
+use crate::licensee_codes::{NewLicenseeCode, OldLicenseeCode, NEW_LICENSEE_CODE};
+
+pub const ENTRY_POINT: usize = 0x100;
+pub const LOGO: usize = 0x104;
+pub const TITLE: usize = 0x134;
+pub const MANUFACTURER_CODE: usize = 0x13F;
+pub const CGB_FLAG: usize = 0x143;
+pub const NEW_LICENSEE: usize = 0x144;
+pub const SGB_FLAG: usize = 0x146;
+pub const CARTRIDGE_TYPE: usize = 0x147;
+pub const ROM_SIZE: usize = 0x148;
+pub const RAM_SIZE: usize = 0x149;
+pub const DESTINATION: usize = 0x14A;
+pub const OLD_LICENSEE: usize = 0x14B;
+pub const VERSION: usize = 0x14C;
+pub const HEADER_CHECKSUM: usize = 0x14D;
+pub const GLOBAL_CHECKSUM: usize = 0x14E;
+pub const HEADER_END: usize = 0x150;
+
+pub const NINTENDO_LOGO: [u8; 48] = [
+    0xCE, 0xED, 0x66, 0x66, 0xCC, 0x0D, 0x00, 0x0B, 0x03, 0x73, 0x00, 0x83, 0x00, 0x0C, 0x00, 0x0D,
+    0x00, 0x08, 0x11, 0x1F, 0x88, 0x89, 0x00, 0x0E, 0xDC, 0xCC, 0x6E, 0xE6, 0xDD, 0xDD, 0xD9, 0x99,
+    0xBB, 0xBB, 0x67, 0x63, 0x6E, 0x0E, 0xEC, 0xCC, 0xDD, 0xDC, 0x99, 0x9F, 0xBB, 0xB9, 0x33, 0x3E,
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CgbSupport {
+    None,
+    /// 0x80: runs on both DMG and CGB
+    Compatible,
+    /// 0xC0: CGB only
+    Only,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Destination {
+    Japan,
+    Overseas,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Licensee {
+    Old(OldLicenseeCode),
+    New(NewLicenseeCode),
+}
+
+impl fmt::Display for Licensee {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Licensee::Old(code) => code.fmt(f),
+            Licensee::New(code) => code.fmt(f),
+        }
+    }
+}
+
+/// Mapper chip of a cartridge type, without the extra hardware
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Mapper {
+    RomOnly,
+    MBC1,
+    MBC2,
+    MMM01,
+    MBC3,
+    MBC5,
+    MBC6,
+    MBC7,
+    PocketCamera,
+    BandaiTama5,
+    HuC3,
+    HuC1,
+    Unknown,
+}
+
+impl fmt::Display for Mapper {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            Mapper::RomOnly => "ROM",
+            Mapper::MBC1 => "MBC1",
+            Mapper::MBC2 => "MBC2",
+            Mapper::MMM01 => "MMM01",
+            Mapper::MBC3 => "MBC3",
+            Mapper::MBC5 => "MBC5",
+            Mapper::MBC6 => "MBC6",
+            Mapper::MBC7 => "MBC7",
+            Mapper::PocketCamera => "POCKET CAMERA",
+            Mapper::BandaiTama5 => "BANDAI TAMA5",
+            Mapper::HuC3 => "HuC3",
+            Mapper::HuC1 => "HuC1",
+            Mapper::Unknown => "UNKNOWN",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CartridgeType {
+    RomOnly,
+    MBC1,
+    MBC1Ram,
+    MBC1RamBattery,
+    MBC2,
+    MBC2Battery,
+    RomRam,
+    RomRamBattery,
+    MMM01,
+    MMM01Ram,
+    MMM01RamBattery,
+    MBC3TimerBattery,
+    MBC3TimerRamBattery,
+    MBC3,
+    MBC3Ram,
+    MBC3RamBattery,
+    MBC5,
+    MBC5Ram,
+    MBC5RamBattery,
+    MBC5Rumble,
+    MBC5RumbleRam,
+    MBC5RumbleRamBattery,
+    MBC6,
+    MBC7SensorRumbleRamBattery,
+    PocketCamera,
+    BandaiTama5,
+    HuC3,
+    HuC1RamBattery,
+    Unknown(u8),
+}
+
+impl CartridgeType {
+    pub fn code(&self) -> u8 {
+        match self {
+            CartridgeType::RomOnly => 0x00,
+            CartridgeType::MBC1 => 0x01,
+            CartridgeType::MBC1Ram => 0x02,
+            CartridgeType::MBC1RamBattery => 0x03,
+            CartridgeType::MBC2 => 0x05,
+            CartridgeType::MBC2Battery => 0x06,
+            CartridgeType::RomRam => 0x08,
+            CartridgeType::RomRamBattery => 0x09,
+            CartridgeType::MMM01 => 0x0B,
+            CartridgeType::MMM01Ram => 0x0C,
+            CartridgeType::MMM01RamBattery => 0x0D,
+            CartridgeType::MBC3TimerBattery => 0x0F,
+            CartridgeType::MBC3TimerRamBattery => 0x10,
+            CartridgeType::MBC3 => 0x11,
+            CartridgeType::MBC3Ram => 0x12,
+            CartridgeType::MBC3RamBattery => 0x13,
+            CartridgeType::MBC5 => 0x19,
+            CartridgeType::MBC5Ram => 0x1A,
+            CartridgeType::MBC5RamBattery => 0x1B,
+            CartridgeType::MBC5Rumble => 0x1C,
+            CartridgeType::MBC5RumbleRam => 0x1D,
+            CartridgeType::MBC5RumbleRamBattery => 0x1E,
+            CartridgeType::MBC6 => 0x20,
+            CartridgeType::MBC7SensorRumbleRamBattery => 0x22,
+            CartridgeType::PocketCamera => 0xFC,
+            CartridgeType::BandaiTama5 => 0xFD,
+            CartridgeType::HuC3 => 0xFE,
+            CartridgeType::HuC1RamBattery => 0xFF,
+            CartridgeType::Unknown(code) => *code,
+        }
+    }
+
+    /// Name of the mapper chip, without the extra hardware
+    pub fn mapper(&self) -> Mapper {
+        match self {
+            CartridgeType::RomOnly | CartridgeType::RomRam | CartridgeType::RomRamBattery => {
+                Mapper::RomOnly
+            }
+            CartridgeType::MBC1 | CartridgeType::MBC1Ram | CartridgeType::MBC1RamBattery => {
+                Mapper::MBC1
+            }
+            CartridgeType::MBC2 | CartridgeType::MBC2Battery => Mapper::MBC2,
+            CartridgeType::MMM01 | CartridgeType::MMM01Ram | CartridgeType::MMM01RamBattery => {
+                Mapper::MMM01
+            }
+            CartridgeType::MBC3TimerBattery
+            | CartridgeType::MBC3TimerRamBattery
+            | CartridgeType::MBC3
+            | CartridgeType::MBC3Ram
+            | CartridgeType::MBC3RamBattery => Mapper::MBC3,
+            CartridgeType::MBC5
+            | CartridgeType::MBC5Ram
+            | CartridgeType::MBC5RamBattery
+            | CartridgeType::MBC5Rumble
+            | CartridgeType::MBC5RumbleRam
+            | CartridgeType::MBC5RumbleRamBattery => Mapper::MBC5,
+            CartridgeType::MBC6 => Mapper::MBC6,
+            CartridgeType::MBC7SensorRumbleRamBattery => Mapper::MBC7,
+            CartridgeType::PocketCamera => Mapper::PocketCamera,
+            CartridgeType::BandaiTama5 => Mapper::BandaiTama5,
+            CartridgeType::HuC3 => Mapper::HuC3,
+            CartridgeType::HuC1RamBattery => Mapper::HuC1,
+            CartridgeType::Unknown(_) => Mapper::Unknown,
+        }
+    }
+
+    /// MBC2 RAM is built into the mapper and is not reported here
+    pub fn has_ram(&self) -> bool {
+        matches!(
+            self,
+            CartridgeType::MBC1Ram
+                | CartridgeType::MBC1RamBattery
+                | CartridgeType::RomRam
+                | CartridgeType::RomRamBattery
+                | CartridgeType::MMM01Ram
+                | CartridgeType::MMM01RamBattery
+                | CartridgeType::MBC3TimerRamBattery
+                | CartridgeType::MBC3Ram
+                | CartridgeType::MBC3RamBattery
+                | CartridgeType::MBC5Ram
+                | CartridgeType::MBC5RamBattery
+                | CartridgeType::MBC5RumbleRam
+                | CartridgeType::MBC5RumbleRamBattery
+                | CartridgeType::MBC7SensorRumbleRamBattery
+                | CartridgeType::PocketCamera
+                | CartridgeType::HuC3
+                | CartridgeType::HuC1RamBattery
+        )
+    }
+
+    pub fn has_battery(&self) -> bool {
+        matches!(
+            self,
+            CartridgeType::MBC1RamBattery
+                | CartridgeType::MBC2Battery
+                | CartridgeType::RomRamBattery
+                | CartridgeType::MMM01RamBattery
+                | CartridgeType::MBC3TimerBattery
+                | CartridgeType::MBC3TimerRamBattery
+                | CartridgeType::MBC3RamBattery
+                | CartridgeType::MBC5RamBattery
+                | CartridgeType::MBC5RumbleRamBattery
+                | CartridgeType::MBC7SensorRumbleRamBattery
+                | CartridgeType::HuC3
+                | CartridgeType::HuC1RamBattery
+        )
+    }
+
+    pub fn has_rtc(&self) -> bool {
+        matches!(
+            self,
+            CartridgeType::MBC3TimerBattery
+                | CartridgeType::MBC3TimerRamBattery
+                | CartridgeType::HuC3
+        )
+    }
+
+    pub fn has_rumble(&self) -> bool {
+        matches!(
+            self,
+            CartridgeType::MBC5Rumble
+                | CartridgeType::MBC5RumbleRam
+                | CartridgeType::MBC5RumbleRamBattery
+                | CartridgeType::MBC7SensorRumbleRamBattery
+        )
+    }
+
+    pub fn has_sensor(&self) -> bool {
+        matches!(self, CartridgeType::MBC7SensorRumbleRamBattery)
+    }
+}
+
+impl From<u8> for CartridgeType {
+    fn from(code: u8) -> CartridgeType {
+        match code {
+            0x00 => CartridgeType::RomOnly,
+            0x01 => CartridgeType::MBC1,
+            0x02 => CartridgeType::MBC1Ram,
+            0x03 => CartridgeType::MBC1RamBattery,
+            0x05 => CartridgeType::MBC2,
+            0x06 => CartridgeType::MBC2Battery,
+            0x08 => CartridgeType::RomRam,
+            0x09 => CartridgeType::RomRamBattery,
+            0x0B => CartridgeType::MMM01,
+            0x0C => CartridgeType::MMM01Ram,
+            0x0D => CartridgeType::MMM01RamBattery,
+            0x0F => CartridgeType::MBC3TimerBattery,
+            0x10 => CartridgeType::MBC3TimerRamBattery,
+            0x11 => CartridgeType::MBC3,
+            0x12 => CartridgeType::MBC3Ram,
+            0x13 => CartridgeType::MBC3RamBattery,
+            0x19 => CartridgeType::MBC5,
+            0x1A => CartridgeType::MBC5Ram,
+            0x1B => CartridgeType::MBC5RamBattery,
+            0x1C => CartridgeType::MBC5Rumble,
+            0x1D => CartridgeType::MBC5RumbleRam,
+            0x1E => CartridgeType::MBC5RumbleRamBattery,
+            0x20 => CartridgeType::MBC6,
+            0x22 => CartridgeType::MBC7SensorRumbleRamBattery,
+            0xFC => CartridgeType::PocketCamera,
+            0xFD => CartridgeType::BandaiTama5,
+            0xFE => CartridgeType::HuC3,
+            0xFF => CartridgeType::HuC1RamBattery,
+            code => CartridgeType::Unknown(code),
+        }
+    }
+}
+
+impl fmt::Display for CartridgeType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if let CartridgeType::Unknown(code) = self {
+            return write!(f, "UNKNOWN (0x{:02X})", code);
+        }
+
+        write!(f, "{}", self.mapper())?;
+        if self.has_sensor() {
+            write!(f, "+SENSOR")?;
+        }
+        if self.has_rtc() && *self != CartridgeType::HuC3 {
+            write!(f, "+TIMER")?;
+        }
+        if self.has_rumble() {
+            write!(f, "+RUMBLE")?;
+        }
+        if self.has_ram() && !matches!(self, CartridgeType::PocketCamera | CartridgeType::HuC3) {
+            write!(f, "+RAM")?;
+        }
+        if self.has_battery() && *self != CartridgeType::HuC3 {
+            write!(f, "+BATTERY")?;
+        }
+        Ok(())
+    }
+}
+
+/// Cartridge header found at 0x0100-0x014F of every ROM.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CartridgeHeader {
+    pub entry_point: [u8; 4],
+    pub logo_valid: bool,
+    pub title: String,
+    /// Only present on CGB cartridges
+    pub manufacturer_code: Option<String>,
+    pub cgb: CgbSupport,
+    pub sgb: bool,
+    pub licensee: Licensee,
+    pub cartridge_type: CartridgeType,
+    pub rom_size_code: u8,
+    pub ram_size_code: u8,
+    pub destination: Destination,
+    pub version: u8,
+    pub header_checksum: u8,
+    pub global_checksum: u16,
+    computed_header_checksum: u8,
+    computed_global_checksum: u16,
+}
+
+impl CartridgeHeader {
+    /// Parses the header, returns `None` if the ROM is too short to contain one.
+    pub fn parse(rom: &[u8]) -> Option<CartridgeHeader> {
+        if rom.len() < HEADER_END {
+            return None;
+        }
+
+        let cgb = match rom[CGB_FLAG] {
+            0xC0 => CgbSupport::Only,
+            flag if flag & 0x80 != 0 => CgbSupport::Compatible,
+            _ => CgbSupport::None,
+        };
+
+        // Later cartridges use the end of the title for the CGB flag, and some
+        // of them for the manufacturer code as well
+        let code = &rom[MANUFACTURER_CODE..CGB_FLAG];
+        let manufacturer_code = match cgb {
+            CgbSupport::None => None,
+            _ => code
+                .iter()
+                .all(|c| c.is_ascii_alphanumeric())
+                .then(|| code.iter().map(|&c| c as char).collect()),
+        };
+
+        let title_size = match (cgb, &manufacturer_code) {
+            (CgbSupport::None, _) => 16,
+            (_, Some(_)) => 11,
+            (_, None) => 15,
+        };
+
+        let title = rom[TITLE..TITLE + title_size]
+            .iter()
+            .take_while(|&&c| c != 0)
+            .map(|&c| c as char)
+            .collect();
+
+        let licensee = match rom[OLD_LICENSEE] {
+            NEW_LICENSEE_CODE => {
+                Licensee::New(NewLicenseeCode([rom[NEW_LICENSEE], rom[NEW_LICENSEE + 1]]))
+            }
+            code => Licensee::Old(OldLicenseeCode(code)),
+        };
+
+        Some(CartridgeHeader {
+            entry_point: rom[ENTRY_POINT..LOGO].try_into().unwrap(),
+            logo_valid: rom[LOGO..TITLE] == NINTENDO_LOGO,
+            title,
+            manufacturer_code,
+            cgb,
+            sgb: rom[SGB_FLAG] == 0x03,
+            licensee,
+            cartridge_type: CartridgeType::from(rom[CARTRIDGE_TYPE]),
+            rom_size_code: rom[ROM_SIZE],
+            ram_size_code: rom[RAM_SIZE],
+            destination: match rom[DESTINATION] {
+                0x00 => Destination::Japan,
+                _ => Destination::Overseas,
+            },
+            version: rom[VERSION],
+            header_checksum: rom[HEADER_CHECKSUM],
+            global_checksum: u16::from_be_bytes([rom[GLOBAL_CHECKSUM], rom[GLOBAL_CHECKSUM + 1]]),
+            computed_header_checksum: header_checksum(rom),
+            computed_global_checksum: global_checksum(rom),
+        })
+    }
+
+    /// ROM size declared in the header, 32 KiB << code
+    pub fn rom_size(&self) -> Option<u32> {
+        match self.rom_size_code {
+            0x00..=0x08 => Some(0x8000 << self.rom_size_code),
+            _ => None,
+        }
+    }
+
+    /// External RAM size declared in the header
+    pub fn ram_size(&self) -> Option<u32> {
+        match self.ram_size_code {
+            0x00 => Some(0),
+            0x01 => Some(2 * 1024),
+            0x02 => Some(8 * 1024),
+            0x03 => Some(32 * 1024),
+            0x04 => Some(128 * 1024),
+            0x05 => Some(64 * 1024),
+            _ => None,
+        }
+    }
+
+    /// The boot ROM refuses to start the cartridge if this doesn't match
+    pub fn header_checksum_valid(&self) -> bool {
+        self.header_checksum == self.computed_header_checksum
+    }
+
+    /// Not checked by the hardware, a mismatch usually means a bad dump or a hack
+    pub fn global_checksum_valid(&self) -> bool {
+        self.global_checksum == self.computed_global_checksum
+    }
+
+    pub fn computed_header_checksum(&self) -> u8 {
+        self.computed_header_checksum
+    }
+
+    pub fn computed_global_checksum(&self) -> u16 {
+        self.computed_global_checksum
+    }
+}
+
+/// Checksum of 0x0134-0x014C as computed by the boot ROM
+pub fn header_checksum(rom: &[u8]) -> u8 {
+    rom[TITLE..HEADER_CHECKSUM]
+        .iter()
+        .fold(0u8, |x, &b| x.wrapping_sub(b).wrapping_sub(1))
+}
+
+/// Sum of every byte of the ROM except the global checksum itself
+pub fn global_checksum(rom: &[u8]) -> u16 {
+    rom.iter()
+        .enumerate()
+        .filter(|(i, _)| *i != GLOBAL_CHECKSUM && *i != GLOBAL_CHECKSUM + 1)
+        .fold(0u16, |sum, (_, &b)| sum.wrapping_add(b as u16))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rom() -> Vec<u8> {
+        let mut rom = vec![0; 0x8000];
+        rom[ENTRY_POINT..LOGO].copy_from_slice(&[0x00, 0xC3, 0x50, 0x01]);
+        rom[LOGO..TITLE].copy_from_slice(&NINTENDO_LOGO);
+        rom[TITLE..TITLE + 7].copy_from_slice(b"TETRIS2");
+        rom[OLD_LICENSEE] = 0x01;
+        rom[DESTINATION] = 0x01;
+        rom[VERSION] = 0x01;
+        rom
+    }
+
+    #[test]
+    fn test_parse() {
+        let mut rom = rom();
+        rom[CARTRIDGE_TYPE] = 0x03;
+        rom[ROM_SIZE] = 0x00;
+        rom[RAM_SIZE] = 0x02;
+
+        let header = CartridgeHeader::parse(&rom).unwrap();
+        assert_eq!(header.entry_point, [0x00, 0xC3, 0x50, 0x01]);
+        assert!(header.logo_valid);
+        assert_eq!(header.title, "TETRIS2");
+        assert_eq!(header.manufacturer_code, None);
+        assert_eq!(header.cgb, CgbSupport::None);
+        assert!(!header.sgb);
+        assert_eq!(header.licensee.to_string(), "Nintendo");
+        assert_eq!(header.cartridge_type, CartridgeType::MBC1RamBattery);
+        assert_eq!(header.cartridge_type.to_string(), "MBC1+RAM+BATTERY");
+        assert_eq!(header.rom_size(), Some(0x8000));
+        assert_eq!(header.ram_size(), Some(0x2000));
+        assert_eq!(header.destination, Destination::Overseas);
+        assert_eq!(header.version, 1);
+
+        assert!(CartridgeHeader::parse(&rom[..0x14F]).is_none());
+    }
+
+    #[test]
+    fn test_cgb_title() {
+        let mut rom = rom();
+        rom[TITLE..CGB_FLAG].copy_from_slice(b"POKEMON_SLVAAXE");
+        rom[CGB_FLAG] = 0x80;
+        rom[SGB_FLAG] = 0x03;
+        rom[OLD_LICENSEE] = NEW_LICENSEE_CODE;
+        rom[NEW_LICENSEE..NEW_LICENSEE + 2].copy_from_slice(b"01");
+        rom[CARTRIDGE_TYPE] = 0x10;
+
+        let header = CartridgeHeader::parse(&rom).unwrap();
+        assert_eq!(header.title, "POKEMON_SLV");
+        assert_eq!(header.manufacturer_code.as_deref(), Some("AAXE"));
+        assert_eq!(header.cgb, CgbSupport::Compatible);
+        assert!(header.sgb);
+        assert_eq!(header.licensee, Licensee::New(NewLicenseeCode(*b"01")));
+        assert!(header.cartridge_type.has_rtc());
+        assert!(header.cartridge_type.has_battery());
+        assert_eq!(header.cartridge_type.to_string(), "MBC3+TIMER+RAM+BATTERY");
+
+        // Without a manufacturer code the title runs up to the CGB flag
+        rom[TITLE..CGB_FLAG].copy_from_slice(b"MARIO GOLF DX\0\0");
+        let header = CartridgeHeader::parse(&rom).unwrap();
+        assert_eq!(header.title, "MARIO GOLF DX");
+        assert_eq!(header.manufacturer_code, None);
+    }
+
+    #[test]
+    fn test_checksums() {
+        let mut rom = rom();
+        let header = CartridgeHeader::parse(&rom).unwrap();
+        assert!(!header.header_checksum_valid());
+
+        rom[HEADER_CHECKSUM] = header_checksum(&rom);
+        let [high, low] = global_checksum(&rom).to_be_bytes();
+        rom[GLOBAL_CHECKSUM] = high;
+        rom[GLOBAL_CHECKSUM + 1] = low;
+
+        let header = CartridgeHeader::parse(&rom).unwrap();
+        assert!(header.header_checksum_valid());
+        assert!(header.global_checksum_valid());
+
+        rom[0x4000] = 0x42;
+        let header = CartridgeHeader::parse(&rom).unwrap();
+        assert!(header.header_checksum_valid());
+        assert!(!header.global_checksum_valid());
+    }
+}
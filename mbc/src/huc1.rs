@@ -0,0 +1,186 @@
+use crate::infrared::{Infrared, InfraredPort};
+use crate::mbc::MBCTrait;
+use crate::mbc::{ram_banks, rom_banks};
+
+pub struct HuC1 {
+    rom: Vec<u8>,
+    ram: Vec<u8>,
+    rom_banks: u16,
+    ram_banks: u8,
+    active_rom_bank: u8,
+    active_ram_bank: u8,
+    /// 0xA000-0xBFFF accesses the IR port instead of RAM. There is no RAM
+    /// enable, RAM is mapped whenever IR mode is off.
+    ir_mode: bool,
+    /// Battery backed RAM changed since the last save
+    dirty: bool,
+    infrared: Infrared,
+}
+
+impl HuC1 {
+    pub fn new(rom: Vec<u8>, ram_size: u32) -> HuC1 {
+        let rom_banks = rom_banks(rom.len() as u32);
+        let ram_banks = ram_banks(ram_size).unwrap_or_else(|e| panic!("{}", e));
+
+        HuC1 {
+            rom,
+            ram: vec![0; ram_size as usize],
+            rom_banks,
+            ram_banks,
+            active_rom_bank: 1,
+            active_ram_bank: 0,
+            ir_mode: false,
+            dirty: false,
+            infrared: Infrared::default(),
+        }
+    }
+
+    fn ram_index(&self, address: u16) -> Option<usize> {
+        if self.ram_banks == 0 {
+            return None;
+        }
+
+        let bank = (self.active_ram_bank % self.ram_banks) as usize;
+        Some((bank * 0x2000) | (address as usize & 0x1FFF))
+    }
+}
+
+impl MBCTrait for HuC1 {
+    fn name(&self) -> String {
+        "HuC1+RAM+Battery".to_string()
+    }
+
+    fn read_rom_raw(&self, address: u16) -> u8 {
+        *self.rom.get(address as usize).unwrap_or(&0xFF)
+    }
+
+    fn read_rom(&self, address: u16) -> u8 {
+        if address < 0x4000 {
+            self.rom[address as usize]
+        } else {
+            let bank = self.active_rom_bank as usize & (self.rom_banks as usize - 1);
+            self.rom[(bank * 0x4000) | (address as usize & 0x3FFF)]
+        }
+    }
+
+    fn write_rom(&mut self, address: u16, value: u8) {
+        match address {
+            0x0000..=0x1FFF => self.ir_mode = value & 0x0F == 0x0E,
+            0x2000..=0x3FFF => self.active_rom_bank = value & 0x3F,
+            0x4000..=0x5FFF => self.active_ram_bank = value & 0x03,
+            _ => {}
+        }
+    }
+
+    fn read_ram(&self, address: u16) -> u8 {
+        if self.ir_mode {
+            return self.infrared.read();
+        }
+
+        self.ram_index(address)
+            .and_then(|idx| self.ram.get(idx))
+            .copied()
+            .unwrap_or(0xFF)
+    }
+
+    fn write_ram(&mut self, address: u16, value: u8) {
+        if self.ir_mode {
+            self.infrared.write(value);
+            return;
+        }
+
+        if let Some(ram) = self
+            .ram_index(address)
+            .and_then(|idx| self.ram.get_mut(idx))
+        {
+            self.dirty |= *ram != value;
+            *ram = value;
+        }
+    }
+
+    fn has_battery(&self) -> bool {
+        true
+    }
+
+    fn take_dirty(&mut self) -> bool {
+        std::mem::take(&mut self.dirty)
+    }
+
+    fn connect_infrared(&mut self, port: Box<dyn InfraredPort>) {
+        self.infrared.connect(port);
+    }
+
+    fn load_ram(&mut self, data: &[u8]) {
+        let len = data.len().min(self.ram.len());
+        self.ram[..len].copy_from_slice(&data[..len]);
+    }
+
+    fn dump_ram(&self) -> Vec<u8> {
+        self.ram.clone()
+    }
+
+    fn rom_banks(&self) -> u16 {
+        self.rom_banks
+    }
+
+    fn ram_banks(&self) -> u8 {
+        self.ram_banks
+    }
+
+    fn rom_size(&self) -> u32 {
+        self.rom.len() as u32
+    }
+
+    fn ram_size(&self) -> u32 {
+        self.ram.len() as u32
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::infrared::infrared_link;
+    use crate::mbc::banked_rom;
+
+    fn huc1() -> HuC1 {
+        HuC1::new(banked_rom(64), 0x8000)
+    }
+
+    #[test]
+    fn test_banking() {
+        let mut mbc = huc1();
+        assert_eq!(mbc.read_rom(0x4000), 1);
+        mbc.write_rom(0x2000, 0x3F);
+        assert_eq!(mbc.read_rom(0x7FFF), 0x3F);
+
+        // RAM is mapped without an enable
+        mbc.write_rom(0x4000, 0x02);
+        mbc.write_ram(0xA010, 0x42);
+        assert_eq!(mbc.read_ram(0xA010), 0x42);
+        assert_eq!(mbc.dump_ram()[2 * 0x2000 + 0x10], 0x42);
+    }
+
+    #[test]
+    fn test_infrared() {
+        let (port_a, port_b) = infrared_link();
+        let mut a = huc1();
+        let mut b = huc1();
+        a.connect_infrared(Box::new(port_a));
+        b.connect_infrared(Box::new(port_b));
+
+        a.write_ram(0xA000, 0x55);
+        a.write_rom(0x0000, 0x0E);
+        b.write_rom(0x0000, 0x0E);
+        assert_eq!(b.read_ram(0xA000), 0xC0);
+
+        a.write_ram(0xA000, 0x01);
+        assert_eq!(b.read_ram(0xA000), 0xC1);
+        assert_eq!(a.read_ram(0xA000), 0xC0);
+        a.write_ram(0xA000, 0x00);
+        assert_eq!(b.read_ram(0xA000), 0xC0);
+
+        // Back to RAM, the IR writes left it untouched
+        a.write_rom(0x0000, 0x0A);
+        assert_eq!(a.read_ram(0xA000), 0x55);
+    }
+}
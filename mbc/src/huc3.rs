@@ -0,0 +1,474 @@
+use crate::infrared::{Infrared, InfraredPort};
+use crate::mbc::MBCTrait;
+use crate::mbc::{ram_banks, rom_banks};
+use crate::rtc::{SystemClock, TimeSource, CYCLES_PER_SECOND};
+
+/// Nibbles of RTC memory, the current time is copied to and from 0x00-0x06
+const RTC_MEMORY_SIZE: usize = 0x100;
+
+const MINUTES_PER_DAY: u64 = 24 * 60;
+
+/// Save footer: the RTC memory one nibble per byte, the minutes and days as
+/// little endian 32-bit values and a 64-bit Unix timestamp
+const HUC3_FOOTER_SIZE: usize = RTC_MEMORY_SIZE + 16;
+
+/// Clock counting minutes since midnight and days, with a 16-bit day counter.
+struct Clock {
+    minutes: u16,
+    days: u16,
+    /// Base clock cycles into the current minute
+    cycles: u32,
+    time_source: Box<dyn TimeSource>,
+}
+
+impl Clock {
+    fn new() -> Clock {
+        Clock {
+            minutes: 0,
+            days: 0,
+            cycles: 0,
+            time_source: Box::new(SystemClock),
+        }
+    }
+
+    fn tick(&mut self, cycles: u32) {
+        self.cycles += cycles;
+        while self.cycles >= CYCLES_PER_SECOND * 60 {
+            self.cycles -= CYCLES_PER_SECOND * 60;
+            self.advance(1);
+        }
+    }
+
+    fn advance(&mut self, minutes: u64) {
+        let total = self.minutes as u64 + minutes;
+        self.minutes = (total % MINUTES_PER_DAY) as u16;
+        self.days = self.days.wrapping_add((total / MINUTES_PER_DAY) as u16);
+    }
+
+    fn set(&mut self, minutes: u16, days: u16) {
+        self.minutes = (minutes as u64 % MINUTES_PER_DAY) as u16;
+        self.days = days;
+        self.cycles = 0;
+    }
+
+    /// Advances the clock by the wall-clock time elapsed since `timestamp`
+    fn catch_up(&mut self, timestamp: u64) {
+        let elapsed = self.time_source.now().saturating_sub(timestamp);
+        self.advance(elapsed / 60);
+        self.tick((elapsed % 60) as u32 * CYCLES_PER_SECOND);
+    }
+}
+
+/// What 0xA000-0xBFFF maps to, selected by writes to 0x0000-0x1FFF
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Mode {
+    RamReadOnly,
+    Ram,
+    /// Write a command to the RTC
+    Command,
+    /// Read back the last command and its result
+    Response,
+    /// Reads report whether the RTC is ready for a command
+    Semaphore,
+    Infrared,
+    Disabled,
+}
+
+pub struct HuC3 {
+    rom: Vec<u8>,
+    ram: Vec<u8>,
+    rom_banks: u16,
+    ram_banks: u8,
+    active_rom_bank: u8,
+    active_ram_bank: u8,
+    mode: Mode,
+    /// Battery backed RAM changed since the last save
+    dirty: bool,
+    clock: Clock,
+    memory: [u8; RTC_MEMORY_SIZE],
+    address: u8,
+    /// Last command in bits 4-6 and its result in bits 0-3
+    response: u8,
+    infrared: Infrared,
+}
+
+impl HuC3 {
+    pub fn new(rom: Vec<u8>, ram_size: u32) -> HuC3 {
+        let rom_banks = rom_banks(rom.len() as u32);
+        let ram_banks = ram_banks(ram_size).unwrap_or_else(|e| panic!("{}", e));
+
+        HuC3 {
+            rom,
+            ram: vec![0; ram_size as usize],
+            rom_banks,
+            ram_banks,
+            active_rom_bank: 1,
+            active_ram_bank: 0,
+            mode: Mode::Disabled,
+            dirty: false,
+            clock: Clock::new(),
+            memory: [0; RTC_MEMORY_SIZE],
+            address: 0,
+            response: 0,
+            infrared: Infrared::default(),
+        }
+    }
+
+    fn ram_index(&self, address: u16) -> Option<usize> {
+        if self.ram_banks == 0 {
+            return None;
+        }
+
+        let bank = (self.active_ram_bank % self.ram_banks) as usize;
+        Some((bank * 0x2000) | (address as usize & 0x1FFF))
+    }
+
+    /// Value stored as `count` nibbles of RTC memory from `start`, least significant first
+    fn read_nibbles(&self, start: usize, count: usize) -> u16 {
+        self.memory[start..start + count]
+            .iter()
+            .rev()
+            .fold(0, |value, &nibble| (value << 4) | nibble as u16)
+    }
+
+    fn write_nibbles(&mut self, start: usize, count: usize, value: u16) {
+        for (i, nibble) in self.memory[start..start + count].iter_mut().enumerate() {
+            *nibble = (value >> (i * 4)) as u8 & 0x0F;
+        }
+    }
+
+    fn command(&mut self, value: u8) {
+        let command = (value >> 4) & 0x07;
+        let argument = value & 0x0F;
+        let mut result = 0;
+
+        match command {
+            // Read a nibble and move to the next one
+            0x1 => {
+                result = self.memory[self.address as usize];
+                self.address = self.address.wrapping_add(1);
+            }
+            // Write a nibble and move to the next one
+            0x3 => {
+                let nibble = &mut self.memory[self.address as usize];
+                self.dirty |= *nibble != argument;
+                *nibble = argument;
+                self.address = self.address.wrapping_add(1);
+            }
+            0x4 => self.address = (self.address & 0xF0) | argument,
+            0x5 => self.address = (self.address & 0x0F) | (argument << 4),
+            0x6 => match argument {
+                // Copy the current time to memory
+                0x0 => {
+                    self.write_nibbles(0x00, 3, self.clock.minutes);
+                    self.write_nibbles(0x03, 4, self.clock.days);
+                }
+                // Set the clock from memory
+                0x1 => {
+                    let minutes = self.read_nibbles(0x00, 3);
+                    let days = self.read_nibbles(0x03, 4);
+                    self.clock.set(minutes, days);
+                }
+                // Status, always ready
+                0x2 => result = 0x01,
+                // Start the speaker, there is no audio output to play it on
+                0xE => log::debug!("HuC3 tone generator started"),
+                _ => log::warn!("Unknown HuC3 RTC command 0x{:02X}", value),
+            },
+            _ => log::warn!("Unknown HuC3 RTC command 0x{:02X}", value),
+        }
+
+        self.response = (command << 4) | result;
+    }
+}
+
+impl MBCTrait for HuC3 {
+    fn name(&self) -> String {
+        "HuC3+RAM+Battery+RTC".to_string()
+    }
+
+    fn read_rom_raw(&self, address: u16) -> u8 {
+        *self.rom.get(address as usize).unwrap_or(&0xFF)
+    }
+
+    fn read_rom(&self, address: u16) -> u8 {
+        if address < 0x4000 {
+            self.rom[address as usize]
+        } else {
+            let bank = self.active_rom_bank as usize & (self.rom_banks as usize - 1);
+            self.rom[(bank * 0x4000) | (address as usize & 0x3FFF)]
+        }
+    }
+
+    fn write_rom(&mut self, address: u16, value: u8) {
+        match address {
+            0x0000..=0x1FFF => {
+                self.mode = match value & 0x0F {
+                    0x0 => Mode::RamReadOnly,
+                    0xA => Mode::Ram,
+                    0xB => Mode::Command,
+                    0xC => Mode::Response,
+                    0xD => Mode::Semaphore,
+                    0xE => Mode::Infrared,
+                    _ => Mode::Disabled,
+                }
+            }
+            0x2000..=0x3FFF => self.active_rom_bank = value & 0x7F,
+            0x4000..=0x5FFF => self.active_ram_bank = value & 0x03,
+            _ => {}
+        }
+    }
+
+    fn read_ram(&self, address: u16) -> u8 {
+        match self.mode {
+            Mode::RamReadOnly | Mode::Ram => self
+                .ram_index(address)
+                .and_then(|idx| self.ram.get(idx))
+                .copied()
+                .unwrap_or(0xFF),
+            Mode::Response => 0x80 | self.response,
+            // Commands run immediately, the RTC is always ready
+            Mode::Semaphore => 0xFF,
+            Mode::Infrared => self.infrared.read(),
+            Mode::Command | Mode::Disabled => 0xFF,
+        }
+    }
+
+    fn write_ram(&mut self, address: u16, value: u8) {
+        match self.mode {
+            Mode::Ram => {
+                if let Some(ram) = self
+                    .ram_index(address)
+                    .and_then(|idx| self.ram.get_mut(idx))
+                {
+                    self.dirty |= *ram != value;
+                    *ram = value;
+                }
+            }
+            Mode::Command => self.command(value),
+            Mode::Infrared => self.infrared.write(value),
+            _ => {}
+        }
+    }
+
+    fn has_battery(&self) -> bool {
+        true
+    }
+
+    fn take_dirty(&mut self) -> bool {
+        std::mem::take(&mut self.dirty)
+    }
+
+    fn has_rtc(&self) -> bool {
+        true
+    }
+
+    fn tick(&mut self, cycles: u32) {
+        self.clock.tick(cycles);
+    }
+
+    fn save_footer(&self) -> Vec<u8> {
+        let mut footer = self.memory.to_vec();
+        footer.extend_from_slice(&(self.clock.minutes as u32).to_le_bytes());
+        footer.extend_from_slice(&(self.clock.days as u32).to_le_bytes());
+        footer.extend_from_slice(&self.clock.time_source.now().to_le_bytes());
+        footer
+    }
+
+    fn load_footer(&mut self, footer: &[u8]) {
+        if footer.len() != HUC3_FOOTER_SIZE {
+            log::warn!("Save file has no HuC3 RTC footer");
+            return;
+        }
+
+        let (memory, clock) = footer.split_at(RTC_MEMORY_SIZE);
+        for (nibble, value) in self.memory.iter_mut().zip(memory) {
+            *nibble = value & 0x0F;
+        }
+
+        let minutes = u32::from_le_bytes(clock[0..4].try_into().unwrap());
+        let days = u32::from_le_bytes(clock[4..8].try_into().unwrap());
+        self.clock.set(minutes as u16, days as u16);
+        self.clock
+            .catch_up(u64::from_le_bytes(clock[8..16].try_into().unwrap()));
+    }
+
+    fn connect_infrared(&mut self, port: Box<dyn InfraredPort>) {
+        self.infrared.connect(port);
+    }
+
+    fn load_ram(&mut self, data: &[u8]) {
+        let len = data.len().min(self.ram.len());
+        self.ram[..len].copy_from_slice(&data[..len]);
+    }
+
+    fn dump_ram(&self) -> Vec<u8> {
+        self.ram.clone()
+    }
+
+    fn rom_banks(&self) -> u16 {
+        self.rom_banks
+    }
+
+    fn ram_banks(&self) -> u8 {
+        self.ram_banks
+    }
+
+    fn rom_size(&self) -> u32 {
+        self.rom.len() as u32
+    }
+
+    fn ram_size(&self) -> u32 {
+        self.ram.len() as u32
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::infrared::infrared_link;
+    use crate::mbc::banked_rom;
+    use crate::rtc::FakeClock;
+
+    fn huc3() -> HuC3 {
+        HuC3::new(banked_rom(128), 0x8000)
+    }
+
+    fn command(mbc: &mut HuC3, value: u8) -> u8 {
+        mbc.write_rom(0x0000, 0x0B);
+        mbc.write_ram(0xA000, value);
+        mbc.write_rom(0x0000, 0x0C);
+        mbc.read_ram(0xA000)
+    }
+
+    fn set_address(mbc: &mut HuC3, address: u8) {
+        command(mbc, 0x40 | (address & 0x0F));
+        command(mbc, 0x50 | (address >> 4));
+    }
+
+    /// Sets the clock through RTC memory
+    fn set_time(mbc: &mut HuC3, minutes: u16, days: u16) {
+        set_address(mbc, 0x00);
+        let value = minutes as u32 | ((days as u32) << 12);
+        for i in 0..7 {
+            command(mbc, 0x30 | ((value >> (i * 4)) & 0x0F) as u8);
+        }
+        command(mbc, 0x61);
+    }
+
+    /// Minutes and days of the clock, read back through RTC memory
+    fn time(mbc: &mut HuC3) -> (u16, u16) {
+        command(mbc, 0x60);
+        set_address(mbc, 0x00);
+        let value = (0..7).fold(0, |value, i| {
+            value | (((command(mbc, 0x10) & 0x0F) as u32) << (i * 4))
+        });
+        ((value & 0xFFF) as u16, (value >> 12) as u16)
+    }
+
+    #[test]
+    fn test_modes() {
+        let mut mbc = huc3();
+        mbc.write_rom(0x2000, 0x45);
+        assert_eq!(mbc.read_rom(0x4000), 0x45);
+
+        mbc.write_ram(0xA000, 0x42);
+        mbc.write_rom(0x0000, 0x0A);
+        assert_eq!(mbc.read_ram(0xA000), 0x00);
+        mbc.write_rom(0x4000, 0x01);
+        mbc.write_ram(0xA000, 0x42);
+        assert!(mbc.take_dirty());
+
+        // Mode 0 maps RAM read-only
+        mbc.write_rom(0x0000, 0x00);
+        mbc.write_ram(0xA000, 0x24);
+        assert!(!mbc.take_dirty());
+        assert_eq!(mbc.read_ram(0xA000), 0x42);
+        assert_eq!(mbc.dump_ram()[0x2000], 0x42);
+
+        mbc.write_rom(0x0000, 0x0D);
+        assert_eq!(mbc.read_ram(0xA000) & 0x01, 0x01);
+    }
+
+    #[test]
+    fn test_rtc_memory() {
+        let mut mbc = huc3();
+        set_address(&mut mbc, 0x58);
+        command(&mut mbc, 0x37);
+        command(&mut mbc, 0x3A);
+
+        set_address(&mut mbc, 0x58);
+        assert_eq!(command(&mut mbc, 0x10), 0x97);
+        assert_eq!(command(&mut mbc, 0x10), 0x9A);
+    }
+
+    #[test]
+    fn test_rtc_time() {
+        let mut mbc = huc3();
+
+        // 23:59 on day 0x2AB
+        set_address(&mut mbc, 0x00);
+        for nibble in [0xF, 0x9, 0x5, 0xB, 0xA, 0x2, 0x0] {
+            command(&mut mbc, 0x30 | nibble);
+        }
+        command(&mut mbc, 0x61);
+
+        for _ in 0..(4_194_304 / 4) * 60 {
+            mbc.tick(4);
+        }
+
+        command(&mut mbc, 0x60);
+        set_address(&mut mbc, 0x00);
+        let nibbles: Vec<u8> = (0..7).map(|_| command(&mut mbc, 0x10) & 0x0F).collect();
+        assert_eq!(nibbles, [0, 0, 0, 0xC, 0xA, 0x2, 0]);
+
+        // The day counter wraps after 0xFFFF
+        set_time(&mut mbc, 23 * 60 + 59, 0xFFFF);
+        for _ in 0..(4_194_304 / 4) * 60 {
+            mbc.tick(4);
+        }
+        assert_eq!(time(&mut mbc), (0, 0));
+    }
+
+    #[test]
+    fn test_save_footer() {
+        let clock = FakeClock::new(1_000_000);
+        let mut mbc = huc3();
+        mbc.clock.time_source = Box::new(clock.clone());
+
+        set_address(&mut mbc, 0x58);
+        command(&mut mbc, 0x37);
+        assert!(mbc.take_dirty());
+        set_time(&mut mbc, 23 * 60, 0x1234);
+
+        let footer = mbc.save_footer();
+        assert_eq!(footer.len(), HUC3_FOOTER_SIZE);
+
+        // Two days, one hour and a few seconds pass before the save is loaded
+        clock.advance(2 * 86400 + 3600 + 5);
+        let mut loaded = huc3();
+        loaded.clock.time_source = Box::new(clock);
+        loaded.load_footer(&footer);
+
+        set_address(&mut loaded, 0x58);
+        assert_eq!(command(&mut loaded, 0x10), 0x97);
+        assert_eq!(time(&mut loaded), (0, 0x1237));
+        assert_eq!(loaded.clock.cycles, 5 * 4_194_304);
+    }
+
+    #[test]
+    fn test_infrared() {
+        let (port_a, port_b) = infrared_link();
+        let mut mbc = huc3();
+        let mut other = huc3();
+        mbc.connect_infrared(Box::new(port_a));
+        other.connect_infrared(Box::new(port_b));
+
+        mbc.write_rom(0x0000, 0x0E);
+        other.write_rom(0x0000, 0x0E);
+        other.write_ram(0xA000, 0x01);
+        assert_eq!(mbc.read_ram(0xA000), 0xC1);
+        other.write_ram(0xA000, 0x00);
+        assert_eq!(mbc.read_ram(0xA000), 0xC0);
+    }
+}
@@ -0,0 +1,66 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+/// Whatever the cartridge's IR LED and receiver are pointed at.
+pub trait InfraredPort: Send {
+    /// Called when the cartridge turns its LED on or off.
+    fn set_led(&mut self, on: bool);
+
+    /// Whether light reaches the cartridge's receiver.
+    fn receiving(&self) -> bool;
+}
+
+/// One of two IR ports facing each other, each receiver sees the other LED.
+#[derive(Debug)]
+pub struct InfraredLink {
+    leds: Arc<[AtomicBool; 2]>,
+    side: usize,
+}
+
+/// Points two cartridges at each other, connect each end to one emulator
+/// instance with [`MBC::connect_infrared`](crate::MBC::connect_infrared).
+pub fn infrared_link() -> (InfraredLink, InfraredLink) {
+    let leds = Arc::new([AtomicBool::new(false), AtomicBool::new(false)]);
+
+    (
+        InfraredLink {
+            leds: leds.clone(),
+            side: 0,
+        },
+        InfraredLink { leds, side: 1 },
+    )
+}
+
+impl InfraredPort for InfraredLink {
+    fn set_led(&mut self, on: bool) {
+        self.leds[self.side].store(on, Ordering::Relaxed);
+    }
+
+    fn receiving(&self) -> bool {
+        self.leds[1 - self.side].load(Ordering::Relaxed)
+    }
+}
+
+/// IR interface shared by the HuC1 and HuC3: bit 0 drives the LED on writes,
+/// reads return 0xC1 while light is received and 0xC0 otherwise.
+#[derive(Default)]
+pub(crate) struct Infrared {
+    port: Option<Box<dyn InfraredPort>>,
+}
+
+impl Infrared {
+    pub fn connect(&mut self, port: Box<dyn InfraredPort>) {
+        self.port = Some(port);
+    }
+
+    pub fn read(&self) -> u8 {
+        let receiving = self.port.as_ref().is_some_and(|port| port.receiving());
+        0xC0 | receiving as u8
+    }
+
+    pub fn write(&mut self, value: u8) {
+        if let Some(port) = self.port.as_mut() {
+            port.set_led(value & 0x01 != 0);
+        }
+    }
+}
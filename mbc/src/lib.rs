@@ -1,14 +1,31 @@
+pub mod error;
+pub mod header;
 pub mod licensee_codes;
 pub mod mbc;
 
+mod eeprom;
+mod huc1;
+mod huc3;
+pub mod infrared;
 mod mbc1;
+mod mbc2;
 mod mbc3;
 mod mbc5;
+mod mbc7;
 mod no_mbc;
+pub mod rtc;
 
+pub use error::CartridgeError;
+pub use header::{CartridgeHeader, CartridgeType};
+pub use huc1::HuC1;
+pub use huc3::HuC3;
+pub use infrared::{infrared_link, InfraredPort};
 pub use mbc::MBCTrait;
 pub use mbc::MBC;
 pub use mbc1::MBC1;
+pub use mbc2::MBC2;
 pub use mbc3::MBC3;
 pub use mbc5::MBC5;
+pub use mbc7::MBC7;
 pub use no_mbc::NoMBC;
+pub use rtc::{TimeSource, RTC};
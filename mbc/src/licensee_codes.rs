@@ -1,7 +1,9 @@
 use std::fmt;
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 #[repr(transparent)]
 pub struct OldLicenseeCode(pub u8);
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 #[repr(transparent)]
 pub struct NewLicenseeCode(pub [u8; 2]);
 
@@ -162,3 +164,76 @@ impl fmt::Display for OldLicenseeCode {
         }
     }
 }
+
+impl fmt::Display for NewLicenseeCode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match &self.0 {
+            b"00" => write!(f, "None"),
+            b"01" => write!(f, "Nintendo Research & Development 1"),
+            b"08" => write!(f, "Capcom"),
+            b"13" => write!(f, "EA (Electronic Arts)"),
+            b"18" => write!(f, "Hudson Soft"),
+            b"19" => write!(f, "B-AI"),
+            b"20" => write!(f, "KSS"),
+            b"22" => write!(f, "Planning Office WADA"),
+            b"24" => write!(f, "PCM Complete"),
+            b"25" => write!(f, "San-X"),
+            b"28" => write!(f, "Kemco"),
+            b"29" => write!(f, "SETA Corporation"),
+            b"30" => write!(f, "Viacom"),
+            b"31" => write!(f, "Nintendo"),
+            b"32" => write!(f, "Bandai"),
+            b"33" => write!(f, "Ocean Software/Acclaim Entertainment"),
+            b"34" => write!(f, "Konami"),
+            b"35" => write!(f, "HectorSoft"),
+            b"37" => write!(f, "Taito"),
+            b"38" => write!(f, "Hudson Soft"),
+            b"39" => write!(f, "Banpresto"),
+            b"41" => write!(f, "Ubi Soft"),
+            b"42" => write!(f, "Atlus"),
+            b"44" => write!(f, "Malibu Interactive"),
+            b"46" => write!(f, "Angel"),
+            b"47" => write!(f, "Bullet-Proof Software"),
+            b"49" => write!(f, "Irem"),
+            b"50" => write!(f, "Absolute"),
+            b"51" => write!(f, "Acclaim Entertainment"),
+            b"52" => write!(f, "Activision"),
+            b"53" => write!(f, "Sammy USA Corporation"),
+            b"54" => write!(f, "Konami"),
+            b"55" => write!(f, "Hi Tech Expressions"),
+            b"56" => write!(f, "LJN"),
+            b"57" => write!(f, "Matchbox"),
+            b"58" => write!(f, "Mattel"),
+            b"59" => write!(f, "Milton Bradley Company"),
+            b"60" => write!(f, "Titus Interactive"),
+            b"61" => write!(f, "Virgin Games Ltd."),
+            b"64" => write!(f, "Lucasfilm Games"),
+            b"67" => write!(f, "Ocean Software"),
+            b"69" => write!(f, "EA (Electronic Arts)"),
+            b"70" => write!(f, "Infogrames"),
+            b"71" => write!(f, "Interplay Entertainment"),
+            b"72" => write!(f, "Broderbund"),
+            b"73" => write!(f, "Sculptured Software"),
+            b"75" => write!(f, "The Sales Curve Limited"),
+            b"78" => write!(f, "THQ"),
+            b"79" => write!(f, "Accolade"),
+            b"80" => write!(f, "Misawa Entertainment"),
+            b"83" => write!(f, "lozc"),
+            b"86" => write!(f, "Tokuma Shoten"),
+            b"87" => write!(f, "Tsukuda Original"),
+            b"91" => write!(f, "Chunsoft Co."),
+            b"92" => write!(f, "Video System"),
+            b"93" => write!(f, "Ocean Software/Acclaim Entertainment"),
+            b"95" => write!(f, "Varie"),
+            b"96" => write!(f, "Yonezawa/s'pal"),
+            b"97" => write!(f, "Kaneko"),
+            b"99" => write!(f, "Pack-In-Video"),
+            b"9H" => write!(f, "Bottom Up"),
+            b"A4" => write!(f, "Konami (Yu-Gi-Oh!)"),
+            b"BL" => write!(f, "MTO"),
+            b"DK" => write!(f, "Kodansha"),
+
+            _ => write!(f, "Unknown"),
+        }
+    }
+}
@@ -1,22 +1,30 @@
+use crate::error::CartridgeError;
+use crate::header::{CartridgeHeader, CartridgeType, Mapper, HEADER_END};
+use crate::huc1::HuC1;
+use crate::huc3::HuC3;
+use crate::infrared::InfraredPort;
 use crate::mbc1::MBC1;
+use crate::mbc2::MBC2;
 use crate::mbc3::MBC3;
 use crate::mbc5::MBC5;
+use crate::mbc7::MBC7;
 use crate::no_mbc::NoMBC;
+use crate::rtc::RTC;
 
-pub fn rom_banks<T: TryFrom<u32>>(rom_size: u32) -> T {
-    let banks = rom_size / (16 * 1024);
+const ROM_BANK_SIZE: usize = 0x4000;
 
-    T::try_from(banks).unwrap_or_else(|_| panic!("Invalid ROM banks: {}", banks))
+pub fn rom_banks(rom_size: u32) -> u16 {
+    (rom_size / ROM_BANK_SIZE as u32).min(u16::MAX as u32) as u16
 }
 
-pub fn ram_banks(ram_size: u32) -> u8 {
+pub fn ram_banks(ram_size: u32) -> Result<u8, CartridgeError> {
     match ram_size {
-        0 => 0,
-        0x2000 => 1,
-        0x8000 => 4,
-        0x20000 => 16,
-        0x10000 => 8,
-        _ => panic!("Unsupported RAM size: {}", ram_size),
+        0 => Ok(0),
+        0x800 | 0x2000 => Ok(1),
+        0x8000 => Ok(4),
+        0x20000 => Ok(16),
+        0x10000 => Ok(8),
+        _ => Err(CartridgeError::UnsupportedRamSize(ram_size)),
     }
 }
 
@@ -32,12 +40,56 @@ pub trait MBCTrait: Send {
     fn write_ram(&mut self, address: u16, value: u8);
 
     fn has_battery(&self) -> bool;
+    /// Returns whether battery backed RAM or EEPROM contents changed since the
+    /// last call. Register writes and writes of the value already stored don't count.
+    fn take_dirty(&mut self) -> bool {
+        false
+    }
     fn dump_ram(&self) -> Vec<u8>;
+    /// Restores the RAM from a dump, extra bytes are ignored
+    fn load_ram(&mut self, data: &[u8]);
 
     fn has_rtc(&self) -> bool {
         false
     }
 
+    /// Advances the cartridge hardware by `cycles` cycles of the 4 MiHz base
+    /// clock, regardless of the CPU speed.
+    fn tick(&mut self, _cycles: u32) {}
+
+    /// State of the rumble motor, for carts that have one
+    fn rumble(&self) -> bool {
+        false
+    }
+
+    /// Tilts carts with an accelerometer by `x` g to the right and `y` g
+    /// towards the bottom of the console.
+    fn set_tilt(&mut self, _x: f32, _y: f32) {}
+
+    /// Points the IR LED and receiver of carts that have them at `port`
+    fn connect_infrared(&mut self, _port: Box<dyn InfraredPort>) {}
+
+    fn rtc(&self) -> Option<&RTC> {
+        None
+    }
+
+    fn rtc_mut(&mut self) -> Option<&mut RTC> {
+        None
+    }
+
+    /// State saved after the RAM in .sav files, the VBA-M/BGB RTC footer by default
+    fn save_footer(&self) -> Vec<u8> {
+        self.rtc()
+            .map_or_else(Vec::new, |rtc| rtc.save_footer().to_vec())
+    }
+
+    /// Restores the state written by [`MBCTrait::save_footer`]
+    fn load_footer(&mut self, footer: &[u8]) {
+        if let Some(rtc) = self.rtc_mut() {
+            rtc.load_footer(footer);
+        }
+    }
+
     fn rom_banks(&self) -> u16 {
         2
     }
@@ -50,23 +102,13 @@ pub trait MBCTrait: Send {
     fn ram_size(&self) -> u32;
 
     fn rom_name(&self) -> String {
-        const TITLE_START: u16 = 0x134;
-        const CGB_FLAG: u16 = 0x143;
-
-        let title_size = match self.read_rom(CGB_FLAG) & 0x80 {
-            0x80 | 0xC0 => 11,
-            _ => 16,
-        };
+        let header: Vec<u8> = (0..HEADER_END as u16)
+            .map(|address| self.read_rom_raw(address))
+            .collect();
 
-        let mut title = String::with_capacity(title_size);
-
-        for i in 0..title_size {
-            match self.read_rom_raw(TITLE_START + i as u16) {
-                0 => break,
-                c => title.push(c as char),
-            }
-        }
-        title
+        CartridgeHeader::parse(&header)
+            .map(|header| header.title)
+            .unwrap_or_default()
     }
 }
 
@@ -74,44 +116,130 @@ pub struct MBC {
     mbc: Box<dyn MBCTrait>,
 }
 
-fn get_mbc(rom: Vec<u8>) -> Box<dyn MBCTrait> {
-    let ty = rom[0x147];
-    let ram_size = match rom[0x149] {
-        0x00 => 0,
-        0x01 => 2 * 1024,
-        0x02 => 8 * 1024,
-        0x03 => 32 * 1024,
-        0x04 => 128 * 1024,
-        0x05 => 64 * 1024,
-        _ => panic!("Unsupported RAM size: {:02X}", rom[0x149]),
-    };
+/// Bank a flash cart maps `bank` to when only `banks` banks are populated.
+///
+/// Power of two sized chips wrap around, anything else is split into the largest
+/// power of two followed by the remainder, which gets mirrored on its own.
+fn mirrored_bank(bank: usize, banks: usize) -> usize {
+    if banks.is_power_of_two() {
+        return bank % banks;
+    }
 
-    match ty {
-        0x00 => Box::new(NoMBC::new(rom)),
-        0x01 => Box::new(MBC1::new(rom, 0, false)),
-        0x02 => Box::new(MBC1::new(rom, ram_size, true)),
-        0x03 => Box::new(MBC1::new(rom, ram_size, true)),
+    let low = 1 << banks.ilog2();
+    match bank % banks.next_power_of_two() {
+        bank if bank < low => bank,
+        bank => low + mirrored_bank(bank - low, banks - low),
+    }
+}
+
+/// Pads the ROM to a power of two number of banks, at least two, by mirroring
+/// the existing banks so the mappers can mask bank numbers safely.
+fn mirror_rom(mut rom: Vec<u8>) -> Vec<u8> {
+    let banks = rom.len().div_ceil(ROM_BANK_SIZE);
+    rom.resize(banks * ROM_BANK_SIZE, 0xFF);
 
-        0x0F => Box::new(MBC3::new(rom, ram_size, false, true)),
-        0x10 => Box::new(MBC3::new(rom, ram_size, true, true)),
-        0x11 => Box::new(MBC3::new(rom, ram_size, true, false)),
-        0x12 => Box::new(MBC3::new(rom, ram_size, true, false)),
-        0x13 => Box::new(MBC3::new(rom, ram_size, true, false)),
+    let total = banks.next_power_of_two().max(2);
+    if total != banks {
+        log::warn!("Mirroring {} ROM banks to {}", banks, total);
+    }
 
-        0x19 => Box::new(MBC5::new(rom, ram_size, false)),
-        0x1A => Box::new(MBC5::new(rom, ram_size, true)),
-        0x1B => Box::new(MBC5::new(rom, ram_size, true)),
-        0x1C => Box::new(MBC5::new(rom, ram_size, false)),
-        0x1D => Box::new(MBC5::new(rom, ram_size, true)),
-        0x1E => Box::new(MBC5::new(rom, ram_size, true)),
+    for bank in banks..total {
+        let start = mirrored_bank(bank, banks) * ROM_BANK_SIZE;
+        rom.extend_from_within(start..start + ROM_BANK_SIZE);
+    }
+    rom
+}
+
+fn get_mbc(mut rom: Vec<u8>) -> Result<Box<dyn MBCTrait>, CartridgeError> {
+    let header =
+        CartridgeHeader::parse(&rom).ok_or(CartridgeError::TruncatedRom { size: rom.len() })?;
+    let ty = header.cartridge_type;
+    let ram_size = match header.ram_size() {
+        Some(size) if ty.has_ram() => size,
+        Some(_) => 0,
+        None => return Err(CartridgeError::BadRamSize(header.ram_size_code)),
+    };
+    // The mappers below can't fail once the RAM size is known to be valid
+    ram_banks(ram_size)?;
+
+    let max_rom_size = match ty.mapper() {
+        Mapper::RomOnly => 0x8000,
+        Mapper::MBC2 => 0x4_0000,
+        Mapper::HuC1 => 0x10_0000,
+        Mapper::MBC1 | Mapper::MBC7 | Mapper::HuC3 => 0x20_0000,
+        // MBC30 only decodes 8 bits of bank number, larger dumps still load
+        Mapper::MBC3 | Mapper::MBC5 => 0x80_0000,
+        _ => return Err(CartridgeError::UnsupportedMapper(ty)),
+    };
+
+    let Some(header_rom_size) = header.rom_size() else {
+        return Err(CartridgeError::RomSizeMismatch {
+            header: None,
+            actual: rom.len(),
+        });
+    };
+    if header_rom_size != rom.len() as u32 {
+        log::warn!(
+            "ROM size {} doesn't match the header ({})",
+            rom.len(),
+            header_rom_size
+        );
+    }
 
-        _ => panic!("Unsupported MBC type: 0x{:02X}", ty),
+    // Oversized dumps keep what the mapper, or the header for oversized hacks, can address
+    let addressable = max_rom_size.max(header_rom_size as usize);
+    if rom.len() > addressable {
+        log::warn!("Truncating ROM from {} to {} bytes", rom.len(), addressable);
+        rom.truncate(addressable);
     }
+    let rom = mirror_rom(rom);
+
+    Ok(match ty {
+        CartridgeType::RomOnly => Box::new(NoMBC::new(rom)),
+
+        CartridgeType::MBC1 | CartridgeType::MBC1Ram | CartridgeType::MBC1RamBattery => {
+            Box::new(MBC1::new(rom, ram_size, ty.has_battery()))
+        }
+
+        CartridgeType::MBC2 | CartridgeType::MBC2Battery => {
+            Box::new(MBC2::new(rom, ty.has_battery()))
+        }
+
+        CartridgeType::MBC3TimerBattery
+        | CartridgeType::MBC3TimerRamBattery
+        | CartridgeType::MBC3
+        | CartridgeType::MBC3Ram
+        | CartridgeType::MBC3RamBattery => {
+            Box::new(MBC3::new(rom, ram_size, ty.has_battery(), ty.has_rtc()))
+        }
+
+        CartridgeType::MBC5
+        | CartridgeType::MBC5Ram
+        | CartridgeType::MBC5RamBattery
+        | CartridgeType::MBC5Rumble
+        | CartridgeType::MBC5RumbleRam
+        | CartridgeType::MBC5RumbleRamBattery => {
+            Box::new(MBC5::new(rom, ram_size, ty.has_battery(), ty.has_rumble()))
+        }
+
+        CartridgeType::MBC7SensorRumbleRamBattery => Box::new(MBC7::new(rom)),
+
+        CartridgeType::HuC1RamBattery => Box::new(HuC1::new(rom, ram_size)),
+
+        CartridgeType::HuC3 => Box::new(HuC3::new(rom, ram_size)),
+
+        _ => return Err(CartridgeError::UnsupportedMapper(ty)),
+    })
 }
 
 impl MBC {
+    /// Loads a ROM, panicking if it can't be loaded. See [`MBC::try_new`].
     pub fn new(rom: Vec<u8>) -> MBC {
-        MBC { mbc: get_mbc(rom) }
+        MBC::try_new(rom).unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    pub fn try_new(rom: Vec<u8>) -> Result<MBC, CartridgeError> {
+        Ok(MBC { mbc: get_mbc(rom)? })
     }
 
     pub fn empty() -> MBC {
@@ -156,10 +284,58 @@ impl MBC {
         self.mbc.has_rtc()
     }
 
+    pub fn tick(&mut self, cycles: u32) {
+        self.mbc.tick(cycles)
+    }
+
+    pub fn rumble(&self) -> bool {
+        self.mbc.rumble()
+    }
+
+    pub fn set_tilt(&mut self, x: f32, y: f32) {
+        self.mbc.set_tilt(x, y)
+    }
+
+    pub fn connect_infrared(&mut self, port: Box<dyn InfraredPort>) {
+        self.mbc.connect_infrared(port)
+    }
+
+    pub fn rtc(&self) -> Option<&RTC> {
+        self.mbc.rtc()
+    }
+
+    pub fn rtc_mut(&mut self) -> Option<&mut RTC> {
+        self.mbc.rtc_mut()
+    }
+
     pub fn dump_ram(&self) -> Vec<u8> {
         self.mbc.dump_ram()
     }
 
+    pub fn load_ram(&mut self, data: &[u8]) {
+        self.mbc.load_ram(data)
+    }
+
+    /// Returns whether battery backed RAM changed since the last call
+    pub fn take_ram_dirty(&mut self) -> bool {
+        self.mbc.take_dirty()
+    }
+
+    /// Contents of a .sav file: the RAM followed by the mapper's footer, e.g. the
+    /// VBA-M/BGB RTC footer
+    pub fn save_data(&self) -> Vec<u8> {
+        let mut data = self.mbc.dump_ram();
+        data.extend_from_slice(&self.mbc.save_footer());
+        data
+    }
+
+    /// Loads a .sav file, catching up the clock on the time since it was saved
+    pub fn load_save_data(&mut self, data: &[u8]) {
+        let ram_size = self.mbc.ram_size() as usize;
+        self.mbc.load_ram(&data[..ram_size.min(data.len())]);
+        self.mbc.load_footer(&data[ram_size.min(data.len())..]);
+    }
+
     pub fn rom_banks(&self) -> u16 {
         self.mbc.rom_banks()
     }
@@ -200,3 +376,151 @@ impl std::fmt::Debug for MBC {
             .finish()
     }
 }
+
+/// ROM where every byte holds the low bits of its bank number, except the
+/// second byte of each bank which holds the high bits
+#[cfg(test)]
+pub(crate) fn banked_rom(banks: usize) -> Vec<u8> {
+    (0..banks)
+        .flat_map(|bank| {
+            let mut data = [bank as u8; 0x4000];
+            data[1] = (bank >> 8) as u8;
+            data
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::header::{CARTRIDGE_TYPE, RAM_SIZE, ROM_SIZE};
+    use crate::rtc::RTC_FOOTER_SIZE;
+
+    fn rom(ty: u8, rom_size_code: u8, banks: usize) -> Vec<u8> {
+        let mut rom = banked_rom(banks);
+        rom[CARTRIDGE_TYPE] = ty;
+        rom[ROM_SIZE] = rom_size_code;
+        rom[RAM_SIZE] = 0x00;
+        rom
+    }
+
+    #[test]
+    fn test_mirrored_bank() {
+        assert_eq!(mirrored_bank(5, 4), 1);
+        assert_eq!(mirrored_bank(3, 3), 2);
+        // 1.5 MiB: the upper 512 KiB repeat
+        assert_eq!(mirrored_bank(95, 96), 95);
+        assert_eq!(mirrored_bank(96, 96), 64);
+        assert_eq!(mirrored_bank(127, 96), 95);
+        assert_eq!(mirrored_bank(130, 96), 2);
+    }
+
+    #[test]
+    fn test_errors() {
+        assert_eq!(
+            MBC::try_new(vec![0; 0x100]).unwrap_err(),
+            CartridgeError::TruncatedRom { size: 0x100 }
+        );
+
+        assert_eq!(
+            MBC::try_new(rom(0x20, 0x00, 2)).unwrap_err(),
+            CartridgeError::UnsupportedMapper(CartridgeType::MBC6)
+        );
+
+        let mut bad_ram = rom(0x03, 0x00, 2);
+        bad_ram[RAM_SIZE] = 0x07;
+        assert_eq!(
+            MBC::try_new(bad_ram).unwrap_err(),
+            CartridgeError::BadRamSize(0x07)
+        );
+
+        assert_eq!(
+            MBC::try_new(rom(0x01, 0x0B, 2)).unwrap_err(),
+            CartridgeError::RomSizeMismatch {
+                header: None,
+                actual: 0x8000
+            }
+        );
+
+        assert_eq!(
+            ram_banks(0x1000).unwrap_err(),
+            CartridgeError::UnsupportedRamSize(0x1000)
+        );
+    }
+
+    #[test]
+    fn test_oversized_rom() {
+        // 64 KiB dump of a 32 KiB ROM only cart, the extra banks are dropped
+        let mbc = MBC::try_new(rom(0x00, 0x00, 4)).unwrap();
+        assert_eq!(mbc.rom_size(), 0x8000);
+        assert_eq!(mbc.read_rom(0x0000), 0x00);
+        assert_eq!(mbc.read_rom(0x4000), 0x01);
+        assert_eq!(mbc.read_rom(0x7FFF), 0x01);
+
+        // MBC2 can only address 16 banks
+        let mut mbc = MBC::try_new(rom(0x05, 0x03, 32)).unwrap();
+        assert_eq!(mbc.rom_banks(), 16);
+        mbc.write_rom(0x2100, 0x0F);
+        assert_eq!(mbc.read_rom(0x4000), 0x0F);
+    }
+
+    #[test]
+    fn test_large_mbc1() {
+        // 4 MiB MBC1 hacks used to overflow the u8 bank count
+        let rom = rom(0x01, 0x07, 256);
+        let mut mbc = MBC::try_new(rom).unwrap();
+        assert_eq!(mbc.rom_banks(), 256);
+
+        mbc.write_rom(0x2000, 0x1F);
+        mbc.write_rom(0x4000, 0x03);
+        assert_eq!(mbc.read_rom(0x4000), 0x7F);
+    }
+
+    #[test]
+    fn test_save_data() {
+        let mut rom = rom(0x10, 0x01, 4);
+        rom[RAM_SIZE] = 0x02;
+
+        let mut mbc = MBC::try_new(rom.clone()).unwrap();
+        assert!(!mbc.take_ram_dirty());
+
+        // Writes while RAM is disabled, of the same value, or to the RTC don't change the save
+        mbc.write_ram(0xA000, 0x42);
+        mbc.write_rom(0x0000, 0x0A);
+        mbc.write_ram(0xA000, 0x00);
+        mbc.write_rom(0x4000, 0x09);
+        mbc.write_ram(0xA000, 0x03);
+        assert!(!mbc.take_ram_dirty());
+
+        mbc.write_rom(0x4000, 0x00);
+        mbc.write_ram(0xA000, 0x42);
+        assert!(mbc.take_ram_dirty());
+        assert!(!mbc.take_ram_dirty());
+
+        let data = mbc.save_data();
+        assert_eq!(data.len(), 0x2000 + RTC_FOOTER_SIZE);
+        assert_eq!(data[0], 0x42);
+
+        let mut loaded = MBC::try_new(rom).unwrap();
+        loaded.load_save_data(&data);
+        assert_eq!(loaded.dump_ram(), mbc.dump_ram());
+        assert_eq!(
+            loaded.rtc().unwrap().registers(),
+            mbc.rtc().unwrap().registers()
+        );
+        assert_eq!(loaded.rtc().unwrap().registers()[1], 3);
+    }
+
+    #[test]
+    fn test_undersized_rom() {
+        // 3 banks declared as 128 KiB, bank 3 mirrors bank 2
+        let mut mbc = MBC::try_new(rom(0x01, 0x02, 3)).unwrap();
+        assert_eq!(mbc.rom_banks(), 4);
+
+        mbc.write_rom(0x2000, 0x03);
+        assert_eq!(mbc.read_rom(0x4000), 0x02);
+        mbc.write_rom(0x2000, 0x05);
+        assert_eq!(mbc.read_rom(0x4000), 0x01);
+        assert_eq!(mbc.read_rom_raw(0xFFFF), 0x02);
+    }
+}
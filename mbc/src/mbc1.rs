@@ -1,3 +1,4 @@
+use crate::header::{LOGO, NINTENDO_LOGO};
 use crate::mbc::MBCTrait;
 use crate::mbc::{ram_banks, rom_banks};
 
@@ -5,41 +6,98 @@ pub struct MBC1 {
     rom: Vec<u8>,
     ram: Vec<u8>,
     has_battery: bool,
-    rom_banks: u8,
+    /// MBC1M: BANK2 is wired to ROM bank bits 4-5 instead of 5-6
+    multicart: bool,
+    rom_banks: u16,
     ram_banks: u8,
-    active_rom_bank: u8,
+    /// 5-bit BANK1 register, lower bits of the ROM bank number
+    bank1: u8,
+    /// 2-bit BANK2 register, upper ROM bank bits or RAM bank number
+    bank2: u8,
     rom_offsets: (i32, i32),
-    active_ram_bank: u8,
     ram_offset: i32,
     ram_enabled: bool,
+    /// Battery backed RAM changed since the last save
+    dirty: bool,
     banking_mode: bool,
 }
 
+/// MBC1M carts are 8 Mbit with a game in every 2 Mbit block, each with its
+/// own header.
+fn is_multicart(rom: &[u8]) -> bool {
+    if rom.len() != 0x10_0000 {
+        return false;
+    }
+
+    [0x10, 0x20, 0x30].iter().any(|bank| {
+        let logo = bank * 0x4000 + LOGO;
+        rom[logo..logo + NINTENDO_LOGO.len()] == NINTENDO_LOGO
+    })
+}
+
 impl MBC1 {
     pub fn new(rom: Vec<u8>, ram_size: u32, has_battery: bool) -> MBC1 {
         let rom_banks = rom_banks(rom.len() as u32);
-        let ram_banks = ram_banks(ram_size);
+        let ram_banks = ram_banks(ram_size).unwrap_or_else(|e| panic!("{}", e));
+        let multicart = is_multicart(&rom);
+
+        if multicart {
+            log::info!("Detected MBC1M multicart");
+        }
 
-        MBC1 {
+        let mut mbc = MBC1 {
             rom,
             ram: vec![0; ram_banks as usize * 0x2000],
             has_battery,
+            multicart,
             rom_banks,
             ram_banks,
-            active_rom_bank: 1,
+            bank1: 1,
+            bank2: 0,
             rom_offsets: (0x0000, 0x0000),
-            active_ram_bank: 0,
             ram_offset: -0xA000,
             ram_enabled: false,
+            dirty: false,
             banking_mode: false,
-        }
+        };
+        mbc.update_offsets();
+        mbc
+    }
+
+    /// Recomputes the ROM and RAM offsets from the bank registers.
+    ///
+    /// BANK2 always supplies the upper bits of the 0x4000-0x7FFF bank. In mode 1
+    /// it also switches the 0x0000-0x3FFF bank and the RAM bank.
+    fn update_offsets(&mut self) {
+        let (shift, mask) = match self.multicart {
+            true => (4, 0x0F),
+            false => (5, 0x1F),
+        };
+        let rom_mask = self.rom_banks as i32 - 1;
+
+        let upper = (self.bank2 as i32) << shift;
+        let lower_bank = match self.banking_mode {
+            true => upper & rom_mask,
+            false => 0,
+        };
+        let upper_bank = (upper | (self.bank1 & mask) as i32) & rom_mask;
+        self.rom_offsets = (lower_bank * 0x4000, (upper_bank - 1) * 0x4000);
+
+        let ram_bank = match self.banking_mode && self.ram_banks > 1 {
+            true => self.bank2 % self.ram_banks,
+            false => 0,
+        };
+        self.ram_offset = ram_bank as i32 * 0x2000 - 0xA000;
     }
 }
 
 impl MBCTrait for MBC1 {
     fn name(&self) -> String {
-        let mut name = "MBC1".to_string();
-        if self.ram.len() > 0 {
+        let mut name = match self.multicart {
+            true => "MBC1M".to_string(),
+            false => "MBC1".to_string(),
+        };
+        if !self.ram.is_empty() {
             name.push_str("+RAM");
         }
         if self.has_battery {
@@ -50,12 +108,12 @@ impl MBCTrait for MBC1 {
     }
 
     fn read_rom_raw(&self, address: u16) -> u8 {
-        self.rom[address as usize]
+        *self.rom.get(address as usize).unwrap_or(&0xFF)
     }
 
     fn read_rom(&self, address: u16) -> u8 {
         if address < 0x4000 {
-            self.rom[address as usize + self.rom_offsets.0 as usize]
+            self.rom[(address as i32 + self.rom_offsets.0) as usize]
         } else {
             self.rom[(address as i32 + self.rom_offsets.1) as usize]
         }
@@ -67,71 +125,38 @@ impl MBCTrait for MBC1 {
             0x0000..=0x1FFF => {
                 self.ram_enabled = value & 0x0F == 0x0A;
             }
-            // ROM Bank Number
+            // ROM Bank Number, 0 is turned into 1 before any masking
             0x2000..=0x3FFF => {
-                let mut value = value & 0x1F;
-                value = if value == 0 { 1 } else { value };
-
-                value = value & (self.rom_banks - 1);
-
-                value = if self.banking_mode {
-                    (self.active_rom_bank & 0b110_0000) | value
-                } else {
-                    value
+                self.bank1 = match value & 0x1F {
+                    0 => 1,
+                    value => value,
                 };
-                self.active_rom_bank = value;
-
-                self.rom_offsets.1 = (value as i32 - 1) * 0x4000;
+                self.update_offsets();
             }
             // RAM Bank Number or Upper Bits of ROM Bank Number
             0x4000..=0x5FFF => {
-                if self.ram_banks > 1 && self.banking_mode {
-                    let value = value & 0b11;
-                    self.active_ram_bank = value;
-                    self.ram_offset = (value as u16 * 0x2000) as i32 - 0xA000;
-
-                    log::debug!(
-                        "Setting RAM bank to {} - offset -0x{:04x}",
-                        value,
-                        -self.ram_offset
-                    );
-                } else if self.rom_banks > 32 {
-                    let value = value & 0b11;
-                    let value = (self.active_rom_bank & 0x1F) | (value << 5);
-                    let value = value & (self.rom_banks - 1);
-                    self.active_rom_bank = value;
-                    self.rom_offsets.1 = (value as i32 - 1) * 0x4000;
-
-                    log::debug!(
-                        "Setting ROM bank to {} - offset 0x{:04x}",
-                        self.active_rom_bank,
-                        self.rom_offsets.1
-                    );
-                }
+                self.bank2 = value & 0b11;
+                self.update_offsets();
             }
             // ROM/RAM Mode Select
             0x6000..=0x7FFF => {
                 self.banking_mode = (value & 1) != 0;
-                self.ram_offset = -0xA000;
-
-                if self.banking_mode {
-                    let active_rom_bank = self.active_rom_bank & 0b1_1111;
-                    self.rom_offsets.1 = (active_rom_bank as i32 - 1) * 0x4000;
-                }
-
-                log::debug!(
-                    "Setting banking mode to {} - active ROM bank {} - offset 0x{:04x}",
-                    self.banking_mode,
-                    self.active_rom_bank,
-                    self.rom_offsets.1
-                );
+                self.update_offsets();
             }
-            _ => {}
+            _ => return,
         }
+
+        log::debug!(
+            "MBC1 banks: mode {} - ROM offsets 0x{:05X}, 0x{:05X} - RAM offset -0x{:04X}",
+            self.banking_mode,
+            self.rom_offsets.0,
+            self.rom_offsets.1,
+            -self.ram_offset
+        );
     }
 
     fn read_ram(&self, address: u16) -> u8 {
-        if self.ram_enabled == false || self.ram_banks == 0 {
+        if !self.ram_enabled || self.ram_banks == 0 {
             return 0xFF;
         }
 
@@ -140,11 +165,12 @@ impl MBCTrait for MBC1 {
     }
 
     fn write_ram(&mut self, address: u16, value: u8) {
-        if self.ram_enabled == false || self.ram_banks == 0 {
+        if !self.ram_enabled || self.ram_banks == 0 {
             return;
         }
 
         let idx: usize = (address as i32 + self.ram_offset) as usize;
+        self.dirty |= self.has_battery && self.ram[idx] != value;
         self.ram[idx] = value;
     }
 
@@ -152,12 +178,21 @@ impl MBCTrait for MBC1 {
         self.has_battery
     }
 
+    fn take_dirty(&mut self) -> bool {
+        std::mem::take(&mut self.dirty)
+    }
+
+    fn load_ram(&mut self, data: &[u8]) {
+        let len = data.len().min(self.ram.len());
+        self.ram[..len].copy_from_slice(&data[..len]);
+    }
+
     fn dump_ram(&self) -> Vec<u8> {
         self.ram.clone()
     }
 
     fn rom_banks(&self) -> u16 {
-        self.rom_banks as u16
+        self.rom_banks
     }
 
     fn ram_banks(&self) -> u8 {
@@ -172,3 +207,84 @@ impl MBCTrait for MBC1 {
         self.ram.len() as u32
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mbc::banked_rom;
+
+    #[test]
+    fn test_rom_banking() {
+        let mut mbc = MBC1::new(banked_rom(128), 0, false);
+
+        mbc.write_rom(0x2000, 0x00);
+        assert_eq!(mbc.read_rom(0x4000), 0x01);
+        // Zero is checked on the 5-bit value, bank 0x20 becomes 0x21
+        mbc.write_rom(0x4000, 0x01);
+        assert_eq!(mbc.read_rom(0x4000), 0x21);
+        mbc.write_rom(0x2000, 0x25);
+        assert_eq!(mbc.read_rom(0x4000), 0x25);
+
+        // Only 64 banks: BANK2 bit 1 is ignored
+        let mut mbc = MBC1::new(banked_rom(64), 0, false);
+        mbc.write_rom(0x4000, 0x03);
+        mbc.write_rom(0x2000, 0x02);
+        assert_eq!(mbc.read_rom(0x4000), 0x22);
+    }
+
+    #[test]
+    fn test_mode_1() {
+        let mut mbc = MBC1::new(banked_rom(128), 0x8000, true);
+        mbc.write_rom(0x4000, 0x02);
+        assert_eq!(mbc.read_rom(0x0000), 0x00);
+
+        // Mode 1 maps bank 0x40 to 0x0000-0x3FFF
+        mbc.write_rom(0x6000, 0x01);
+        assert_eq!(mbc.read_rom(0x0000), 0x40);
+        assert_eq!(mbc.read_rom(0x3FFF), 0x40);
+        assert_eq!(mbc.read_rom(0x4000), 0x41);
+
+        // ... and selects the RAM bank
+        mbc.write_rom(0x0000, 0x0A);
+        mbc.write_ram(0xA000, 0x22);
+        mbc.write_rom(0x4000, 0x00);
+        assert_eq!(mbc.read_rom(0x0000), 0x00);
+        assert_eq!(mbc.read_ram(0xA000), 0x00);
+        assert_eq!(mbc.dump_ram()[2 * 0x2000], 0x22);
+
+        mbc.write_rom(0x6000, 0x00);
+        mbc.write_rom(0x4000, 0x02);
+        assert_eq!(mbc.read_rom(0x0000), 0x00);
+        assert_eq!(mbc.read_ram(0xA000), 0x00);
+    }
+
+    #[test]
+    fn test_multicart() {
+        let mut multicart = banked_rom(64);
+        for bank in [0x00, 0x10, 0x20, 0x30] {
+            let logo = bank * 0x4000 + LOGO;
+            multicart[logo..logo + NINTENDO_LOGO.len()].copy_from_slice(&NINTENDO_LOGO);
+        }
+
+        let mut mbc = MBC1::new(multicart, 0, false);
+        assert_eq!(mbc.name(), "MBC1M");
+
+        // BANK1 bit 4 is not connected, BANK2 selects the game
+        mbc.write_rom(0x2000, 0x12);
+        assert_eq!(mbc.read_rom(0x4000), 0x02);
+        mbc.write_rom(0x4000, 0x01);
+        assert_eq!(mbc.read_rom(0x4000), 0x12);
+
+        mbc.write_rom(0x6000, 0x01);
+        mbc.write_rom(0x4000, 0x02);
+        assert_eq!(mbc.read_rom(0x0000), 0x20);
+        assert_eq!(mbc.read_rom(0x4000), 0x22);
+
+        // Writing 0x10 gives the game's bank 0 instead of 1
+        mbc.write_rom(0x2000, 0x10);
+        assert_eq!(mbc.read_rom(0x4000), 0x20);
+
+        // A regular 8 Mbit ROM without the extra logos
+        assert_eq!(MBC1::new(banked_rom(64), 0, false).name(), "MBC1");
+    }
+}
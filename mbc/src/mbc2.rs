@@ -0,0 +1,173 @@
+use crate::mbc::rom_banks;
+use crate::mbc::MBCTrait;
+
+/// 512 half-bytes of RAM built into the mapper
+const RAM_SIZE: usize = 0x200;
+
+pub struct MBC2 {
+    rom: Vec<u8>,
+    ram: Vec<u8>,
+    has_battery: bool,
+    rom_banks: u16,
+    active_rom_bank: u8,
+    ram_enabled: bool,
+    /// Battery backed RAM changed since the last save
+    dirty: bool,
+}
+
+impl MBC2 {
+    pub fn new(rom: Vec<u8>, has_battery: bool) -> MBC2 {
+        let rom_banks = rom_banks(rom.len() as u32);
+
+        MBC2 {
+            rom,
+            ram: vec![0; RAM_SIZE],
+            has_battery,
+            rom_banks,
+            active_rom_bank: 1,
+            ram_enabled: false,
+            dirty: false,
+        }
+    }
+}
+
+impl MBCTrait for MBC2 {
+    fn name(&self) -> String {
+        let mut name = "MBC2".to_string();
+        if self.has_battery {
+            name.push_str("+Battery");
+        }
+
+        name
+    }
+
+    fn read_rom_raw(&self, address: u16) -> u8 {
+        *self.rom.get(address as usize).unwrap_or(&0xFF)
+    }
+
+    fn read_rom(&self, address: u16) -> u8 {
+        if address < 0x4000 {
+            self.rom[address as usize]
+        } else {
+            self.rom[self.active_rom_bank as usize * 0x4000 + (address as usize & 0x3FFF)]
+        }
+    }
+
+    fn write_rom(&mut self, address: u16, value: u8) {
+        match address {
+            // Bit 8 of the address selects between RAM enable and ROM bank number
+            0x0000..=0x3FFF if address & 0x100 == 0 => {
+                self.ram_enabled = value & 0x0F == 0x0A;
+            }
+            0x0000..=0x3FFF => {
+                let value = match value & 0x0F {
+                    0 => 1,
+                    value => value,
+                };
+                self.active_rom_bank = value & (self.rom_banks - 1) as u8;
+            }
+            _ => {}
+        }
+    }
+
+    fn read_ram(&self, address: u16) -> u8 {
+        if !self.ram_enabled {
+            return 0xFF;
+        }
+
+        // Only the lower nibble is connected, the upper one reads as 1s
+        0xF0 | self.ram[address as usize & (RAM_SIZE - 1)]
+    }
+
+    fn write_ram(&mut self, address: u16, value: u8) {
+        if !self.ram_enabled {
+            return;
+        }
+
+        let ram = &mut self.ram[address as usize & (RAM_SIZE - 1)];
+        self.dirty |= self.has_battery && *ram != value & 0x0F;
+        *ram = value & 0x0F;
+    }
+
+    fn has_battery(&self) -> bool {
+        self.has_battery
+    }
+
+    fn take_dirty(&mut self) -> bool {
+        std::mem::take(&mut self.dirty)
+    }
+
+    fn load_ram(&mut self, data: &[u8]) {
+        for (ram, value) in self.ram.iter_mut().zip(data) {
+            *ram = value & 0x0F;
+        }
+    }
+
+    fn dump_ram(&self) -> Vec<u8> {
+        self.ram.clone()
+    }
+
+    fn rom_banks(&self) -> u16 {
+        self.rom_banks
+    }
+
+    fn rom_size(&self) -> u32 {
+        self.rom.len() as u32
+    }
+
+    fn ram_size(&self) -> u32 {
+        RAM_SIZE as u32
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mbc::banked_rom;
+
+    fn mbc2() -> MBC2 {
+        let rom = banked_rom(16);
+        MBC2::new(rom, true)
+    }
+
+    #[test]
+    fn test_rom_banking() {
+        let mut mbc = mbc2();
+        assert_eq!(mbc.read_rom(0x4000), 1);
+
+        // Bit 8 set selects the ROM bank register, anywhere in 0x0000-0x3FFF
+        mbc.write_rom(0x2100, 0x05);
+        assert_eq!(mbc.read_rom(0x7FFF), 5);
+        mbc.write_rom(0x0100, 0xF3);
+        assert_eq!(mbc.read_rom(0x4000), 3);
+        mbc.write_rom(0x3F00, 0x00);
+        assert_eq!(mbc.read_rom(0x4000), 1);
+
+        // Bit 8 clear is RAM enable and leaves the bank alone
+        mbc.write_rom(0x2000, 0x05);
+        assert_eq!(mbc.read_rom(0x4000), 1);
+    }
+
+    #[test]
+    fn test_ram() {
+        let mut mbc = mbc2();
+        mbc.write_ram(0xA000, 0x0A);
+        assert_eq!(mbc.read_ram(0xA000), 0xFF);
+
+        mbc.write_rom(0x0000, 0x0A);
+        mbc.write_ram(0xA000, 0x5A);
+        assert_eq!(mbc.read_ram(0xA000), 0xFA);
+
+        // 512 half-bytes mirrored through 0xA000-0xBFFF
+        assert_eq!(mbc.read_ram(0xA200), 0xFA);
+        mbc.write_ram(0xBFFF, 0x03);
+        assert_eq!(mbc.read_ram(0xA1FF), 0xF3);
+        assert_eq!(mbc.dump_ram().len(), 0x200);
+
+        // Writing with bit 8 set doesn't touch RAM enable
+        mbc.write_rom(0x0100, 0x00);
+        assert_eq!(mbc.read_ram(0xA000), 0xFA);
+        mbc.write_rom(0x0000, 0x00);
+        assert_eq!(mbc.read_ram(0xA000), 0xFF);
+    }
+}
@@ -1,29 +1,34 @@
 use crate::mbc::MBCTrait;
 use crate::mbc::{ram_banks, rom_banks};
+use crate::rtc::RTC;
 
-struct RTC {
-    rtc_ram: [u8; 5],
-}
+/// RTC registers selected by writing 0x08-0x0C to the RAM bank register
+const RTC_SELECT: std::ops::RangeInclusive<u8> = 0x08..=0x0C;
 
 pub struct MBC3 {
     rom: Vec<u8>,
     ram: Vec<u8>,
     has_battery: bool,
-    rom_banks: u8,
+    /// MBC30: 8-bit ROM bank number and 8 RAM banks
+    is_mbc30: bool,
+    rom_banks: u16,
     ram_banks: u8,
     active_rom_bank: u8,
+    /// Raw value of the RAM bank register, 0x08-0x0C select an RTC register
     active_ram_bank: u8,
     ram_enabled: bool,
+    /// Battery backed RAM changed since the last save
+    dirty: bool,
     rtc: Option<RTC>,
 }
 
 impl MBC3 {
     pub fn new(rom: Vec<u8>, ram_size: u32, has_battery: bool, has_rtc: bool) -> MBC3 {
         let rom_banks = rom_banks(rom.len() as u32);
-        let ram_banks = ram_banks(ram_size);
+        let ram_banks = ram_banks(ram_size).unwrap_or_else(|e| panic!("{}", e));
 
         let rtc = match has_rtc {
-            true => Some(RTC { rtc_ram: [0; 5] }),
+            true => Some(RTC::new()),
             false => None,
         };
 
@@ -31,20 +36,34 @@ impl MBC3 {
             rom,
             ram: vec![0; ram_size as usize],
             has_battery,
+            is_mbc30: rom_banks > 128 || ram_banks > 4,
             rom_banks,
             ram_banks,
             active_rom_bank: 1,
             active_ram_bank: 0,
             ram_enabled: false,
-            rtc: rtc,
+            dirty: false,
+            rtc,
         }
     }
+
+    fn ram_index(&self, address: u16) -> Option<usize> {
+        if self.ram_banks == 0 {
+            return None;
+        }
+
+        let bank = (self.active_ram_bank % self.ram_banks) as usize;
+        Some((bank * 0x2000) | (address as usize & 0x1FFF))
+    }
 }
 
 impl MBCTrait for MBC3 {
     fn name(&self) -> String {
-        let mut name = "MBC3".to_string();
-        if self.ram.len() > 0 {
+        let mut name = match self.is_mbc30 {
+            true => "MBC30".to_string(),
+            false => "MBC3".to_string(),
+        };
+        if !self.ram.is_empty() {
             name.push_str("+RAM");
         }
         if self.has_battery {
@@ -57,11 +76,16 @@ impl MBCTrait for MBC3 {
     }
 
     fn read_rom_raw(&self, address: u16) -> u8 {
-        self.rom[address as usize]
+        *self.rom.get(address as usize).unwrap_or(&0xFF)
     }
 
     fn read_rom(&self, address: u16) -> u8 {
-        *self.rom.get(address as usize).unwrap_or(&0xFF)
+        if address < 0x4000 {
+            self.rom[address as usize]
+        } else {
+            let bank = self.active_rom_bank as usize & (self.rom_banks as usize - 1);
+            self.rom[(bank * 0x4000) | (address as usize & 0x3FFF)]
+        }
     }
 
     fn write_rom(&mut self, address: u16, value: u8) {
@@ -72,21 +96,26 @@ impl MBCTrait for MBC3 {
             }
             // ROM bank select
             0x2000..=0x3FFF => {
-                self.active_rom_bank = value & 0x7F;
+                self.active_rom_bank = match self.is_mbc30 {
+                    true => value,
+                    false => value & 0x7F,
+                };
                 if self.active_rom_bank == 0 {
                     self.active_rom_bank = 1;
                 }
             }
-            // RAM bank select
+            // RAM bank or RTC register select
             0x4000..=0x5FFF => {
-                self.active_ram_bank = value & 0x0F;
+                self.active_ram_bank = match self.is_mbc30 {
+                    true => value & 0x0F,
+                    false if RTC_SELECT.contains(&value) => value,
+                    false => value & 0x03,
+                };
             }
             // Latch clock data
             0x6000..=0x7FFF => {
                 if let Some(ref mut rtc) = self.rtc {
-                    if value & 0x01 == 0x01 {
-                        // rtc.latched_rtc = rtc.rtc_ram;
-                    }
+                    rtc.write_latch(value);
                 }
             }
             _ => {}
@@ -94,37 +123,78 @@ impl MBCTrait for MBC3 {
     }
 
     fn read_ram(&self, a: u16) -> u8 {
-        if self.ram_enabled == false {
+        if !self.ram_enabled {
             return 0xFF;
         }
 
-        let idx = self.active_ram_bank as usize * 0x2000 | (a as usize & 0x1FFF);
-        *self.ram.get(idx).unwrap_or(&0xFF)
+        if RTC_SELECT.contains(&self.active_ram_bank) {
+            return match self.rtc {
+                Some(ref rtc) => rtc.read(self.active_ram_bank),
+                None => 0xFF,
+            };
+        }
+
+        self.ram_index(a)
+            .and_then(|idx| self.ram.get(idx))
+            .copied()
+            .unwrap_or(0xFF)
     }
 
     fn write_ram(&mut self, a: u16, v: u8) {
-        if self.ram_enabled == false {
+        if !self.ram_enabled {
+            return;
+        }
+
+        if RTC_SELECT.contains(&self.active_ram_bank) {
+            if let Some(ref mut rtc) = self.rtc {
+                rtc.write(self.active_ram_bank, v);
+            }
             return;
         }
 
-        let idx = self.active_ram_bank as usize * 0x2000 | (a as usize & 0x1FFF);
-        self.ram[idx] = v;
+        if let Some(ram) = self.ram_index(a).and_then(|idx| self.ram.get_mut(idx)) {
+            self.dirty |= self.has_battery && *ram != v;
+            *ram = v;
+        }
     }
 
     fn has_battery(&self) -> bool {
         self.has_battery
     }
 
+    fn take_dirty(&mut self) -> bool {
+        std::mem::take(&mut self.dirty)
+    }
+
     fn has_rtc(&self) -> bool {
         self.rtc.is_some()
     }
 
+    fn tick(&mut self, cycles: u32) {
+        if let Some(ref mut rtc) = self.rtc {
+            rtc.tick(cycles);
+        }
+    }
+
+    fn rtc(&self) -> Option<&RTC> {
+        self.rtc.as_ref()
+    }
+
+    fn rtc_mut(&mut self) -> Option<&mut RTC> {
+        self.rtc.as_mut()
+    }
+
+    fn load_ram(&mut self, data: &[u8]) {
+        let len = data.len().min(self.ram.len());
+        self.ram[..len].copy_from_slice(&data[..len]);
+    }
+
     fn dump_ram(&self) -> Vec<u8> {
         self.ram.clone()
     }
 
     fn rom_banks(&self) -> u16 {
-        self.rom_banks as u16
+        self.rom_banks
     }
 
     fn ram_banks(&self) -> u8 {
@@ -139,3 +209,115 @@ impl MBCTrait for MBC3 {
         self.ram.len() as u32
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mbc::banked_rom;
+
+    #[test]
+    fn test_rom_banking() {
+        let mut mbc = MBC3::new(banked_rom(128), 0x8000, true, false);
+        assert_eq!(mbc.name(), "MBC3+RAM+Battery");
+        assert_eq!(mbc.read_rom(0x0000), 0);
+        assert_eq!(mbc.read_rom(0x4000), 1);
+
+        mbc.write_rom(0x2000, 0x42);
+        assert_eq!(mbc.read_rom(0x4000), 0x42);
+        assert_eq!(mbc.read_rom(0x7FFF), 0x42);
+        assert_eq!(mbc.read_rom(0x3FFF), 0);
+
+        // Bank 0 maps to bank 1, unlike MBC1 every other bank is reachable
+        mbc.write_rom(0x2000, 0x00);
+        assert_eq!(mbc.read_rom(0x4000), 1);
+        mbc.write_rom(0x2000, 0x20);
+        assert_eq!(mbc.read_rom(0x4000), 0x20);
+
+        // Only 7 bits are used
+        mbc.write_rom(0x2000, 0x85);
+        assert_eq!(mbc.read_rom(0x4000), 0x05);
+
+        // Banks past the end of a smaller ROM wrap around
+        let mut mbc = MBC3::new(banked_rom(32), 0, false, false);
+        mbc.write_rom(0x2000, 0x25);
+        assert_eq!(mbc.read_rom(0x4000), 0x05);
+    }
+
+    #[test]
+    fn test_ram_banking() {
+        let mut mbc = MBC3::new(banked_rom(4), 0x8000, true, false);
+        assert_eq!(mbc.ram_banks(), 4);
+
+        mbc.write_rom(0x0000, 0x0A);
+        for bank in 0..4 {
+            mbc.write_rom(0x4000, bank);
+            mbc.write_ram(0xA000, 0x10 + bank);
+        }
+        for bank in 0..4 {
+            mbc.write_rom(0x4000, bank);
+            assert_eq!(mbc.read_ram(0xA000), 0x10 + bank);
+        }
+
+        // No RTC: the RTC registers read open bus
+        mbc.write_rom(0x4000, 0x08);
+        assert_eq!(mbc.read_ram(0xA000), 0xFF);
+
+        mbc.write_rom(0x0000, 0x00);
+        mbc.write_rom(0x4000, 0x00);
+        assert_eq!(mbc.read_ram(0xA000), 0xFF);
+    }
+
+    #[test]
+    fn test_rtc_select() {
+        let mut mbc = MBC3::new(banked_rom(4), 0x2000, true, true);
+        assert!(mbc.has_rtc());
+        mbc.write_rom(0x0000, 0x0A);
+        mbc.write_ram(0xA000, 0x55);
+
+        for (register, value) in (0x08..=0x0C).zip([12, 34, 5, 0xFF, 0xC1]) {
+            mbc.write_rom(0x4000, register);
+            mbc.write_ram(0xA000, value);
+        }
+        mbc.write_rom(0x6000, 0x00);
+        mbc.write_rom(0x6000, 0x01);
+        for (register, value) in (0x08..=0x0C).zip([12, 34, 5, 0xFF, 0xC1]) {
+            mbc.write_rom(0x4000, register);
+            assert_eq!(mbc.read_ram(0xBFFF), value);
+        }
+
+        mbc.write_rom(0x4000, 0x00);
+        assert_eq!(mbc.read_ram(0xA000), 0x55);
+    }
+
+    #[test]
+    fn test_rtc_tick() {
+        let mut mbc = MBC3::new(banked_rom(4), 0, true, true);
+        mbc.write_rom(0x0000, 0x0A);
+        mbc.write_rom(0x4000, 0x08);
+
+        for _ in 0..(4_194_304 / 4) * 3 {
+            mbc.tick(4);
+        }
+        assert_eq!(mbc.read_ram(0xA000), 0);
+        mbc.write_rom(0x6000, 0x00);
+        mbc.write_rom(0x6000, 0x01);
+        assert_eq!(mbc.read_ram(0xA000), 3);
+    }
+
+    #[test]
+    fn test_mbc30() {
+        let mut mbc = MBC3::new(banked_rom(256), 0x10000, true, true);
+        assert_eq!(mbc.name(), "MBC30+RAM+Battery+RTC");
+        assert_eq!(mbc.ram_banks(), 8);
+
+        mbc.write_rom(0x2000, 0xFF);
+        assert_eq!(mbc.read_rom(0x4000), 0xFF);
+        mbc.write_rom(0x2000, 0x85);
+        assert_eq!(mbc.read_rom(0x4000), 0x85);
+
+        mbc.write_rom(0x0000, 0x0A);
+        mbc.write_rom(0x4000, 0x07);
+        mbc.write_ram(0xA123, 0x77);
+        assert_eq!(mbc.dump_ram()[7 * 0x2000 + 0x123], 0x77);
+    }
+}
@@ -5,30 +5,38 @@ pub struct MBC5 {
     rom: Vec<u8>,
     ram: Vec<u8>,
     has_battery: bool,
+    has_rumble: bool,
+    /// Rumble motor, driven by bit 3 of the RAM bank register on rumble carts
+    motor_on: bool,
     rom_banks: u16,
     ram_banks: u8,
     active_rom_back: u16,
     rom_upper_bank_offset: i32,
     active_ram_back: u8,
     ram_enabled: bool,
+    /// Battery backed RAM changed since the last save
+    dirty: bool,
     ram_offset: i32,
 }
 
 impl MBC5 {
-    pub fn new(rom: Vec<u8>, ram_size: u32, has_battery: bool) -> MBC5 {
+    pub fn new(rom: Vec<u8>, ram_size: u32, has_battery: bool, has_rumble: bool) -> MBC5 {
         let rom_banks = rom_banks(rom.len() as u32);
-        let ram_banks = ram_banks(ram_size);
+        let ram_banks = ram_banks(ram_size).unwrap_or_else(|e| panic!("{}", e));
 
         MBC5 {
             rom,
             ram: vec![0; ram_size as usize],
             has_battery,
+            has_rumble,
+            motor_on: false,
             rom_banks,
             ram_banks,
             active_rom_back: 1,
             rom_upper_bank_offset: 0,
             active_ram_back: 0,
             ram_enabled: false,
+            dirty: false,
             ram_offset: -0xA000,
         }
     }
@@ -37,7 +45,10 @@ impl MBC5 {
 impl MBCTrait for MBC5 {
     fn name(&self) -> String {
         let mut name = "MBC5".to_string();
-        if self.ram.len() > 0 {
+        if self.has_rumble {
+            name.push_str("+Rumble");
+        }
+        if !self.ram.is_empty() {
             name.push_str("+RAM");
         }
         if self.has_battery {
@@ -48,7 +59,7 @@ impl MBCTrait for MBC5 {
     }
 
     fn read_rom_raw(&self, address: u16) -> u8 {
-        self.rom[address as usize]
+        *self.rom.get(address as usize).unwrap_or(&0xFF)
     }
 
     fn read_rom(&self, address: u16) -> u8 {
@@ -73,13 +84,20 @@ impl MBCTrait for MBC5 {
             }
             // ROM bank select ( 9th bit )
             0x3000..=0x3FFF => {
-                self.active_rom_back = (self.active_rom_back & 0xFF) | (value as u16 & 0b1) << 1;
+                self.active_rom_back = (self.active_rom_back & 0xFF) | (value as u16 & 0b1) << 8;
                 self.active_rom_back &= self.rom_banks - 1;
                 self.rom_upper_bank_offset = (self.active_rom_back as i32 - 1) * 0x4000;
             }
-            // RAM bank select
+            // RAM bank select, bit 3 is wired to the motor on rumble carts
             0x4000..=0x5FFF => {
-                self.active_ram_back = (value & 0x0F) % self.ram_banks;
+                let value = if self.has_rumble {
+                    self.motor_on = value & 0x08 != 0;
+                    value & 0x07
+                } else {
+                    value & 0x0F
+                };
+
+                self.active_ram_back = value % self.ram_banks.max(1);
                 self.ram_offset = self.active_ram_back as i32 * 0x2000 - 0xA000;
             }
 
@@ -88,27 +106,43 @@ impl MBCTrait for MBC5 {
     }
 
     fn read_ram(&self, address: u16) -> u8 {
-        if self.ram_enabled == false {
+        if !self.ram_enabled {
             return 0xFF;
         }
 
         let idx = (address as i32 + self.ram_offset) as usize;
-        self.ram[idx]
+        *self.ram.get(idx).unwrap_or(&0xFF)
     }
 
     fn write_ram(&mut self, address: u16, value: u8) {
-        if self.ram_enabled == false {
+        if !self.ram_enabled {
             return;
         }
 
-        let idx = self.active_ram_back as usize * 0x2000 | (address as usize & 0x1FFF);
-        self.ram[idx] = value;
+        let idx = (address as i32 + self.ram_offset) as usize;
+        if let Some(ram) = self.ram.get_mut(idx) {
+            self.dirty |= self.has_battery && *ram != value;
+            *ram = value;
+        }
     }
 
     fn has_battery(&self) -> bool {
         self.has_battery
     }
 
+    fn take_dirty(&mut self) -> bool {
+        std::mem::take(&mut self.dirty)
+    }
+
+    fn rumble(&self) -> bool {
+        self.motor_on
+    }
+
+    fn load_ram(&mut self, data: &[u8]) {
+        let len = data.len().min(self.ram.len());
+        self.ram[..len].copy_from_slice(&data[..len]);
+    }
+
     fn dump_ram(&self) -> Vec<u8> {
         self.ram.clone()
     }
@@ -129,3 +163,50 @@ impl MBCTrait for MBC5 {
         self.ram.len() as u32
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mbc::banked_rom;
+
+    #[test]
+    fn test_rom_banking() {
+        let mut mbc = MBC5::new(banked_rom(512), 0, false, false);
+
+        // Bank 0 can be mapped to 0x4000-0x7FFF
+        mbc.write_rom(0x2000, 0x00);
+        assert_eq!(mbc.read_rom(0x4000), 0x00);
+
+        mbc.write_rom(0x2000, 0x34);
+        mbc.write_rom(0x3000, 0x01);
+        assert_eq!(mbc.read_rom(0x4000), 0x34);
+        assert_eq!(mbc.read_rom(0x4001), 0x01);
+
+        mbc.write_rom(0x3000, 0x00);
+        assert_eq!(mbc.read_rom(0x4001), 0x00);
+    }
+
+    #[test]
+    fn test_rumble() {
+        let mut mbc = MBC5::new(banked_rom(4), 0x8000, true, true);
+        assert_eq!(mbc.name(), "MBC5+Rumble+RAM+Battery");
+        mbc.write_rom(0x0000, 0x0A);
+
+        mbc.write_rom(0x4000, 0x0B);
+        assert!(mbc.rumble());
+        mbc.write_ram(0xA000, 0x42);
+        assert_eq!(mbc.dump_ram()[3 * 0x2000], 0x42);
+
+        mbc.write_rom(0x4000, 0x03);
+        assert!(!mbc.rumble());
+        assert_eq!(mbc.read_ram(0xA000), 0x42);
+
+        // Without a motor bit 3 selects the RAM bank
+        let mut mbc = MBC5::new(banked_rom(4), 0x20000, true, false);
+        mbc.write_rom(0x0000, 0x0A);
+        mbc.write_rom(0x4000, 0x08);
+        mbc.write_ram(0xA000, 0x42);
+        assert!(!mbc.rumble());
+        assert_eq!(mbc.dump_ram()[8 * 0x2000], 0x42);
+    }
+}
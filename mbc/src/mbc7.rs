@@ -0,0 +1,266 @@
+use crate::eeprom::{Eeprom, EEPROM_SIZE};
+use crate::mbc::rom_banks;
+use crate::mbc::MBCTrait;
+
+/// Accelerometer reading when level, and the change for a tilt of 1 g
+const ACCEL_CENTER: f32 = 0x81D0 as f32;
+const ACCEL_PER_G: f32 = 0x70 as f32;
+
+/// Value the accelerometer registers hold after an erase, until the next latch
+const ACCEL_ERASED: u16 = 0x8000;
+
+pub struct MBC7 {
+    rom: Vec<u8>,
+    rom_banks: u16,
+    active_rom_bank: u8,
+    /// RAM area needs 0x0A written to 0x0000-0x1FFF and 0x40 to 0x4000-0x5FFF
+    ram_enabled: [bool; 2],
+    /// Tilt set by the frontend, in g
+    tilt: (f32, f32),
+    accel_x: u16,
+    accel_y: u16,
+    /// An erase must come before each latch
+    latch_ready: bool,
+    eeprom: Eeprom,
+}
+
+impl MBC7 {
+    pub fn new(rom: Vec<u8>) -> MBC7 {
+        let rom_banks = rom_banks(rom.len() as u32);
+
+        MBC7 {
+            rom,
+            rom_banks,
+            active_rom_bank: 1,
+            ram_enabled: [false; 2],
+            tilt: (0.0, 0.0),
+            accel_x: ACCEL_ERASED,
+            accel_y: ACCEL_ERASED,
+            latch_ready: false,
+            eeprom: Eeprom::new(),
+        }
+    }
+
+    fn ram_enabled(&self) -> bool {
+        self.ram_enabled == [true; 2]
+    }
+
+    fn accel_value(tilt: f32) -> u16 {
+        (ACCEL_CENTER + ACCEL_PER_G * tilt.clamp(-2.0, 2.0)) as u16
+    }
+}
+
+impl MBCTrait for MBC7 {
+    fn name(&self) -> String {
+        "MBC7+Sensor+EEPROM".to_string()
+    }
+
+    fn read_rom_raw(&self, address: u16) -> u8 {
+        *self.rom.get(address as usize).unwrap_or(&0xFF)
+    }
+
+    fn read_rom(&self, address: u16) -> u8 {
+        if address < 0x4000 {
+            self.rom[address as usize]
+        } else {
+            let bank = self.active_rom_bank as usize & (self.rom_banks as usize - 1);
+            self.rom[(bank * 0x4000) | (address as usize & 0x3FFF)]
+        }
+    }
+
+    fn write_rom(&mut self, address: u16, value: u8) {
+        match address {
+            0x0000..=0x1FFF => self.ram_enabled[0] = value == 0x0A,
+            // Bank 0 can be mapped here as on MBC5
+            0x2000..=0x3FFF => self.active_rom_bank = value,
+            0x4000..=0x5FFF => self.ram_enabled[1] = value == 0x40,
+            _ => {}
+        }
+    }
+
+    fn read_ram(&self, address: u16) -> u8 {
+        // Only 0xA000-0xAFFF is decoded, bits 4-7 select the register
+        if !self.ram_enabled() || address >= 0xB000 {
+            return 0xFF;
+        }
+
+        match (address >> 4) & 0x0F {
+            0x2 => self.accel_x as u8,
+            0x3 => (self.accel_x >> 8) as u8,
+            0x4 => self.accel_y as u8,
+            0x5 => (self.accel_y >> 8) as u8,
+            0x6 => 0x00,
+            0x8 => self.eeprom.read(),
+            _ => 0xFF,
+        }
+    }
+
+    fn write_ram(&mut self, address: u16, value: u8) {
+        if !self.ram_enabled() || address >= 0xB000 {
+            return;
+        }
+
+        match (address >> 4) & 0x0F {
+            0x0 if value == 0x55 => {
+                self.accel_x = ACCEL_ERASED;
+                self.accel_y = ACCEL_ERASED;
+                self.latch_ready = true;
+            }
+            0x1 if value == 0xAA && self.latch_ready => {
+                self.accel_x = MBC7::accel_value(self.tilt.0);
+                self.accel_y = MBC7::accel_value(self.tilt.1);
+                self.latch_ready = false;
+            }
+            0x8 => self.eeprom.write(value),
+            _ => {}
+        }
+    }
+
+    fn has_battery(&self) -> bool {
+        true
+    }
+
+    fn take_dirty(&mut self) -> bool {
+        self.eeprom.take_dirty()
+    }
+
+    fn set_tilt(&mut self, x: f32, y: f32) {
+        self.tilt = (x, y);
+    }
+
+    fn load_ram(&mut self, data: &[u8]) {
+        self.eeprom.load(data);
+    }
+
+    fn dump_ram(&self) -> Vec<u8> {
+        self.eeprom.dump()
+    }
+
+    fn rom_banks(&self) -> u16 {
+        self.rom_banks
+    }
+
+    fn rom_size(&self) -> u32 {
+        self.rom.len() as u32
+    }
+
+    fn ram_size(&self) -> u32 {
+        EEPROM_SIZE as u32
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mbc::banked_rom;
+
+    const CS: u8 = 0x80;
+    const CLK: u8 = 0x40;
+    const DI: u8 = 0x02;
+
+    fn mbc7() -> MBC7 {
+        let mut mbc = MBC7::new(banked_rom(64));
+        mbc.write_rom(0x0000, 0x0A);
+        mbc.write_rom(0x4000, 0x40);
+        mbc
+    }
+
+    /// Clocks `count` bits of `value` into the EEPROM, MSB first, and returns
+    /// the DO bits read back after each rising edge
+    fn shift(mbc: &mut MBC7, value: u32, count: u32) -> u32 {
+        let mut out = 0;
+        for bit in (0..count).rev() {
+            let di = if (value >> bit) & 1 != 0 { DI } else { 0 };
+            mbc.write_ram(0xA080, CS | di);
+            mbc.write_ram(0xA080, CS | CLK | di);
+            out = (out << 1) | (mbc.read_ram(0xA080) & 1) as u32;
+        }
+        out
+    }
+
+    /// Runs a command: start bit, opcode and address, then `data_bits` more bits
+    fn command(mbc: &mut MBC7, opcode: u32, address: u32, data: u32, data_bits: u32) -> u32 {
+        mbc.write_ram(0xA080, 0x00);
+        mbc.write_ram(0xA080, CS);
+        shift(mbc, (1 << 10) | (opcode << 8) | address, 11);
+        let out = shift(mbc, data, data_bits);
+        mbc.write_ram(0xA080, 0x00);
+        out
+    }
+
+    #[test]
+    fn test_enable() {
+        let mut mbc = MBC7::new(banked_rom(64));
+        mbc.write_rom(0x2000, 0x21);
+        assert_eq!(mbc.read_rom(0x4000), 0x21);
+
+        mbc.write_rom(0x0000, 0x0A);
+        assert_eq!(mbc.read_ram(0xA060), 0xFF);
+        mbc.write_rom(0x4000, 0x40);
+        assert_eq!(mbc.read_ram(0xA060), 0x00);
+        assert_eq!(mbc.read_ram(0xB060), 0xFF);
+    }
+
+    #[test]
+    fn test_accelerometer() {
+        let mut mbc = mbc7();
+        mbc.set_tilt(1.0, -0.5);
+
+        // Latching without an erase first does nothing
+        mbc.write_ram(0xA010, 0xAA);
+        assert_eq!(mbc.read_ram(0xA020), 0x00);
+        assert_eq!(mbc.read_ram(0xA030), 0x80);
+
+        mbc.write_ram(0xA000, 0x55);
+        mbc.write_ram(0xA010, 0xAA);
+        let x = u16::from_le_bytes([mbc.read_ram(0xA020), mbc.read_ram(0xA030)]);
+        let y = u16::from_le_bytes([mbc.read_ram(0xA040), mbc.read_ram(0xA050)]);
+        assert_eq!(x, 0x81D0 + 0x70);
+        assert_eq!(y, 0x81D0 - 0x38);
+
+        // The value stays latched until the next erase
+        mbc.set_tilt(0.0, 0.0);
+        mbc.write_ram(0xA010, 0xAA);
+        assert_eq!(mbc.read_ram(0xA020), 0x40);
+        mbc.write_ram(0xA000, 0x55);
+        assert_eq!(mbc.read_ram(0xA020), 0x00);
+
+        // Latching the accelerometer leaves the EEPROM alone
+        assert!(!mbc.take_dirty());
+    }
+
+    #[test]
+    fn test_eeprom() {
+        let mut mbc = mbc7();
+        assert_eq!(mbc.read_ram(0xA080) & 1, 1);
+
+        // Writes are ignored until EWEN
+        command(&mut mbc, 0b01, 0x05, 0x1234, 16);
+        assert_eq!(command(&mut mbc, 0b10, 0x05, 0, 16), 0xFFFF);
+
+        command(&mut mbc, 0b00, 0xC0, 0, 0);
+        assert!(!mbc.take_dirty());
+        command(&mut mbc, 0b01, 0x05, 0x1234, 16);
+        assert!(mbc.take_dirty());
+        command(&mut mbc, 0b01, 0x06, 0xABCD, 16);
+        assert_eq!(command(&mut mbc, 0b10, 0x05, 0, 16), 0x1234);
+
+        // Reads continue with the next word
+        assert_eq!(command(&mut mbc, 0b10, 0x05, 0, 32), 0x1234_ABCD);
+
+        command(&mut mbc, 0b11, 0x05, 0, 0);
+        assert_eq!(command(&mut mbc, 0b10, 0x05, 0, 16), 0xFFFF);
+
+        assert_eq!(mbc.ram_size(), 0x100);
+        assert_eq!(&mbc.dump_ram()[0x0C..0x0E], &[0xCD, 0xAB]);
+
+        // EWDS, then WRAL is ignored
+        command(&mut mbc, 0b00, 0x00, 0, 0);
+        command(&mut mbc, 0b00, 0x40, 0x5555, 16);
+        assert_eq!(command(&mut mbc, 0b10, 0x06, 0, 16), 0xABCD);
+
+        let mut other = mbc7();
+        other.load_ram(&mbc.dump_ram());
+        assert_eq!(command(&mut other, 0b10, 0x06, 0, 16), 0xABCD);
+    }
+}
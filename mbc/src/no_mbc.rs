@@ -16,11 +16,11 @@ impl MBCTrait for NoMBC {
     }
 
     fn read_rom_raw(&self, address: u16) -> u8 {
-        self.rom[address as usize]
+        *self.rom.get(address as usize).unwrap_or(&0xFF)
     }
 
     fn read_rom(&self, address: u16) -> u8 {
-        self.rom[address as usize]
+        *self.rom.get(address as usize).unwrap_or(&0xFF)
     }
 
     fn write_rom(&mut self, _address: u16, _value: u8) {
@@ -39,6 +39,8 @@ impl MBCTrait for NoMBC {
         false
     }
 
+    fn load_ram(&mut self, _data: &[u8]) {}
+
     fn dump_ram(&self) -> Vec<u8> {
         vec![]
     }
@@ -0,0 +1,398 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Cycles of the 4 MiHz base clock in one second
+pub(crate) const CYCLES_PER_SECOND: u32 = 4_194_304;
+
+/// Size of the RTC footer appended to .sav files by VBA-M and BGB
+pub const RTC_FOOTER_SIZE: usize = 48;
+/// Older footer with a 32-bit timestamp
+pub const RTC_FOOTER_SIZE_32: usize = 44;
+
+const DAY_HIGH: u8 = 0b0000_0001;
+const HALT: u8 = 0b0100_0000;
+const DAY_CARRY: u8 = 0b1000_0000;
+
+/// Wall-clock time, used to catch up on the time that passed while the
+/// emulator wasn't running.
+pub trait TimeSource: Send {
+    /// Seconds since the Unix epoch
+    fn now(&self) -> u64;
+}
+
+pub struct SystemClock;
+
+impl TimeSource for SystemClock {
+    fn now(&self) -> u64 {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |time| time.as_secs())
+    }
+}
+
+/// Manually advanced clock, clones share the same time.
+#[derive(Clone, Default)]
+pub struct FakeClock(Arc<AtomicU64>);
+
+impl FakeClock {
+    pub fn new(now: u64) -> FakeClock {
+        FakeClock(Arc::new(AtomicU64::new(now)))
+    }
+
+    pub fn advance(&self, seconds: u64) {
+        self.0.fetch_add(seconds, Ordering::Relaxed);
+    }
+}
+
+impl TimeSource for FakeClock {
+    fn now(&self) -> u64 {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+/// MBC3 real-time clock.
+///
+/// The registers are read through the latched copy, writes go to the running
+/// clock. Values out of range (e.g. 60 seconds) are kept and count up to the
+/// register width before wrapping, without carrying into the next register.
+pub struct RTC {
+    seconds: u8,
+    minutes: u8,
+    hours: u8,
+    /// 9-bit day counter
+    days: u16,
+    halted: bool,
+    day_carry: bool,
+    latched: [u8; 5],
+    /// The last write to the latch register was 0x00
+    latch_armed: bool,
+    /// Base clock cycles into the current second
+    cycles: u32,
+    time_source: Box<dyn TimeSource>,
+}
+
+impl Default for RTC {
+    fn default() -> RTC {
+        RTC::new()
+    }
+}
+
+impl RTC {
+    pub fn new() -> RTC {
+        RTC::with_time_source(Box::new(SystemClock))
+    }
+
+    pub fn with_time_source(time_source: Box<dyn TimeSource>) -> RTC {
+        RTC {
+            seconds: 0,
+            minutes: 0,
+            hours: 0,
+            days: 0,
+            halted: false,
+            day_carry: false,
+            latched: [0; 5],
+            latch_armed: false,
+            cycles: 0,
+            time_source,
+        }
+    }
+
+    pub fn set_time_source(&mut self, time_source: Box<dyn TimeSource>) {
+        self.time_source = time_source;
+    }
+
+    /// Current wall-clock time, to be stored alongside the registers
+    pub fn timestamp(&self) -> u64 {
+        self.time_source.now()
+    }
+
+    /// Advances the clock by the wall-clock time elapsed since `timestamp`
+    pub fn catch_up(&mut self, timestamp: u64) {
+        let elapsed = self.time_source.now().saturating_sub(timestamp);
+        self.advance(elapsed);
+    }
+
+    /// Serializes the clock as the VBA-M/BGB footer: the running and the latched
+    /// registers as little endian 32-bit values, followed by a 64-bit Unix timestamp.
+    pub fn save_footer(&self) -> [u8; RTC_FOOTER_SIZE] {
+        let mut footer = [0; RTC_FOOTER_SIZE];
+
+        let registers = self.registers().into_iter().chain(self.latched);
+        for (chunk, register) in footer.chunks_exact_mut(4).zip(registers) {
+            chunk.copy_from_slice(&(register as u32).to_le_bytes());
+        }
+        footer[40..].copy_from_slice(&self.timestamp().to_le_bytes());
+        footer
+    }
+
+    /// Restores the clock from a 48 or 44 byte footer and catches up on the
+    /// time elapsed since it was written. Footers of any other size are ignored.
+    pub fn load_footer(&mut self, footer: &[u8]) {
+        if !matches!(footer.len(), RTC_FOOTER_SIZE | RTC_FOOTER_SIZE_32) {
+            log::warn!("Save file has no RTC footer");
+            return;
+        }
+
+        // Only the low byte of each 32-bit register is meaningful
+        let registers: Vec<u8> = footer.chunks_exact(4).take(10).map(|r| r[0]).collect();
+
+        for (register, &value) in (0x08..=0x0C).zip(&registers[..5]) {
+            self.write(register, value);
+        }
+        self.latched.copy_from_slice(&registers[5..]);
+
+        let timestamp = match footer.len() {
+            RTC_FOOTER_SIZE => u64::from_le_bytes(footer[40..48].try_into().unwrap()),
+            _ => u32::from_le_bytes(footer[40..44].try_into().unwrap()) as u64,
+        };
+        self.catch_up(timestamp);
+    }
+
+    /// Runs the clock for `cycles` cycles of the 4 MiHz base clock
+    pub fn tick(&mut self, cycles: u32) {
+        if self.halted {
+            return;
+        }
+
+        self.cycles += cycles;
+        while self.cycles >= CYCLES_PER_SECOND {
+            self.cycles -= CYCLES_PER_SECOND;
+            self.tick_second();
+        }
+    }
+
+    fn tick_second(&mut self) {
+        // Only a regular rollover carries, invalid values just wrap around
+        if self.seconds != 59 {
+            self.seconds = (self.seconds + 1) & 0x3F;
+            return;
+        }
+        self.seconds = 0;
+
+        if self.minutes != 59 {
+            self.minutes = (self.minutes + 1) & 0x3F;
+            return;
+        }
+        self.minutes = 0;
+
+        if self.hours != 23 {
+            self.hours = (self.hours + 1) & 0x1F;
+            return;
+        }
+        self.hours = 0;
+
+        self.days = (self.days + 1) & 0x1FF;
+        if self.days == 0 {
+            self.day_carry = true;
+        }
+    }
+
+    /// Advances the running clock by whole seconds
+    pub fn advance(&mut self, mut seconds: u64) {
+        if self.halted {
+            return;
+        }
+
+        // Step out of invalid register values one second at a time
+        while seconds > 0 && (self.seconds > 59 || self.minutes > 59 || self.hours > 23) {
+            self.tick_second();
+            seconds -= 1;
+        }
+
+        let total = self.seconds as u64
+            + self.minutes as u64 * 60
+            + self.hours as u64 * 3600
+            + self.days as u64 * 86400
+            + seconds;
+        let days = total / 86400;
+
+        self.seconds = (total % 60) as u8;
+        self.minutes = (total / 60 % 60) as u8;
+        self.hours = (total / 3600 % 24) as u8;
+        self.days = (days % 512) as u16;
+        if days >= 512 {
+            self.day_carry = true;
+        }
+    }
+
+    /// Registers S, M, H, DL, DH of the running clock
+    pub fn registers(&self) -> [u8; 5] {
+        let mut dh = (self.days >> 8) as u8 & DAY_HIGH;
+        if self.halted {
+            dh |= HALT;
+        }
+        if self.day_carry {
+            dh |= DAY_CARRY;
+        }
+
+        [self.seconds, self.minutes, self.hours, self.days as u8, dh]
+    }
+
+    pub fn latched(&self) -> [u8; 5] {
+        self.latched
+    }
+
+    /// Writing 0x00 then 0x01 copies the running clock to the latched registers
+    pub fn write_latch(&mut self, value: u8) {
+        if self.latch_armed && value == 0x01 {
+            self.latched = self.registers();
+        }
+        self.latch_armed = value == 0x00;
+    }
+
+    /// Reads register 0x08-0x0C
+    pub fn read(&self, register: u8) -> u8 {
+        self.latched[(register - 0x08) as usize]
+    }
+
+    /// Writes register 0x08-0x0C
+    pub fn write(&mut self, register: u8, value: u8) {
+        match register {
+            0x08 => {
+                self.seconds = value & 0x3F;
+                self.cycles = 0;
+            }
+            0x09 => self.minutes = value & 0x3F,
+            0x0A => self.hours = value & 0x1F,
+            0x0B => self.days = (self.days & 0x100) | value as u16,
+            0x0C => {
+                self.days = (self.days & 0xFF) | ((value & DAY_HIGH) as u16) << 8;
+                self.halted = value & HALT != 0;
+                self.day_carry = value & DAY_CARRY != 0;
+            }
+            _ => unreachable!(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn latch(rtc: &mut RTC) -> [u8; 5] {
+        rtc.write_latch(0x00);
+        rtc.write_latch(0x01);
+        rtc.latched()
+    }
+
+    #[test]
+    fn test_tick() {
+        let mut rtc = RTC::with_time_source(Box::new(FakeClock::new(0)));
+
+        rtc.tick(CYCLES_PER_SECOND - 4);
+        assert_eq!(latch(&mut rtc), [0, 0, 0, 0, 0]);
+        rtc.tick(4);
+        assert_eq!(latch(&mut rtc), [1, 0, 0, 0, 0]);
+
+        rtc.write(0x08, 59);
+        rtc.write(0x09, 59);
+        rtc.write(0x0A, 23);
+        rtc.write(0x0B, 0xFF);
+        rtc.tick(CYCLES_PER_SECOND);
+        assert_eq!(latch(&mut rtc), [0, 0, 0, 0, DAY_HIGH]);
+
+        // Day 511 overflows into the carry flag, which stays set
+        rtc.write(0x08, 59);
+        rtc.write(0x09, 59);
+        rtc.write(0x0A, 23);
+        rtc.write(0x0B, 0xFF);
+        rtc.write(0x0C, DAY_HIGH);
+        rtc.tick(CYCLES_PER_SECOND);
+        assert_eq!(latch(&mut rtc), [0, 0, 0, 0, DAY_CARRY]);
+        rtc.tick(CYCLES_PER_SECOND);
+        assert_eq!(latch(&mut rtc), [1, 0, 0, 0, DAY_CARRY]);
+    }
+
+    #[test]
+    fn test_invalid_values() {
+        let mut rtc = RTC::new();
+
+        // 63 wraps to 0 without incrementing the minutes
+        rtc.write(0x08, 0xFF);
+        assert_eq!(rtc.registers()[0], 63);
+        rtc.tick(CYCLES_PER_SECOND);
+        assert_eq!(rtc.registers()[..2], [0, 0]);
+
+        rtc.write(0x0A, 30);
+        rtc.advance(3600 * 2);
+        assert_eq!(rtc.registers()[2], 0);
+        assert_eq!(rtc.registers()[3], 0);
+    }
+
+    #[test]
+    fn test_latch_and_halt() {
+        let mut rtc = RTC::new();
+        rtc.tick(CYCLES_PER_SECOND);
+
+        // Only a 0x00 -> 0x01 sequence latches
+        rtc.write_latch(0x01);
+        assert_eq!(rtc.read(0x08), 0);
+        rtc.write_latch(0x00);
+        rtc.write_latch(0x01);
+        assert_eq!(rtc.read(0x08), 1);
+
+        rtc.tick(CYCLES_PER_SECOND);
+        assert_eq!(rtc.read(0x08), 1);
+
+        rtc.write(0x0C, HALT);
+        rtc.tick(CYCLES_PER_SECOND * 2);
+        rtc.advance(100);
+        assert_eq!(latch(&mut rtc), [2, 0, 0, 0, HALT]);
+
+        rtc.write(0x0C, 0);
+        rtc.tick(CYCLES_PER_SECOND);
+        assert_eq!(latch(&mut rtc), [3, 0, 0, 0, 0]);
+    }
+
+    #[test]
+    fn test_footer() {
+        let clock = FakeClock::new(1_700_000_000);
+        let mut rtc = RTC::with_time_source(Box::new(clock.clone()));
+        rtc.write(0x08, 30);
+        rtc.write(0x0B, 0x20);
+        rtc.write(0x0C, DAY_HIGH);
+        latch(&mut rtc);
+        rtc.write(0x09, 10);
+
+        let footer = rtc.save_footer();
+        assert_eq!(footer[0..4], [30, 0, 0, 0]);
+        assert_eq!(footer[4..8], [10, 0, 0, 0]);
+        assert_eq!(footer[16..20], [DAY_HIGH, 0, 0, 0]);
+        assert_eq!(footer[24..28], [0, 0, 0, 0]);
+        assert_eq!(footer[40..48], 1_700_000_000u64.to_le_bytes());
+
+        clock.advance(65);
+        let mut loaded = RTC::with_time_source(Box::new(clock.clone()));
+        loaded.load_footer(&footer);
+        assert_eq!(loaded.registers(), [35, 11, 0, 0x20, DAY_HIGH]);
+        assert_eq!(loaded.latched(), rtc.latched());
+
+        // 32-bit timestamp variant
+        let mut loaded = RTC::with_time_source(Box::new(clock));
+        loaded.load_footer(&footer[..RTC_FOOTER_SIZE_32]);
+        assert_eq!(loaded.registers(), [35, 11, 0, 0x20, DAY_HIGH]);
+
+        // Truncated footers are ignored
+        let mut loaded = RTC::new();
+        loaded.load_footer(&footer[..20]);
+        assert_eq!(loaded.registers(), [0; 5]);
+    }
+
+    #[test]
+    fn test_catch_up() {
+        let clock = FakeClock::new(1_700_000_000);
+        let mut rtc = RTC::with_time_source(Box::new(clock.clone()));
+        let saved = rtc.timestamp();
+
+        clock.advance(86400 * 3 + 3600 * 5 + 60 * 7 + 11);
+        rtc.catch_up(saved);
+        assert_eq!(latch(&mut rtc), [11, 7, 5, 3, 0]);
+
+        let saved = rtc.timestamp();
+        clock.advance(86400 * 600);
+        rtc.catch_up(saved);
+        // Day 603 wraps around to 91
+        assert_eq!(latch(&mut rtc), [11, 7, 5, 91, DAY_CARRY]);
+    }
+}